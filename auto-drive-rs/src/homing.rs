@@ -0,0 +1,164 @@
+//! Supports "coming home" to the EV3 infrared beacon, via the [InfraredSensor]'s seek mode.
+
+use std::time::{Duration, Instant};
+
+use ev3_common::behaviors::{Beacon, Behavior, MotorCommand, SensorReadings};
+use ev3_common::env::env_var_or;
+use ev3dev_lang_rust::sensors::{InfraredSensor, Sensor};
+use ev3dev_lang_rust::Ev3Result;
+
+/// The fastest that [Homing] will drive towards the beacon, in degrees per second.
+const HOMING_SPEED_MAX: f32 = 400.0;
+
+/// The slowest that [Homing] will drive towards the beacon, in degrees per second.
+const HOMING_SPEED_MIN: f32 = 100.0;
+
+/// How much to slow down for each unit of beacon distance closer to the stopping distance.
+const HOMING_SPEED_PER_DISTANCE: f32 = 15.0;
+
+/// The proportional gain for steering: how much faster one wheel runs than the other, in degrees
+///   per second, for each unit of beacon heading.
+const HOMING_STEERING_GAIN: f32 = 16.0;
+
+/// How fast to spin in place while searching for a beacon that isn't visible.
+const SEARCH_SPEED: i32 = 200;
+
+/// How long to search for a beacon that isn't visible before giving up.
+const SEARCH_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The environment variable that can be used to override [BEACON_CHANNEL_DEFAULT].
+const BEACON_CHANNEL_VAR: &str = "AUTO_DRIVE_BEACON_CHANNEL";
+
+/// The default channel (`1` to `4`) that the beacon is expected to be set to.
+const BEACON_CHANNEL_DEFAULT: u8 = 1;
+
+/// The environment variable that can be used to override [HOME_DISTANCE_DEFAULT].
+const HOME_DISTANCE_VAR: &str = "AUTO_DRIVE_HOME_DISTANCE";
+
+/// The default beacon distance (from `0` to `100`) at which [Homing] considers itself home.
+/// Like the sensor's proximity readings, these aren't calibrated, but `10` is roughly 10-15 cm.
+const HOME_DISTANCE_DEFAULT: i32 = 10;
+
+/// Locates the EV3 infrared beacon via an [InfraredSensor] in seek mode.
+pub struct BeaconSeeker {
+    sensor: InfraredSensor,
+    channel: u8,
+}
+
+impl BeaconSeeker {
+    /// Finds the infrared sensor, if one is plugged in.
+    ///
+    /// The beacon's channel can be configured via the `AUTO_DRIVE_BEACON_CHANNEL` environment
+    ///   variable.
+    pub fn find() -> Ev3Result<Option<BeaconSeeker>> {
+        match InfraredSensor::find() {
+            Ok(sensor) => {
+                let channel = env_var_or(BEACON_CHANNEL_VAR, BEACON_CHANNEL_DEFAULT, |channel| {
+                    (1..=4).contains(channel)
+                })?;
+                println!("Come home: enabled, seeking beacon on channel {}.", channel);
+                Ok(Some(BeaconSeeker { sensor, channel }))
+            }
+            Err(_) => {
+                println!("Come home: disabled, as no infrared sensor is plugged in.");
+                Ok(None)
+            }
+        }
+    }
+
+    /// Switches the sensor into seek mode; must be called before [BeaconSeeker::get_beacon].
+    pub fn start(&self) -> Ev3Result<()> {
+        self.sensor.set_mode_ir_seek()
+    }
+
+    /// Switches the sensor back into proximity mode, in case it's also being used as the
+    ///   robot's proximity sensor.
+    pub fn finish(&self) -> Ev3Result<()> {
+        self.sensor.set_mode_ir_prox()
+    }
+
+    /// Returns the beacon's current position, or `None` if it can't be seen.
+    pub fn get_beacon(&self) -> Ev3Result<Option<Beacon>> {
+        // Each channel gets a pair of values: heading and then distance.
+        let (heading, distance) = match self.channel {
+            1 => (self.sensor.get_value0()?, self.sensor.get_value1()?),
+            2 => (self.sensor.get_value2()?, self.sensor.get_value3()?),
+            3 => (self.sensor.get_value4()?, self.sensor.get_value5()?),
+            _ => (self.sensor.get_value6()?, self.sensor.get_value7()?),
+        };
+
+        // The sensor reports a distance of -128 when the beacon isn't in sight.
+        if distance == -128 {
+            Ok(None)
+        } else {
+            Ok(Some(Beacon { heading, distance }))
+        }
+    }
+}
+
+/// Steers towards the beacon, and ends the session once close enough to it.
+pub struct Homing {
+    stop_distance: i32,
+    lost_since: Option<Instant>,
+}
+
+impl Homing {
+    /// Constructs a new [Homing] behavior.
+    ///
+    /// The distance at which to stop can be configured via the `AUTO_DRIVE_HOME_DISTANCE`
+    ///   environment variable.
+    pub fn new() -> Ev3Result<Homing> {
+        Ok(Homing {
+            stop_distance: env_var_or(HOME_DISTANCE_VAR, HOME_DISTANCE_DEFAULT, |distance| {
+                (0..=100).contains(distance)
+            })?,
+            lost_since: None,
+        })
+    }
+}
+
+impl Behavior for Homing {
+    fn name(&self) -> &'static str {
+        "homing"
+    }
+
+    fn priority(&self) -> u8 {
+        5
+    }
+
+    fn propose(&mut self, readings: &SensorReadings) -> Ev3Result<Option<MotorCommand>> {
+        let beacon = match readings.beacon {
+            Some(beacon) => beacon,
+            None => {
+                // Spin in place to look for the beacon, but don't do so forever.
+                let lost_since = *self.lost_since.get_or_insert_with(Instant::now);
+                if lost_since.elapsed() > SEARCH_TIMEOUT {
+                    println!("Come home: unable to find the beacon; giving up.");
+                    return Ok(Some(MotorCommand::Exit));
+                }
+
+                return Ok(Some(MotorCommand::Tank {
+                    left_speed: SEARCH_SPEED,
+                    right_speed: -SEARCH_SPEED,
+                }));
+            }
+        };
+        self.lost_since = None;
+
+        if beacon.distance <= self.stop_distance {
+            println!("Come home: arrived at the beacon.");
+            return Ok(Some(MotorCommand::Exit));
+        }
+
+        // Slow down on approach, and steer proportionally to the beacon's heading.
+        let speed = (HOMING_SPEED_PER_DISTANCE * (beacon.distance - self.stop_distance) as f32)
+            .max(HOMING_SPEED_MIN)
+            .min(HOMING_SPEED_MAX);
+        let steering = HOMING_STEERING_GAIN * beacon.heading as f32;
+
+        Ok(Some(MotorCommand::Tank {
+            left_speed: (speed + steering) as i32,
+            right_speed: (speed - steering) as i32,
+        }))
+    }
+}
@@ -1,27 +1,39 @@
 extern crate ev3dev_lang_rust;
 
+mod homing;
+
 use std::time::Duration;
 
-use rand::prelude::*;
+use ev3_common::behaviors::{
+    Arbiter, Avoid, Bump, Cliff, Cruise, Escape, LowBattery, SensorReadings, UserStop,
+};
+use ev3_common::cliff::CliffDetector;
+use ev3_common::motors::LargeMotorSet;
+use ev3_common::proximity::{find_proximity_sensor, ProximitySensor};
+use ev3dev_lang_rust::sensors::TouchSensor;
+use ev3dev_lang_rust::{Ev3Button, Ev3Result, PowerSupply};
+
+use homing::{BeaconSeeker, Homing};
 
-use ev3dev_lang_rust::motors::{LargeMotor, MotorPort};
-use ev3dev_lang_rust::sensors::{TouchSensor, UltrasonicSensor};
-use ev3dev_lang_rust::{sound, Ev3Button, Ev3Result, Led};
+/// The environment variable that can be used to adjust how the infrared sensor's proximity
+///   readings are mapped to centimeters, if it's the proximity sensor.
+const IR_CM_PER_PERCENT_VAR: &str = "AUTO_DRIVE_IR_CM_PER_PERCENT";
 
-const PROXIMITY_CM_THRESHOLD_SLOW: f32 = 40.0;
-const PROXIMITY_CM_THRESHOLD_STOP: f32 = 15.0;
+/// How often the [ev3_common::behaviors::Arbiter] re-reads the sensors and re-picks a winning behavior.
+const TICK_INTERVAL: Duration = Duration::from_millis(50);
 
 fn main() -> Ev3Result<()> {
     // Get motors and sensors.
-    let motors = LargeMotorSet {
-        motors: vec![
-            LargeMotor::get(MotorPort::OutB)?,
-            LargeMotor::get(MotorPort::OutC)?,
-        ],
+    let devices = Ev3Devices {
+        motors: LargeMotorSet::new()?,
+        proximity_sensor: find_proximity_sensor(IR_CM_PER_PERCENT_VAR)?,
+        touch_sensor: TouchSensor::find()?,
+        cliff_detector: CliffDetector::find(),
+        beacon_seeker: BeaconSeeker::find()?,
+        buttons: Ev3Button::new()?,
+        power_supply: PowerSupply::new()?,
     };
-    let ultrasonic_sensor = UltrasonicSensor::find()?;
-    let touch_sensor = TouchSensor::find()?;
-    let buttons = Ev3Button::new()?;
+    let buttons = &devices.buttons;
 
     println!(
         "Waiting for button push. Press backspace to exit or anything else to start auto-driving."
@@ -34,11 +46,11 @@ fn main() -> Ev3Result<()> {
             println!("Backspace pressed. Bye!");
             break;
         } else if !buttons_pressed.is_empty() {
-            match auto_drive(&motors, &ultrasonic_sensor, &touch_sensor, &buttons) {
+            match auto_drive(&devices).and_then(|_| come_home(&devices)) {
                 Err(err) => {
                     // If the driving errored out, make sure we try to stop the motors.
                     eprintln!("Driving error: {:?}", err);
-                    stop(&motors)?;
+                    stop(&devices.motors)?;
                 }
                 _ => {
                     /*
@@ -58,44 +70,66 @@ fn main() -> Ev3Result<()> {
 }
 
 /// Runs an "auto pilot" Roomba-esque sequence until one of the brick's buttons is pushed.
-fn auto_drive(
-    motors: &LargeMotorSet,
-    ultrasonic_sensor: &UltrasonicSensor,
-    touch_sensor: &TouchSensor,
-    buttons: &Ev3Button,
-) -> Ev3Result<()> {
+///
+/// The actual driving logic is made up of the [ev3_common::behaviors::Behavior]s registered here,
+///   which are arbitrated between by a [ev3_common::behaviors::Arbiter].
+fn auto_drive(devices: &Ev3Devices) -> Ev3Result<()> {
     println!("Auto drive: starting. Press any brick button to stop.");
-    start_straight(motors)?;
+    let mut arbiter = Arbiter::new(vec![
+        Box::new(Cruise),
+        Box::new(Avoid),
+        Box::new(Escape::new()),
+        Box::new(Bump::new()),
+        Box::new(Cliff::new()),
+        Box::new(LowBattery::new()),
+        Box::new(UserStop),
+    ])?;
+
+    arbiter.run(
+        &devices.motors,
+        || devices.read_sensors(false),
+        TICK_INTERVAL,
+    )?;
+
+    stop(&devices.motors)?;
 
-    loop {
-        let mut distance_cm = ultrasonic_sensor.get_distance_centimeters()?;
-
-        while touch_sensor.get_pressed_state()? || distance_cm < PROXIMITY_CM_THRESHOLD_STOP {
-            change_direction(motors)?;
-            distance_cm = ultrasonic_sensor.get_distance_centimeters()?;
-        }
+    Ok(())
+}
 
-        /*
-         * Our target speed is calculated as whatever percentage we are between the two
-         * thresholds.
-         */
-        let duty_cycle_percentage = (distance_cm.min(PROXIMITY_CM_THRESHOLD_SLOW)
-            - PROXIMITY_CM_THRESHOLD_STOP)
-            / (PROXIMITY_CM_THRESHOLD_SLOW - PROXIMITY_CM_THRESHOLD_STOP);
-        let duty_cycle = (100.0 * duty_cycle_percentage) as i32;
-        motors.set_duty_cycle_sp(duty_cycle)?;
+/// Drives back to the EV3 infrared beacon, until close enough to it or until one of the brick's
+///   buttons is pushed.
+///
+/// Does nothing if no infrared sensor is plugged in.
+fn come_home(devices: &Ev3Devices) -> Ev3Result<()> {
+    let beacon_seeker = match &devices.beacon_seeker {
+        Some(beacon_seeker) => beacon_seeker,
+        None => return Ok(()),
+    };
 
-        // Wait for a bit before looping again.
-        std::thread::sleep(Duration::from_millis(1000));
+    // Don't let the button push that ended auto-driving also end homing.
+    devices.wait_for_button_release();
+
+    println!("Come home: starting. Press any brick button to stop.");
+    beacon_seeker.start()?;
+    let mut arbiter = Arbiter::new(vec![
+        Box::new(Homing::new()?),
+        Box::new(Escape::new()),
+        Box::new(Bump::new()),
+        Box::new(Cliff::new()),
+        Box::new(LowBattery::new()),
+        Box::new(UserStop),
+    ])?;
+    let result = arbiter.run(
+        &devices.motors,
+        || devices.read_sensors(true),
+        TICK_INTERVAL,
+    );
 
-        buttons.process();
-        if !buttons.get_pressed_buttons().is_empty() {
-            println!("Auto drive: request to exit received.");
-            break;
-        }
-    }
+    // Always switch the sensor back, as it may also be the proximity sensor.
+    beacon_seeker.finish()?;
+    result?;
 
-    stop(motors)?;
+    stop(&devices.motors)?;
 
     Ok(())
 }
@@ -111,149 +145,61 @@ fn stop(motors: &LargeMotorSet) -> Ev3Result<()> {
     Ok(())
 }
 
-fn start_straight(motors: &LargeMotorSet) -> Ev3Result<()> {
-    motors.set_duty_cycle_sp(100)?;
-    motors.run_direct()?;
-
-    Ok(())
+/// The EV3 devices used while driving.
+struct Ev3Devices {
+    motors: LargeMotorSet,
+    proximity_sensor: Box<dyn ProximitySensor + Send>,
+    touch_sensor: TouchSensor,
+    cliff_detector: Option<CliffDetector>,
+    beacon_seeker: Option<BeaconSeeker>,
+    buttons: Ev3Button,
+    power_supply: PowerSupply,
 }
 
-fn change_direction(motors: &LargeMotorSet) -> Ev3Result<()> {
-    backup(motors)?;
-    turn_random(motors)?;
-    start_straight(motors)?;
-
-    Ok(())
-}
-
-fn backup(motors: &LargeMotorSet) -> Ev3Result<()> {
-    stop(motors)?;
-
-    let leds = Led::new()?;
-
-    // Play fun backing-up sound and turn on backing-up lights.
-    sound::tone_sequence(&[(1000.0, 500, 500), (1000.0, 500, 500), (1000.0, 500, 500)])?.wait()?;
-    leds.set_left_color(Led::COLOR_RED)?;
-    leds.set_right_color(Led::COLOR_RED)?;
-
-    motors.set_speed_sp(-500)?;
-    motors.run_timed(Some(Duration::from_millis(1500)))?;
-    motors.wait_until(LargeMotor::STATE_RUNNING, None);
-    motors.wait_until_not_moving(None);
-
-    // Turn off backing-up lights.
-    leds.set_left_color(Led::COLOR_GREEN)?;
-    leds.set_right_color(Led::COLOR_GREEN)?;
-
-    Ok(())
-}
-
-fn turn_random(motors: &LargeMotorSet) -> Ev3Result<()> {
-    // Flip a coin for left or right turn.
-    let direction = if rand::random() {
-        vec![-1, 1]
-    } else {
-        vec![1, -1]
-    };
-
-    // Randomly decide how many millis to backup for.
-    let backup_time = Duration::from_millis(rand::thread_rng().gen_range(250..=750));
-
-    // Run the random turn.
-    for (motor, direction) in motors.motors.iter().zip(direction) {
-        // Set this wheel to run at 750, either forwards or backwards.
-        motor.set_speed_sp(750 * direction)?;
-    }
-    motors.run_timed(Some(backup_time))?;
-    motors.wait_until(LargeMotor::STATE_RUNNING, None);
-    motors.wait_until_not_moving(None);
-
-    Ok(())
-}
-
-/// Represents a set of [LargeMotors] that ought to be managed in concert.
-struct LargeMotorSet {
-    motors: Vec<LargeMotor>,
-}
-
-impl LargeMotorSet {
-    /// Proxies [LargeMotor::set_stop_action].
-    pub fn set_stop_action(&self, stop_action: &str) -> Ev3Result<()> {
-        for motor in &self.motors {
-            motor.set_stop_action(stop_action)?;
-        }
-
-        Ok(())
-    }
-
-    /// Proxies [LargeMotor::stop].
-    pub fn stop(&self) -> Ev3Result<()> {
-        for motor in &self.motors {
-            motor.stop()?;
-        }
-
-        Ok(())
-    }
-
-    /// Proxies [LargeMotor::set_duty_cycle_sp].
-    pub fn set_duty_cycle_sp(&self, duty_cycle: i32) -> Ev3Result<()> {
-        for motor in &self.motors {
-            motor.set_duty_cycle_sp(duty_cycle)?;
-        }
-
-        Ok(())
-    }
-
-    /// Proxies [LargeMotor::run_direct].
-    pub fn run_direct(&self) -> Ev3Result<()> {
-        for motor in &self.motors {
-            motor.run_direct()?;
-        }
-
-        Ok(())
-    }
-
-    /// Proxies [LargeMotor::set_speed_sp].
-    pub fn set_speed_sp(&self, speed_sp: i32) -> Ev3Result<()> {
-        for motor in &self.motors {
-            motor.set_speed_sp(speed_sp)?;
-        }
-
-        Ok(())
-    }
-
-    /// Proxies [LargeMotor::run_timed].
-    pub fn run_timed(&self, time_sp: Option<Duration>) -> Ev3Result<()> {
-        for motor in &self.motors {
-            motor.run_timed(time_sp)?;
-        }
-
-        Ok(())
-    }
-
-    /// Proxies [LargeMotor::wait_until].
-    pub fn wait_until(&self, state: &str, timeout: Option<Duration>) -> bool {
-        let mut result = true;
-        for motor in &self.motors {
-            result = match motor.wait_until(state, timeout) {
-                true => result,
-                false => false,
-            };
-        }
-
-        result
+impl Ev3Devices {
+    /// Reads all of the sensors, for the [ev3_common::behaviors::Arbiter].
+    ///
+    /// Parameters:
+    /// * `seeking`: whether or not the [BeaconSeeker] is active, in which case the beacon will be
+    ///   read, too
+    fn read_sensors(&self, seeking: bool) -> Ev3Result<SensorReadings> {
+        self.buttons.process();
+
+        let beacon = match (&self.beacon_seeker, seeking) {
+            (Some(beacon_seeker), true) => beacon_seeker.get_beacon()?,
+            _ => None,
+        };
+
+        // The infrared sensor can't measure proximity while it's seeking the beacon, so if it's
+        //   also the proximity sensor, obstacle detection has to be skipped while homing.
+        let distance_cm = if seeking && self.proximity_sensor.is_infrared() {
+            f32::INFINITY
+        } else {
+            self.proximity_sensor.get_distance_cm()?
+        };
+
+        Ok(SensorReadings {
+            distance_cm,
+            touch_pressed: self.touch_sensor.get_pressed_state()?,
+            button_pressed: !self.buttons.get_pressed_buttons().is_empty(),
+            battery_volts: self.power_supply.get_voltage_now()? as f32 / 1_000_000.0,
+            wheel_degrees: self.motors.get_wheel_degrees()?,
+            cliff: match &self.cliff_detector {
+                Some(cliff_detector) => cliff_detector.is_cliff()?,
+                None => false,
+            },
+            beacon,
+        })
     }
 
-    /// Proxies [LargeMotor::wait_until_not_moving].
-    pub fn wait_until_not_moving(&self, timeout: Option<Duration>) -> bool {
-        let mut result = true;
-        for motor in &self.motors {
-            result = match motor.wait_until_not_moving(timeout) {
-                true => result,
-                false => false,
-            };
+    /// Blocks until none of the brick's buttons are pressed.
+    fn wait_for_button_release(&self) {
+        loop {
+            self.buttons.process();
+            if self.buttons.get_pressed_buttons().is_empty() {
+                return;
+            }
+            std::thread::sleep(TICK_INTERVAL);
         }
-
-        result
     }
 }
@@ -0,0 +1,831 @@
+//! A small behavior-based ("subsumption") framework for composing autonomous driving routines,
+//!   which is used by both `auto-drive-rs` and the autopilot in `remote-control-fancy-rs`.
+//!
+//! Each [Behavior] looks at the latest [SensorReadings] on every tick and may propose a
+//!   [MotorCommand] for that tick.
+//! The [Arbiter] asks every behavior, in priority order, and the highest-priority proposal wins
+//!   and gets applied to the motors.
+//! Lower-priority behaviors are thus "subsumed" whenever a higher-priority one has something to
+//!   say, and get control back as soon as it goes quiet again.
+//!
+//! The [Arbiter] only borrows the motors for each tick, so that they can be shared with other
+//!   controls, e.g. manual driving.
+
+use std::cmp::Reverse;
+use std::process::Child;
+use std::time::Duration;
+
+use rand::prelude::*;
+
+use ev3dev_lang_rust::{sound, Ev3Result, Led};
+
+use crate::motors::LargeMotorSet;
+use crate::moves::{cm_to_wheel_degrees, turn_to_wheel_degrees, WheelMove};
+
+/// Obstacles closer than this (in centimeters) make the [Avoid] behavior slow down.
+const AVOID_CM_THRESHOLD_SLOW: f32 = 40.0;
+
+/// Obstacles closer than this (in centimeters) make the [Escape] behavior back away.
+const AVOID_CM_THRESHOLD_STOP: f32 = 15.0;
+
+/// How far the [Bounce] maneuver backs up for.
+const BACKUP_CM: f32 = 30.0;
+
+/// The battery voltage below which the [LowBattery] behavior will stop the robot.
+const LOW_BATTERY_VOLTS: f32 = 6.5;
+
+/// How many consecutive low readings are required before [LowBattery] kicks in, so that brief
+///   voltage dips under load don't stop the robot.
+const LOW_BATTERY_TICKS: u32 = 20;
+
+/// The sensor readings that every [Behavior] gets to look at on each tick.
+///
+/// Sensors that aren't plugged in (or aren't used) should read as harmlessly as possible: an
+///   infinite distance, an unpressed touch sensor or button, a full battery, no cliff, and no
+///   beacon.
+#[derive(Clone, Debug)]
+pub struct SensorReadings {
+    pub distance_cm: f32,
+    pub touch_pressed: bool,
+    /// Whether or not any of the brick's buttons are pressed.
+    pub button_pressed: bool,
+    pub battery_volts: f32,
+    /// The current (left, right) wheel positions, in degrees.
+    pub wheel_degrees: (f32, f32),
+    /// Whether or not the robot is at the edge of a cliff, which will always be `false` if no
+    ///   color sensor is plugged in.
+    pub cliff: bool,
+    /// Where the infrared beacon is, which will only be read while coming home.
+    pub beacon: Option<Beacon>,
+}
+
+/// The beacon's position relative to the robot, as seen by an infrared sensor in seek mode.
+#[derive(Clone, Copy, Debug)]
+pub struct Beacon {
+    /// The beacon's heading, from `-25` (far left) to `25` (far right).
+    pub heading: i32,
+    /// The beacon's (uncalibrated) distance, from `0` to `100`.
+    pub distance: i32,
+}
+
+/// The motor commands that a [Behavior] can propose.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MotorCommand {
+    /// Drive both motors straight ahead at the specified duty cycle (via `run_direct`).
+    Straight { duty_cycle: i32 },
+    /// Run each motor at its own speed (via `run_forever`), e.g. to steer.
+    Tank { left_speed: i32, right_speed: i32 },
+    /// Rotate each wheel by the specified number of degrees (via `run_to_rel_pos`) at the
+    ///   specified speed; see [WheelMove] for tracking when it's done.
+    Move {
+        left_degrees: i32,
+        right_degrees: i32,
+        speed: i32,
+    },
+    /// Brake both motors and hold still.
+    Brake,
+    /// Brake both motors and end the driving session.
+    Exit,
+}
+
+impl MotorCommand {
+    /// Returns `true` if this [MotorCommand] would move the robot forwards.
+    pub fn is_forward(&self) -> bool {
+        match *self {
+            MotorCommand::Straight { duty_cycle } => duty_cycle > 0,
+            MotorCommand::Tank {
+                left_speed,
+                right_speed,
+            } => left_speed + right_speed > 0,
+            MotorCommand::Move {
+                left_degrees,
+                right_degrees,
+                ..
+            } => left_degrees + right_degrees > 0,
+            MotorCommand::Brake | MotorCommand::Exit => false,
+        }
+    }
+}
+
+/// The colors to show on the brick's LEDs while a [Behavior] is in control.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Lights {
+    Normal,
+    Warning,
+    Alert,
+}
+
+/// A single autonomous behavior, which will be consulted by the [Arbiter] on every tick.
+pub trait Behavior {
+    /// A short name for this behavior, used in log output and reported to clients.
+    fn name(&self) -> &'static str;
+
+    /// The priority of this behavior: when several behaviors propose a [MotorCommand] in the
+    ///   same tick, the one with the highest priority wins.
+    fn priority(&self) -> u8;
+
+    /// Returns the [MotorCommand] this behavior would like to run for this tick, if any.
+    ///
+    /// Note that this is called on every tick for every behavior, even for behaviors that
+    ///   end up being subsumed, so that they can keep their internal state up to date.
+    ///
+    /// Parameters:
+    /// * `readings`: the [SensorReadings] for this tick
+    fn propose(&mut self, readings: &SensorReadings) -> Ev3Result<Option<MotorCommand>>;
+
+    /// The [Lights] to show while this behavior is in control.
+    fn lights(&self) -> Lights {
+        Lights::Normal
+    }
+}
+
+/// Picks the winning [Behavior] on each tick and applies its [MotorCommand] to the motors.
+pub struct Arbiter {
+    leds: Led,
+    behaviors: Vec<Box<dyn Behavior>>,
+    current: Option<(Option<usize>, MotorCommand, Lights)>,
+}
+
+impl Arbiter {
+    /// Constructs a new [Arbiter].
+    ///
+    /// Parameters:
+    /// * `behaviors`: the [Behavior]s to arbitrate between, in any order
+    pub fn new(mut behaviors: Vec<Box<dyn Behavior>>) -> Ev3Result<Arbiter> {
+        sort_by_priority(&mut behaviors);
+
+        Ok(Arbiter {
+            leds: Led::new()?,
+            behaviors,
+            current: None,
+        })
+    }
+
+    /// Returns the name of the [Behavior] that won the last tick, if any.
+    pub fn in_control(&self) -> Option<&'static str> {
+        match self.current {
+            Some((Some(index), _, _)) => Some(self.behaviors[index].name()),
+            _ => None,
+        }
+    }
+
+    /// Runs a single tick: asks every [Behavior] for a proposal and applies the winning one.
+    ///
+    /// Returns `false` once the winning behavior has asked to end the session.
+    ///
+    /// Parameters:
+    /// * `readings`: the [SensorReadings] for this tick
+    /// * `motors`: the [LargeMotorSet] to drive
+    pub fn tick(&mut self, readings: &SensorReadings, motors: &LargeMotorSet) -> Ev3Result<bool> {
+        // If nobody has anything to say, just hold still.
+        let (index, command, lights) = match arbitrate(&mut self.behaviors, readings)? {
+            Some(winner) => winner,
+            None => {
+                self.apply(motors, None, MotorCommand::Brake, Lights::Normal)?;
+                return Ok(true);
+            }
+        };
+        self.apply(motors, Some(index), command, lights)?;
+
+        Ok(command != MotorCommand::Exit)
+    }
+
+    /// Runs [Arbiter::tick] over and over, until a [Behavior] asks to end the session.
+    ///
+    /// Parameters:
+    /// * `motors`: the [LargeMotorSet] to drive
+    /// * `read_sensors`: called once per tick to get that tick's [SensorReadings]
+    /// * `tick_interval`: how long to wait between ticks
+    pub fn run<F>(
+        &mut self,
+        motors: &LargeMotorSet,
+        mut read_sensors: F,
+        tick_interval: Duration,
+    ) -> Ev3Result<()>
+    where
+        F: FnMut() -> Ev3Result<SensorReadings>,
+    {
+        while self.tick(&read_sensors()?, motors)? {
+            std::thread::sleep(tick_interval);
+        }
+
+        Ok(())
+    }
+
+    /// Applies the specified [MotorCommand] and [Lights], if they differ from what's already
+    ///   being run.
+    fn apply(
+        &mut self,
+        motors: &LargeMotorSet,
+        index: Option<usize>,
+        command: MotorCommand,
+        lights: Lights,
+    ) -> Ev3Result<()> {
+        let previous = self.current.take();
+        self.current = Some((index, command, lights));
+
+        if previous.map(|(index, _, _)| index) != Some(index) {
+            match index {
+                Some(index) => println!(
+                    "Behaviors: '{}' behavior in control.",
+                    self.behaviors[index].name()
+                ),
+                None => println!("Behaviors: no behavior in control."),
+            }
+        }
+
+        if previous.map(|(_, _, lights)| lights) != Some(lights) {
+            let color = match lights {
+                Lights::Normal => Led::COLOR_GREEN,
+                Lights::Warning => Led::COLOR_AMBER,
+                Lights::Alert => Led::COLOR_RED,
+            };
+            self.leds.set_left_color(color)?;
+            self.leds.set_right_color(color)?;
+        }
+
+        let previous_command = previous.map(|(_, command, _)| command);
+        if previous_command == Some(command) {
+            return Ok(());
+        }
+        match command {
+            MotorCommand::Straight { duty_cycle } => {
+                motors.set_duty_cycle_sp(duty_cycle)?;
+
+                // Only (re)start the motors if they weren't already running directly.
+                if !matches!(previous_command, Some(MotorCommand::Straight { .. })) {
+                    motors.run_direct()?;
+                }
+            }
+            MotorCommand::Tank {
+                left_speed,
+                right_speed,
+            } => {
+                for (motor, speed) in motors.motors.iter().zip(vec![left_speed, right_speed]) {
+                    motor.set_speed_sp(speed)?;
+                }
+                motors.run_forever()?;
+            }
+            MotorCommand::Move {
+                left_degrees,
+                right_degrees,
+                speed,
+            } => {
+                motors.run_to_rel_degrees(&[left_degrees, right_degrees], speed)?;
+            }
+            MotorCommand::Brake | MotorCommand::Exit => {
+                motors.set_stop_action("brake")?;
+                motors.stop()?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Turns the brick's LEDs back to their usual green, e.g. once the [Arbiter] has been
+    ///   stopped while showing warning lights.
+    pub fn reset_lights(&self) -> Ev3Result<()> {
+        self.leds.set_left_color(Led::COLOR_GREEN)?;
+        self.leds.set_right_color(Led::COLOR_GREEN)
+    }
+}
+
+/// Sorts the specified [Behavior]s from the highest priority to the lowest, as [arbitrate]
+///   expects.
+fn sort_by_priority(behaviors: &mut [Box<dyn Behavior>]) {
+    behaviors.sort_by_key(|behavior| Reverse(behavior.priority()));
+}
+
+/// Asks every [Behavior] for a proposal, and picks the winning one.
+///
+/// Returns the winning behavior's index, its [MotorCommand] (which is vetoed if it would drive
+///   forwards over a cliff), and its [Lights], or [None] if nobody proposed anything.
+///
+/// Parameters:
+/// * `behaviors`: the [Behavior]s to ask, from the highest priority to the lowest
+/// * `readings`: the [SensorReadings] for this tick
+fn arbitrate(
+    behaviors: &mut [Box<dyn Behavior>],
+    readings: &SensorReadings,
+) -> Ev3Result<Option<(usize, MotorCommand, Lights)>> {
+    let mut winner = None;
+    for (index, behavior) in behaviors.iter_mut().enumerate() {
+        let proposal = behavior.propose(readings)?;
+        if let (None, Some(command)) = (winner, proposal) {
+            winner = Some((index, command, behavior.lights()));
+        }
+    }
+
+    // Never drive forwards over a cliff, no matter which behavior is asking to.
+    if let Some((index, command, _)) = &mut winner {
+        if readings.cliff && command.is_forward() {
+            println!(
+                "Behaviors: '{}' behavior vetoed: cliff detected.",
+                behaviors[*index].name()
+            );
+            *command = MotorCommand::Brake;
+        }
+    }
+
+    Ok(winner)
+}
+
+/// The lowest-priority [Behavior]: just drive straight ahead at full speed.
+pub struct Cruise;
+
+impl Behavior for Cruise {
+    fn name(&self) -> &'static str {
+        "cruise"
+    }
+
+    fn priority(&self) -> u8 {
+        0
+    }
+
+    fn propose(&mut self, _readings: &SensorReadings) -> Ev3Result<Option<MotorCommand>> {
+        Ok(Some(MotorCommand::Straight { duty_cycle: 100 }))
+    }
+}
+
+/// Slows the robot down as it gets closer to an obstacle.
+pub struct Avoid;
+
+impl Behavior for Avoid {
+    fn name(&self) -> &'static str {
+        "avoid"
+    }
+
+    fn priority(&self) -> u8 {
+        10
+    }
+
+    fn propose(&mut self, readings: &SensorReadings) -> Ev3Result<Option<MotorCommand>> {
+        if readings.distance_cm >= AVOID_CM_THRESHOLD_SLOW {
+            return Ok(None);
+        }
+
+        /*
+         * Our target speed is calculated as whatever percentage we are between the two
+         * thresholds.
+         */
+        let duty_cycle_percentage = (readings.distance_cm.max(AVOID_CM_THRESHOLD_STOP)
+            - AVOID_CM_THRESHOLD_STOP)
+            / (AVOID_CM_THRESHOLD_SLOW - AVOID_CM_THRESHOLD_STOP);
+        let duty_cycle = (100.0 * duty_cycle_percentage) as i32;
+
+        Ok(Some(MotorCommand::Straight { duty_cycle }))
+    }
+}
+
+/// Backs away from and turns away from obstacles that are too close for [Avoid] to handle.
+pub struct Escape {
+    bounce: Bounce,
+}
+
+impl Escape {
+    /// Constructs a new [Escape] behavior.
+    pub fn new() -> Escape {
+        Escape {
+            bounce: Bounce::new(),
+        }
+    }
+}
+
+impl Default for Escape {
+    fn default() -> Escape {
+        Escape::new()
+    }
+}
+
+impl Behavior for Escape {
+    fn name(&self) -> &'static str {
+        "escape"
+    }
+
+    fn priority(&self) -> u8 {
+        20
+    }
+
+    fn propose(&mut self, readings: &SensorReadings) -> Ev3Result<Option<MotorCommand>> {
+        self.bounce
+            .propose(readings, readings.distance_cm < AVOID_CM_THRESHOLD_STOP)
+    }
+
+    fn lights(&self) -> Lights {
+        self.bounce.lights()
+    }
+}
+
+/// Backs away from and turns away from obstacles that the robot has bumped into.
+pub struct Bump {
+    bounce: Bounce,
+}
+
+impl Bump {
+    /// Constructs a new [Bump] behavior.
+    pub fn new() -> Bump {
+        Bump {
+            bounce: Bounce::new(),
+        }
+    }
+}
+
+impl Default for Bump {
+    fn default() -> Bump {
+        Bump::new()
+    }
+}
+
+impl Behavior for Bump {
+    fn name(&self) -> &'static str {
+        "bump"
+    }
+
+    fn priority(&self) -> u8 {
+        30
+    }
+
+    fn propose(&mut self, readings: &SensorReadings) -> Ev3Result<Option<MotorCommand>> {
+        self.bounce.propose(readings, readings.touch_pressed)
+    }
+
+    fn lights(&self) -> Lights {
+        self.bounce.lights()
+    }
+}
+
+/// Backs away from and turns away from cliffs, e.g. the edge of a table.
+pub struct Cliff {
+    bounce: Bounce,
+}
+
+impl Cliff {
+    /// Constructs a new [Cliff] behavior.
+    pub fn new() -> Cliff {
+        Cliff {
+            bounce: Bounce::new(),
+        }
+    }
+}
+
+impl Default for Cliff {
+    fn default() -> Cliff {
+        Cliff::new()
+    }
+}
+
+impl Behavior for Cliff {
+    fn name(&self) -> &'static str {
+        "cliff"
+    }
+
+    fn priority(&self) -> u8 {
+        35
+    }
+
+    fn propose(&mut self, readings: &SensorReadings) -> Ev3Result<Option<MotorCommand>> {
+        self.bounce.propose(readings, readings.cliff)
+    }
+
+    fn lights(&self) -> Lights {
+        self.bounce.lights()
+    }
+}
+
+/// Stops the robot for good once the battery runs low.
+pub struct LowBattery {
+    low_ticks: u32,
+}
+
+impl LowBattery {
+    /// Constructs a new [LowBattery] behavior.
+    pub fn new() -> LowBattery {
+        LowBattery { low_ticks: 0 }
+    }
+}
+
+impl Default for LowBattery {
+    fn default() -> LowBattery {
+        LowBattery::new()
+    }
+}
+
+impl Behavior for LowBattery {
+    fn name(&self) -> &'static str {
+        "low-battery"
+    }
+
+    fn priority(&self) -> u8 {
+        40
+    }
+
+    fn propose(&mut self, readings: &SensorReadings) -> Ev3Result<Option<MotorCommand>> {
+        if self.low_ticks >= LOW_BATTERY_TICKS {
+            // Once the battery has been found to be low, stay stopped.
+            return Ok(Some(MotorCommand::Brake));
+        }
+
+        if readings.battery_volts < LOW_BATTERY_VOLTS {
+            self.low_ticks += 1;
+            if self.low_ticks >= LOW_BATTERY_TICKS {
+                println!(
+                    "Behaviors: battery low ({:.2} V), stopping.",
+                    readings.battery_volts
+                );
+                return Ok(Some(MotorCommand::Brake));
+            }
+        } else {
+            self.low_ticks = 0;
+        }
+
+        Ok(None)
+    }
+
+    fn lights(&self) -> Lights {
+        Lights::Warning
+    }
+}
+
+/// The highest-priority [Behavior]: ends the session when any of the brick's buttons is pushed.
+pub struct UserStop;
+
+impl Behavior for UserStop {
+    fn name(&self) -> &'static str {
+        "user-stop"
+    }
+
+    fn priority(&self) -> u8 {
+        u8::MAX
+    }
+
+    fn propose(&mut self, readings: &SensorReadings) -> Ev3Result<Option<MotorCommand>> {
+        if readings.button_pressed {
+            println!("Behaviors: request to exit received.");
+            Ok(Some(MotorCommand::Exit))
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+/// The phases of a [Bounce] maneuver.
+enum BouncePhase {
+    /// Not currently bouncing.
+    Idle,
+    /// Stopped, while playing the fun backing-up sound.
+    Alerting { sound: Child },
+    /// Backing up, with the backing-up lights on.
+    BackingUp { wheel_move: WheelMove },
+    /// Turning in a random direction, by a random amount.
+    Turning { wheel_move: WheelMove },
+}
+
+/// The "bounce off of obstacles" maneuver shared by [Escape] and [Bump]:
+///   stop, back up for a bit, and then turn in a random direction.
+struct Bounce {
+    phase: BouncePhase,
+    /// Starts playing the sound that's played before backing up.
+    alert: fn() -> Ev3Result<Child>,
+}
+
+impl Bounce {
+    fn new() -> Bounce {
+        Bounce {
+            phase: BouncePhase::Idle,
+            alert: play_alert,
+        }
+    }
+
+    /// Advances the maneuver and returns the [MotorCommand] for its current phase, if any.
+    ///
+    /// Parameters:
+    /// * `readings`: the [SensorReadings] for this tick
+    /// * `triggered`: whether or not the maneuver's trigger condition holds for this tick
+    fn propose(
+        &mut self,
+        readings: &SensorReadings,
+        triggered: bool,
+    ) -> Ev3Result<Option<MotorCommand>> {
+        if let BouncePhase::Alerting { sound } = &mut self.phase {
+            if sound.try_wait()?.is_some() {
+                let backup_degrees = -cm_to_wheel_degrees(BACKUP_CM);
+                self.phase = BouncePhase::BackingUp {
+                    wheel_move: WheelMove::new(
+                        readings.wheel_degrees,
+                        backup_degrees,
+                        backup_degrees,
+                        500,
+                    ),
+                };
+            }
+        }
+        if let BouncePhase::BackingUp { wheel_move } = &self.phase {
+            if wheel_move.is_done(readings.wheel_degrees) {
+                // Flip a coin for left or right turn, and randomly decide how far to turn.
+                let turn_degrees = rand::thread_rng().gen_range(90.0..=270.0);
+                let left_degrees =
+                    turn_to_wheel_degrees(turn_degrees) * if rand::random() { -1.0 } else { 1.0 };
+                self.phase = BouncePhase::Turning {
+                    wheel_move: WheelMove::new(
+                        readings.wheel_degrees,
+                        left_degrees,
+                        -left_degrees,
+                        750,
+                    ),
+                };
+            }
+        }
+        if let BouncePhase::Turning { wheel_move } = &self.phase {
+            if wheel_move.is_done(readings.wheel_degrees) {
+                self.phase = BouncePhase::Idle;
+            }
+        }
+        if let (BouncePhase::Idle, true) = (&self.phase, triggered) {
+            let sound = (self.alert)()?;
+            self.phase = BouncePhase::Alerting { sound };
+        }
+
+        Ok(match self.phase {
+            BouncePhase::Idle => None,
+            BouncePhase::Alerting { .. } => Some(MotorCommand::Brake),
+            BouncePhase::BackingUp { wheel_move } | BouncePhase::Turning { wheel_move } => {
+                Some(wheel_move.command())
+            }
+        })
+    }
+
+    /// Turns on the backing-up lights while backing up.
+    fn lights(&self) -> Lights {
+        match self.phase {
+            BouncePhase::BackingUp { .. } => Lights::Alert,
+            _ => Lights::Normal,
+        }
+    }
+}
+
+/// Starts playing the fun backing-up sound.
+fn play_alert() -> Ev3Result<Child> {
+    sound::tone_sequence(&[(1000.0, 500, 500), (1000.0, 500, 500), (1000.0, 500, 500)])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns [SensorReadings] with nothing going on: no obstacles, buttons, or cliffs, and a
+    ///   full battery.
+    fn readings() -> SensorReadings {
+        SensorReadings {
+            distance_cm: f32::INFINITY,
+            touch_pressed: false,
+            button_pressed: false,
+            battery_volts: 8.0,
+            wheel_degrees: (0.0, 0.0),
+            cliff: false,
+            beacon: None,
+        }
+    }
+
+    /// Returns the name and [MotorCommand] of the winning [Behavior], if any.
+    fn winner(
+        behaviors: &mut [Box<dyn Behavior>],
+        readings: &SensorReadings,
+    ) -> Option<(&'static str, MotorCommand)> {
+        arbitrate(behaviors, readings)
+            .expect("behaviors shouldn't fail")
+            .map(|(index, command, _)| (behaviors[index].name(), command))
+    }
+
+    #[test]
+    fn picks_highest_priority_proposal() {
+        let mut behaviors: Vec<Box<dyn Behavior>> =
+            vec![Box::new(Cruise), Box::new(UserStop), Box::new(Avoid)];
+        sort_by_priority(&mut behaviors);
+
+        let mut readings = readings();
+        assert_eq!(
+            winner(&mut behaviors, &readings),
+            Some(("cruise", MotorCommand::Straight { duty_cycle: 100 }))
+        );
+
+        // Halfway between the slow and stop thresholds.
+        readings.distance_cm = (AVOID_CM_THRESHOLD_SLOW + AVOID_CM_THRESHOLD_STOP) / 2.0;
+        assert_eq!(
+            winner(&mut behaviors, &readings),
+            Some(("avoid", MotorCommand::Straight { duty_cycle: 50 }))
+        );
+
+        readings.button_pressed = true;
+        assert_eq!(
+            winner(&mut behaviors, &readings),
+            Some(("user-stop", MotorCommand::Exit))
+        );
+    }
+
+    #[test]
+    fn returns_none_without_proposals() {
+        let mut behaviors: Vec<Box<dyn Behavior>> = vec![Box::new(Avoid), Box::new(UserStop)];
+        sort_by_priority(&mut behaviors);
+
+        assert_eq!(winner(&mut behaviors, &readings()), None);
+    }
+
+    #[test]
+    fn vetoes_driving_over_cliff() {
+        let mut behaviors: Vec<Box<dyn Behavior>> = vec![Box::new(Cruise), Box::new(UserStop)];
+        sort_by_priority(&mut behaviors);
+
+        let mut readings = readings();
+        readings.cliff = true;
+        assert_eq!(
+            winner(&mut behaviors, &readings),
+            Some(("cruise", MotorCommand::Brake))
+        );
+
+        // Anything that isn't driving forwards is left alone.
+        readings.button_pressed = true;
+        assert_eq!(
+            winner(&mut behaviors, &readings),
+            Some(("user-stop", MotorCommand::Exit))
+        );
+    }
+
+    #[test]
+    fn asks_subsumed_behaviors() {
+        let mut behaviors: Vec<Box<dyn Behavior>> =
+            vec![Box::new(LowBattery::new()), Box::new(UserStop)];
+        sort_by_priority(&mut behaviors);
+
+        // The battery runs low while the user is holding a button down.
+        let mut readings = readings();
+        readings.button_pressed = true;
+        readings.battery_volts = LOW_BATTERY_VOLTS - 1.0;
+        for _ in 0..LOW_BATTERY_TICKS {
+            assert_eq!(
+                winner(&mut behaviors, &readings),
+                Some(("user-stop", MotorCommand::Exit))
+            );
+        }
+
+        // Low battery was still counting the whole time, and so takes over right away.
+        readings.button_pressed = false;
+        assert_eq!(
+            winner(&mut behaviors, &readings),
+            Some(("low-battery", MotorCommand::Brake))
+        );
+    }
+
+    #[test]
+    fn bounces() {
+        let mut bounce = Bounce {
+            phase: BouncePhase::Idle,
+            // Stands in for the sound, and finishes right away.
+            alert: || Ok(std::process::Command::new("true").spawn()?),
+        };
+        let mut readings = readings();
+
+        assert_eq!(bounce.propose(&readings, false).unwrap(), None);
+
+        // Once triggered, it stops while the sound plays, even if it's no longer triggered.
+        assert_eq!(
+            bounce.propose(&readings, true).unwrap(),
+            Some(MotorCommand::Brake)
+        );
+        assert_eq!(bounce.lights(), Lights::Normal);
+        if let BouncePhase::Alerting { sound } = &mut bounce.phase {
+            sound.wait().expect("sound should finish");
+        }
+
+        // Then it backs up.
+        let backup_degrees = -cm_to_wheel_degrees(BACKUP_CM).round() as i32;
+        let backing_up = Some(MotorCommand::Move {
+            left_degrees: backup_degrees,
+            right_degrees: backup_degrees,
+            speed: 500,
+        });
+        assert_eq!(bounce.propose(&readings, false).unwrap(), backing_up);
+        assert_eq!(bounce.lights(), Lights::Alert);
+        assert_eq!(bounce.propose(&readings, false).unwrap(), backing_up);
+
+        // Then it turns, one way or the other, by 90 to 270 degrees.
+        readings.wheel_degrees = (backup_degrees as f32, backup_degrees as f32);
+        let turn_degrees = match bounce.propose(&readings, false).unwrap() {
+            Some(MotorCommand::Move {
+                left_degrees,
+                right_degrees,
+                speed: 750,
+            }) if left_degrees == -right_degrees => left_degrees,
+            other => panic!("expected a turn, found {:?}", other),
+        };
+        assert!(turn_degrees.abs() >= turn_to_wheel_degrees(90.0).floor() as i32);
+        assert!(turn_degrees.abs() <= turn_to_wheel_degrees(270.0).ceil() as i32);
+        assert_eq!(bounce.lights(), Lights::Normal);
+
+        // And then it's done.
+        readings.wheel_degrees = (
+            (backup_degrees + turn_degrees) as f32,
+            (backup_degrees - turn_degrees) as f32,
+        );
+        assert_eq!(bounce.propose(&readings, false).unwrap(), None);
+    }
+}
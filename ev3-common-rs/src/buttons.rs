@@ -0,0 +1,43 @@
+//! Watches the brick's buttons, so that someone standing next to the robot can react to it (e.g.
+//!   the remote control servers engage their emergency stop when any button is pressed).
+
+use ev3dev_lang_rust::Ev3Button;
+
+/// The brick's buttons, which only report new presses.
+pub struct BrickButtons {
+    buttons: Ev3Button,
+    /// Whether any of the buttons was pressed when they were last checked.
+    was_pressed: bool,
+}
+
+impl BrickButtons {
+    /// Finds the brick's buttons, or returns `None` if they can't be read.
+    pub fn find() -> Option<BrickButtons> {
+        match Ev3Button::new() {
+            Ok(buttons) => Some(BrickButtons {
+                buttons,
+                was_pressed: false,
+            }),
+            Err(err) => {
+                eprintln!(
+                    "Buttons: unable to read the brick's buttons; disabled: {:?}",
+                    err
+                );
+                None
+            }
+        }
+    }
+
+    /// Returns `true` if any of the buttons has been pressed since they were last checked.
+    ///
+    /// Only new presses count, so that holding a button down doesn't keep triggering whatever
+    ///   it does.
+    pub fn newly_pressed(&mut self) -> bool {
+        self.buttons.process();
+        let pressed = !self.buttons.get_pressed_buttons().is_empty();
+        let newly_pressed = pressed && !self.was_pressed;
+        self.was_pressed = pressed;
+
+        newly_pressed
+    }
+}
@@ -0,0 +1,88 @@
+//! Detects cliffs (e.g. the edge of a table) via a downward-facing color sensor.
+//!
+//! The color sensor is run in reflected light mode: while it's over the table, it will see a
+//!   fairly steady amount of reflected light, and when it's over the edge, it will suddenly see
+//!   almost none.
+
+use std::cell::Cell;
+
+use ev3dev_lang_rust::sensors::{ColorSensor, Sensor};
+use ev3dev_lang_rust::Ev3Result;
+
+/// A reading that drops below this fraction of the recent baseline is treated as a cliff.
+const CLIFF_DROP_RATIO: f32 = 0.5;
+
+/// A reading (in percent) at or below this is always treated as a cliff, even without a baseline.
+const CLIFF_REFLECTED_MIN: f32 = 3.0;
+
+/// How much weight each new (non-cliff) reading gets in the running baseline.
+const BASELINE_SMOOTHING: f32 = 0.05;
+
+/// Detects cliffs via a downward-facing [ColorSensor].
+pub struct CliffDetector {
+    sensor: ColorSensor,
+    baseline: Cell<Option<f32>>,
+}
+
+impl CliffDetector {
+    /// Finds the color sensor and switches it to reflected light mode.
+    ///
+    /// Returns `None` if no color sensor is plugged in, in which case cliff detection is off.
+    pub fn find() -> Option<CliffDetector> {
+        let sensor = match ColorSensor::find() {
+            Ok(sensor) => sensor,
+            Err(_) => {
+                println!("Cliff detection: no color sensor found; disabled.");
+                return None;
+            }
+        };
+        if let Err(err) = sensor.set_mode_col_reflect() {
+            eprintln!(
+                "Cliff detection: unable to configure color sensor; disabled: {:?}",
+                err
+            );
+            return None;
+        }
+
+        println!("Cliff detection: enabled.");
+        Some(CliffDetector {
+            sensor,
+            baseline: Cell::new(None),
+        })
+    }
+
+    /// Returns the latest reflected light reading, as a percentage.
+    pub fn get_reflected(&self) -> Ev3Result<f32> {
+        Ok(self.sensor.get_value0()? as f32)
+    }
+
+    /// Returns `true` if the specified reading would be treated as a cliff, compared to the
+    ///   current baseline, without updating the baseline.
+    ///
+    /// Parameters:
+    /// * `reflected`: a reflected light reading, as a percentage, e.g. from [Self::get_reflected]
+    pub fn is_cliff_reading(&self, reflected: f32) -> bool {
+        match self.baseline.get() {
+            _ if reflected <= CLIFF_REFLECTED_MIN => true,
+            Some(baseline) => reflected < baseline * CLIFF_DROP_RATIO,
+            None => false,
+        }
+    }
+
+    /// Takes a new reading and returns `true` if the sensor is looking over a cliff.
+    pub fn is_cliff(&self) -> Ev3Result<bool> {
+        let reflected = self.get_reflected()?;
+        let cliff = self.is_cliff_reading(reflected);
+
+        // Only track the baseline while over solid ground, so that it's not dragged down by
+        //   readings from over the edge.
+        if !cliff {
+            self.baseline.set(Some(match self.baseline.get() {
+                Some(baseline) => baseline + BASELINE_SMOOTHING * (reflected - baseline),
+                None => reflected,
+            }));
+        }
+
+        Ok(cliff)
+    }
+}
@@ -0,0 +1,297 @@
+//! Provides the device actor shared by the web servers: a single background task that owns the
+//!   robot's devices, along with everything else that's needed to drive them safely.
+//!
+//! Nothing else ever touches the devices.
+//! Request handlers send the actor a command via its [Device] handle, and wait for its reply, and
+//!   the actor handles those commands one at a time, in the order they were sent.
+//! Between commands, the actor is also ticked every [Actor::TICK_INTERVAL], for everything that
+//!   has to happen periodically.
+//! After every command (and tick), it publishes its state via a [watch] channel, so that handlers
+//!   that only need to read the state don't have to wait on the actor at all.
+//!
+//! Anything that has to stop the robot right away (e.g. the emergency stop) can jump the queue,
+//!   via [Device::signal_stop()].
+//!
+//! Each web server defines its own commands and state, by implementing [Actor].
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use actix_web::http::StatusCode;
+use ev3dev_lang_rust::Ev3Error;
+use tokio::sync::{mpsc, oneshot, watch};
+
+/// How many commands can be waiting on the actor before senders have to wait, too.
+const MAILBOX_CAPACITY: usize = 32;
+
+/// The channel that the device actor replies to a command on.
+pub type Reply<T> = oneshot::Sender<Result<T, DeviceError>>;
+
+/// Everything that's owned by the device actor, and how it handles its commands.
+pub trait Actor: 'static {
+    /// The commands that the actor handles.
+    type Command: 'static;
+
+    /// The state that the actor publishes after every command.
+    type State: Clone + 'static;
+
+    /// How often the actor is ticked.
+    const TICK_INTERVAL: Duration;
+
+    /// How long to wait for the motors to stop, when the application is exiting.
+    const STOP_TIMEOUT: Duration;
+
+    /// Returns the state to publish.
+    fn state(&self) -> Self::State;
+
+    /// Handles the specified command, replying to it if it expects a reply.
+    ///
+    /// Parameters:
+    /// * `command`: the command to handle
+    fn handle(&mut self, command: Self::Command);
+
+    /// Runs everything that has to happen periodically.
+    fn tick(&mut self);
+
+    /// Stops the robot right away, for [Device::signal_stop()].
+    ///
+    /// This is called before the actor handles anything else, even the commands that were sent
+    ///   before the stop was signalled, though whatever command goes along with the signal still
+    ///   arrives in order, afterwards.
+    fn stop_signalled(&mut self);
+
+    /// Stops the motors, for when the application is exiting.
+    fn stop(&mut self) -> Result<(), DeviceError>;
+
+    /// Returns `true` if any of the motors is still running.
+    fn is_running(&self) -> Result<bool, DeviceError>;
+}
+
+/// What's sent to the device actor: either one of its commands, or one of the messages that
+///   every actor handles.
+enum Message<C> {
+    Command(C),
+    Tick,
+    Shutdown(Reply<()>),
+}
+
+/// Sends the result of a command back to whoever sent it.
+///
+/// Parameters:
+/// * `reply`: the channel to send the result on
+/// * `result`: the result to send
+pub fn respond<T, E: Into<DeviceError>>(reply: Reply<T>, result: Result<T, E>) {
+    // Sending only fails if the sender has since given up on waiting, which is fine.
+    let _ = reply.send(result.map_err(Into::into));
+}
+
+/// The handle that the rest of the application uses to talk to the device actor.
+pub struct Device<A: Actor> {
+    mailbox: mpsc::Sender<Message<A::Command>>,
+    state: watch::Receiver<A::State>,
+    /// Raised by [Device::signal_stop()], until the actor gets to it.
+    stop_signal: Arc<AtomicBool>,
+}
+
+impl<A: Actor> Clone for Device<A> {
+    fn clone(&self) -> Self {
+        Device {
+            mailbox: self.mailbox.clone(),
+            state: self.state.clone(),
+            stop_signal: self.stop_signal.clone(),
+        }
+    }
+}
+
+impl<A: Actor> Device<A> {
+    /// Starts the device actor (and the task that ticks it), which takes ownership of the
+    ///   specified [Actor], and returns a [Device] handle for it.
+    ///
+    /// Parameters:
+    /// * `actor`: the [Actor] for the device actor to own
+    pub fn start(actor: A) -> Device<A> {
+        let (mailbox, messages) = mpsc::channel(MAILBOX_CAPACITY);
+        let (state_sender, state) = watch::channel(actor.state());
+        let stop_signal = Arc::new(AtomicBool::new(false));
+        actix_web::rt::spawn(run(actor, messages, state_sender, stop_signal.clone()));
+        actix_web::rt::spawn(tick(mailbox.clone(), A::TICK_INTERVAL));
+
+        Device {
+            mailbox,
+            state,
+            stop_signal,
+        }
+    }
+
+    /// Sends the device actor a command, and waits for its reply.
+    ///
+    /// Parameters:
+    /// * `command`: builds the command to send from the [Reply] channel it's to use
+    pub async fn send<T, F>(&self, command: F) -> Result<T, DeviceError>
+    where
+        F: FnOnce(Reply<T>) -> A::Command,
+    {
+        self.request(|reply| Message::Command(command(reply))).await
+    }
+
+    /// Has the device actor stop the robot (via [Actor::stop_signalled()]) before it handles
+    ///   anything else, rather than once it gets through the commands that are already waiting on
+    ///   it.
+    ///
+    /// This doesn't wait for the actor: follow it up with whatever command goes along with it.
+    pub fn signal_stop(&self) {
+        self.stop_signal.store(true, Ordering::SeqCst);
+    }
+
+    /// Stops the motors and then the device actor itself, for when the application is exiting.
+    pub async fn shutdown(&self) -> Result<(), DeviceError> {
+        self.request(Message::Shutdown).await
+    }
+
+    /// Returns the state that the device actor most recently published.
+    pub fn state(&self) -> A::State {
+        self.state.borrow().clone()
+    }
+
+    /// Sends the device actor a [Message], and waits for its reply.
+    ///
+    /// Parameters:
+    /// * `message`: builds the [Message] to send from the [Reply] channel it's to use
+    async fn request<T, F>(&self, message: F) -> Result<T, DeviceError>
+    where
+        F: FnOnce(Reply<T>) -> Message<A::Command>,
+    {
+        let (reply, result) = oneshot::channel();
+        self.mailbox
+            .clone()
+            .send(message(reply))
+            .await
+            .map_err(|_| DeviceError::gone())?;
+
+        result.await.map_err(|_| DeviceError::gone())?
+    }
+}
+
+/// Runs the device actor until it's shut down, handling each [Message] as it arrives, and
+///   publishing the actor's state after each one.
+///
+/// Parameters:
+/// * `actor`: the [Actor] that the device actor owns
+/// * `messages`: the channel that [Message]s arrive on
+/// * `state`: the channel to publish the actor's state to
+/// * `stop_signal`: raised by [Device::signal_stop()]
+async fn run<A: Actor>(
+    mut actor: A,
+    mut messages: mpsc::Receiver<Message<A::Command>>,
+    state: watch::Sender<A::State>,
+    stop_signal: Arc<AtomicBool>,
+) {
+    while let Some(message) = messages.recv().await {
+        if stop_signal.swap(false, Ordering::SeqCst) {
+            actor.stop_signalled();
+        }
+
+        match message {
+            Message::Command(command) => actor.handle(command),
+            Message::Tick => actor.tick(),
+            Message::Shutdown(reply) => {
+                respond(reply, stop_motors(&mut actor).await);
+                return;
+            }
+        }
+
+        // Publishing only fails if every [Device] handle has been dropped, which is fine.
+        let _ = state.broadcast(actor.state());
+    }
+}
+
+/// Stops the motors, and then waits for them to stop, for when the application is exiting.
+///
+/// The motors are checked every [Actor::TICK_INTERVAL], rather than blocking on them, so that
+///   nothing else running on the same thread is held up in the meantime.
+///
+/// Parameters:
+/// * `actor`: the [Actor] that the device actor owns
+async fn stop_motors<A: Actor>(actor: &mut A) -> Result<(), DeviceError> {
+    actor.stop()?;
+
+    let deadline = Instant::now() + A::STOP_TIMEOUT;
+    while actor.is_running()? {
+        if Instant::now() >= deadline {
+            return Err(DeviceError::from(Ev3Error::InternalError {
+                msg: format!("The motors were still moving after {:?}.", A::STOP_TIMEOUT),
+            }));
+        }
+        actix_web::rt::time::delay_for(A::TICK_INTERVAL).await;
+    }
+
+    Ok(())
+}
+
+/// Runs until the device actor stops, sending it a [Message::Tick] at the specified interval.
+///
+/// Parameters:
+/// * `mailbox`: the channel to send the [Message::Tick]s on
+/// * `tick_interval`: how often to send them
+async fn tick<C>(mut mailbox: mpsc::Sender<Message<C>>, tick_interval: Duration) {
+    let mut interval = actix_web::rt::time::interval(tick_interval);
+    loop {
+        interval.tick().await;
+        if mailbox.send(Message::Tick).await.is_err() {
+            return;
+        }
+    }
+}
+
+/// An error returned by the device actor.
+///
+/// Unlike [actix_web::Error], this can be sent back from the actor to the handler waiting on it.
+#[derive(Debug)]
+pub struct DeviceError {
+    status: StatusCode,
+    message: String,
+}
+
+impl DeviceError {
+    /// Returns the [DeviceError] for when the device actor isn't running anymore, which should
+    ///   only happen while the application is exiting.
+    fn gone() -> DeviceError {
+        DeviceError {
+            status: StatusCode::SERVICE_UNAVAILABLE,
+            message: "The EV3 devices are unavailable.".to_string(),
+        }
+    }
+}
+
+impl std::fmt::Display for DeviceError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for DeviceError {}
+
+impl From<actix_web::Error> for DeviceError {
+    fn from(err: actix_web::Error) -> Self {
+        DeviceError {
+            status: err.as_response_error().status_code(),
+            message: err.to_string(),
+        }
+    }
+}
+
+impl From<Ev3Error> for DeviceError {
+    fn from(err: Ev3Error) -> Self {
+        DeviceError {
+            status: StatusCode::INTERNAL_SERVER_ERROR,
+            message: format!("EV3 error: {:?}", err),
+        }
+    }
+}
+
+impl actix_web::error::ResponseError for DeviceError {
+    fn status_code(&self) -> StatusCode {
+        self.status
+    }
+}
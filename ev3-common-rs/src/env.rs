@@ -0,0 +1,53 @@
+//! Reads the settings that can be overridden via environment variables.
+
+use std::fmt::Display;
+use std::str::FromStr;
+
+use ev3dev_lang_rust::Ev3Error;
+
+/// An environment variable whose value couldn't be parsed, or is out of range.
+#[derive(Debug)]
+pub struct InvalidEnvVar {
+    name: String,
+    value: String,
+}
+
+impl Display for InvalidEnvVar {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Invalid {} value '{}'.", self.name, self.value)
+    }
+}
+
+impl std::error::Error for InvalidEnvVar {}
+
+impl From<InvalidEnvVar> for Ev3Error {
+    fn from(err: InvalidEnvVar) -> Self {
+        Ev3Error::InternalError {
+            msg: err.to_string(),
+        }
+    }
+}
+
+/// Returns the parsed value of the specified environment variable, or the specified default if
+///   it's unset.
+///
+/// Parameters:
+/// * `name`: the name of the environment variable to read
+/// * `default`: the value to use if the environment variable isn't set
+/// * `in_range`: returns `true` if the parsed value is allowed
+pub fn env_var_or<T: FromStr>(
+    name: &str,
+    default: T,
+    in_range: fn(&T) -> bool,
+) -> Result<T, InvalidEnvVar> {
+    match std::env::var(name) {
+        Ok(value) => match value.parse::<T>() {
+            Ok(parsed) if in_range(&parsed) => Ok(parsed),
+            _ => Err(InvalidEnvVar {
+                name: name.to_string(),
+                value,
+            }),
+        },
+        Err(_) => Ok(default),
+    }
+}
@@ -0,0 +1,212 @@
+//! Streams telemetry and discrete events (e.g. stalls or obstacles) to clients, via
+//!   [Server-Sent Events](https://developer.mozilla.org/en-US/docs/Web/API/Server-sent_events).
+//!
+//! A single background sampling task reads the telemetry, turns changes in it into discrete
+//!   [Event]s via an [EventDetector], and publishes everything to a [broadcast] channel, which
+//!   each `GET /events` subscriber gets its own receiver for.
+
+use std::time::Duration;
+
+use actix_web::{get, web, HttpResponse};
+use serde::Serialize;
+use tokio::sync::broadcast;
+
+use crate::telemetry::{MotorTelemetry, SensorTelemetry};
+
+/// The environment variable that can be used to override [TELEMETRY_INTERVAL_DEFAULT], in
+///   milliseconds.
+const TELEMETRY_INTERVAL_VAR: &str = "REMOTE_CONTROL_TELEMETRY_INTERVAL_MS";
+
+/// How often telemetry is sampled and sent to subscribers, unless told otherwise.
+const TELEMETRY_INTERVAL_DEFAULT: Duration = Duration::from_millis(500);
+
+/// How many [Event]s a slow subscriber can fall behind by before it starts missing some.
+const EVENTS_CAPACITY: usize = 32;
+
+/// An obstacle closer than this (in centimeters) triggers an `obstacle` [Event].
+const OBSTACLE_CM: f32 = 20.0;
+
+/// A battery voltage below this triggers a `low_battery` [Event].
+const LOW_BATTERY_VOLTS: f32 = 6.5;
+
+/// How far the battery voltage has to recover before another `low_battery` [Event] can be sent,
+///   so that a noisy reading hovering around [LOW_BATTERY_VOLTS] doesn't trigger a flood of them.
+const LOW_BATTERY_HYSTERESIS_VOLTS: f32 = 0.2;
+
+/// A named event, with its data already serialized to JSON.
+#[derive(Clone)]
+pub struct Event {
+    name: &'static str,
+    data: String,
+}
+
+impl Event {
+    /// Constructs a new [Event].
+    ///
+    /// Parameters:
+    /// * `name`: the event's name, e.g. `telemetry` or `stall`
+    /// * `data`: the event's data, which will be serialized to JSON
+    pub fn new<T: Serialize>(name: &'static str, data: &T) -> Event {
+        Event {
+            name,
+            data: serde_json::to_string(data).unwrap_or_else(|err| {
+                eprintln!("Events: unable to serialize '{}' event: {}", name, err);
+                "null".to_string()
+            }),
+        }
+    }
+
+    /// Formats this [Event] as a message in the Server-Sent Events wire format.
+    fn to_message(&self) -> web::Bytes {
+        web::Bytes::from(format!("event: {}\ndata: {}\n\n", self.name, self.data))
+    }
+}
+
+/// Constructs the channel that the sampling task publishes [Event]s to.
+pub fn channel() -> broadcast::Sender<Event> {
+    broadcast::channel(EVENTS_CAPACITY).0
+}
+
+/// Returns how often telemetry should be sampled, which can be configured via the
+///   `REMOTE_CONTROL_TELEMETRY_INTERVAL_MS` environment variable.
+pub fn telemetry_interval() -> anyhow::Result<Duration> {
+    match std::env::var(TELEMETRY_INTERVAL_VAR) {
+        Ok(value) => match value.parse() {
+            Ok(millis) if millis > 0 => Ok(Duration::from_millis(millis)),
+            _ => Err(anyhow::anyhow!(
+                "Invalid {} value '{}'.",
+                TELEMETRY_INTERVAL_VAR,
+                value
+            )),
+        },
+        Err(_) => Ok(TELEMETRY_INTERVAL_DEFAULT),
+    }
+}
+
+/// This API endpoint streams telemetry and discrete events to the client, as Server-Sent Events,
+///   until it disconnects.
+///
+/// Parameters:
+/// * `events`: the channel that the sampling task publishes [Event]s to
+#[get("/events")]
+pub async fn events(events: web::Data<broadcast::Sender<Event>>) -> HttpResponse {
+    let stream = futures::stream::unfold(events.subscribe(), |mut receiver| async move {
+        loop {
+            match receiver.recv().await {
+                Ok(event) => {
+                    return Some((Ok::<_, actix_web::Error>(event.to_message()), receiver))
+                }
+                // If this client can't keep up, just skip what it missed.
+                Err(broadcast::RecvError::Lagged(_)) => continue,
+                Err(broadcast::RecvError::Closed) => return None,
+            }
+        }
+    });
+
+    HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .header(actix_web::http::header::CACHE_CONTROL, "no-cache")
+        .streaming(Box::pin(stream))
+}
+
+/// Turns changes in the sampled telemetry into discrete [Event]s.
+pub struct EventDetector<M> {
+    mode: Option<M>,
+    stalled: Vec<bool>,
+    obstacle: bool,
+    touch_pressed: bool,
+    cliff: bool,
+    low_battery: bool,
+}
+
+impl<M: Clone + PartialEq + Serialize> EventDetector<M> {
+    /// Constructs a new [EventDetector].
+    pub fn new() -> EventDetector<M> {
+        EventDetector {
+            mode: None,
+            stalled: vec![],
+            obstacle: false,
+            touch_pressed: false,
+            cliff: false,
+            low_battery: false,
+        }
+    }
+
+    /// Returns the [Event]s for whatever has changed since the last sample.
+    ///
+    /// Parameters:
+    /// * `mode`: the robot's current driving mode
+    /// * `motors`: the [MotorTelemetry] for each motor, left then right
+    /// * `sensors`: the [SensorTelemetry]
+    pub fn detect(
+        &mut self,
+        mode: &M,
+        motors: &[MotorTelemetry],
+        sensors: &SensorTelemetry,
+    ) -> Vec<Event> {
+        let mut detected = vec![];
+
+        if self.mode.as_ref() != Some(mode) {
+            detected.push(Event::new("mode", &serde_json::json!({ "mode": mode })));
+            self.mode = Some(mode.clone());
+        }
+
+        self.stalled.resize(motors.len(), false);
+        for (index, motor) in motors.iter().enumerate() {
+            let stalled = motor.state.iter().any(|state| state == "stalled");
+            if stalled && !self.stalled[index] {
+                let motor = if index == 0 { "left" } else { "right" };
+                detected.push(Event::new("stall", &serde_json::json!({ "motor": motor })));
+            }
+            self.stalled[index] = stalled;
+        }
+
+        let obstacle =
+            matches!(sensors.distance_cm, Some(distance_cm) if distance_cm < OBSTACLE_CM);
+        if obstacle && !self.obstacle {
+            detected.push(Event::new(
+                "obstacle",
+                &serde_json::json!({ "kind": "proximity", "distance_cm": sensors.distance_cm }),
+            ));
+        }
+        self.obstacle = obstacle;
+
+        let touch_pressed = sensors.touch_pressed == Some(true);
+        if touch_pressed && !self.touch_pressed {
+            detected.push(Event::new(
+                "obstacle",
+                &serde_json::json!({ "kind": "touch" }),
+            ));
+        }
+        self.touch_pressed = touch_pressed;
+
+        let cliff = sensors.cliff == Some(true);
+        if cliff && !self.cliff {
+            detected.push(Event::new(
+                "obstacle",
+                &serde_json::json!({ "kind": "cliff" }),
+            ));
+        }
+        self.cliff = cliff;
+
+        if let Some(battery_volts) = sensors.battery_volts {
+            if !self.low_battery && battery_volts < LOW_BATTERY_VOLTS {
+                detected.push(Event::new(
+                    "low_battery",
+                    &serde_json::json!({ "battery_volts": battery_volts }),
+                ));
+                self.low_battery = true;
+            } else if battery_volts > LOW_BATTERY_VOLTS + LOW_BATTERY_HYSTERESIS_VOLTS {
+                self.low_battery = false;
+            }
+        }
+
+        detected
+    }
+}
+
+impl<M: Clone + PartialEq + Serialize> Default for EventDetector<M> {
+    fn default() -> EventDetector<M> {
+        EventDetector::new()
+    }
+}
@@ -0,0 +1,199 @@
+//! An optional safety layer, shared by the remote control servers, that keeps remote-controlled
+//!   driving from running into things, using the front proximity and touch sensors (if plugged in).
+//!
+//! Inside the slow threshold, forward driving is slowed down, the closer the obstacle the slower.
+//! Inside the stop threshold, or while the touch sensor is pressed, forward driving is refused
+//!   altogether. Driving backwards (away from the obstacle) and turning are always allowed.
+//!
+//! The thresholds can be configured via the `REMOTE_CONTROL_GUARD_SLOW_CM` and
+//!   `REMOTE_CONTROL_GUARD_STOP_CM` environment variables, and the guard can be turned off
+//!   entirely by setting `REMOTE_CONTROL_COLLISION_GUARD` to `off`.
+
+use crate::telemetry::Sensors;
+use ev3dev_lang_rust::Ev3Result;
+use serde::Serialize;
+
+/// The environment variable that can be set to `off` to turn off the [CollisionGuard].
+const GUARD_VAR: &str = "REMOTE_CONTROL_COLLISION_GUARD";
+
+/// The environment variable that can be used to override [SLOW_CM_DEFAULT].
+const SLOW_CM_VAR: &str = "REMOTE_CONTROL_GUARD_SLOW_CM";
+
+/// Obstacles closer than this (in centimeters) slow the robot down, unless told otherwise.
+const SLOW_CM_DEFAULT: f32 = 40.0;
+
+/// The environment variable that can be used to override [STOP_CM_DEFAULT].
+const STOP_CM_VAR: &str = "REMOTE_CONTROL_GUARD_STOP_CM";
+
+/// Obstacles closer than this (in centimeters) stop the robot, unless told otherwise.
+const STOP_CM_DEFAULT: f32 = 15.0;
+
+/// The slowest (as a fraction of the requested speed) that the robot is slowed to, right before
+///   it's stopped, so that it doesn't just creep along.
+const SPEED_FACTOR_MIN: f32 = 0.2;
+
+/// Speed factors are rounded off to this step, so that noisy proximity readings don't cause a
+///   constant stream of motor updates.
+const SPEED_FACTOR_STEP: f32 = 0.1;
+
+/// Vetoes or slows forward driving near obstacles.
+pub struct CollisionGuard {
+    slow_cm: f32,
+    stop_cm: f32,
+}
+
+impl CollisionGuard {
+    /// Configures the [CollisionGuard] for the specified [Sensors].
+    ///
+    /// Returns `None` if the guard has been turned off, or if neither a proximity nor a touch
+    ///   sensor is plugged in, in which case forward driving is never vetoed.
+    ///
+    /// Parameters:
+    /// * `sensors`: the [Sensors] that the guard will read
+    pub fn find(sensors: &Sensors) -> Option<CollisionGuard> {
+        if std::env::var(GUARD_VAR).map_or(false, |value| value.eq_ignore_ascii_case("off")) {
+            println!("Collision guard: turned off.");
+            return None;
+        }
+        if sensors.proximity_sensor.is_none() && sensors.touch_sensor.is_none() {
+            println!("Collision guard: no proximity or touch sensor found; disabled.");
+            return None;
+        }
+
+        let mut slow_cm = env_cm(SLOW_CM_VAR, SLOW_CM_DEFAULT);
+        let mut stop_cm = env_cm(STOP_CM_VAR, STOP_CM_DEFAULT);
+        if stop_cm >= slow_cm {
+            eprintln!(
+                "Invalid {} and {} values: the stop distance must be less than the slow distance; \
+                 using defaults of {} and {}.",
+                STOP_CM_VAR, SLOW_CM_VAR, STOP_CM_DEFAULT, SLOW_CM_DEFAULT
+            );
+            slow_cm = SLOW_CM_DEFAULT;
+            stop_cm = STOP_CM_DEFAULT;
+        }
+
+        println!(
+            "Collision guard: slowing inside {} cm, stopping inside {} cm.",
+            slow_cm, stop_cm
+        );
+        Some(CollisionGuard { slow_cm, stop_cm })
+    }
+
+    /// Checks the [Sensors] for obstacles in front of the robot.
+    ///
+    /// Returns the [Intervention] needed to drive forwards safely, if any.
+    ///
+    /// Parameters:
+    /// * `sensors`: the [Sensors] to read
+    pub fn check(&self, sensors: &Sensors) -> Ev3Result<Option<Intervention>> {
+        if let Some(touch_sensor) = &sensors.touch_sensor {
+            if touch_sensor.get_pressed_state()? {
+                return Ok(Some(Intervention {
+                    action: GuardAction::Stop,
+                    reason: GuardReason::Touch,
+                    distance_cm: None,
+                    speed_factor: 0.0,
+                }));
+            }
+        }
+
+        let distance_cm = match &sensors.proximity_sensor {
+            Some(proximity_sensor) => proximity_sensor.get_distance_cm()?,
+            None => return Ok(None),
+        };
+        if distance_cm < self.stop_cm {
+            Ok(Some(Intervention {
+                action: GuardAction::Stop,
+                reason: GuardReason::Proximity,
+                distance_cm: Some(distance_cm),
+                speed_factor: 0.0,
+            }))
+        } else if distance_cm < self.slow_cm {
+            // Ramp the speed down from full at the slow distance to the minimum at the stop one.
+            let ramp = (distance_cm - self.stop_cm) / (self.slow_cm - self.stop_cm);
+            let speed_factor = SPEED_FACTOR_MIN + (1.0 - SPEED_FACTOR_MIN) * ramp;
+            let speed_factor = (speed_factor / SPEED_FACTOR_STEP).round() * SPEED_FACTOR_STEP;
+            Ok(Some(Intervention {
+                action: GuardAction::Slow,
+                reason: GuardReason::Proximity,
+                distance_cm: Some(distance_cm),
+                speed_factor,
+            }))
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+/// Reads a distance (in centimeters) from the specified environment variable.
+///
+/// Parameters:
+/// * `name`: the name of the environment variable to read
+/// * `default`: the value to use if the environment variable isn't set or isn't valid
+fn env_cm(name: &str, default: f32) -> f32 {
+    match std::env::var(name) {
+        Ok(value) => match value.parse::<f32>() {
+            Ok(cm) if cm.is_finite() && cm >= 0.0 => cm,
+            _ => {
+                eprintln!(
+                    "Invalid {} value '{}'; using default of {}.",
+                    name, value, default
+                );
+                default
+            }
+        },
+        Err(_) => default,
+    }
+}
+
+/// What the [CollisionGuard] did to forward driving.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GuardAction {
+    /// Forward driving was slowed down.
+    Slow,
+    /// Forward driving was refused.
+    Stop,
+}
+
+/// Which sensor the [CollisionGuard] acted on.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GuardReason {
+    /// The proximity sensor saw an obstacle.
+    Proximity,
+    /// The touch sensor was pressed.
+    Touch,
+}
+
+/// How, and why, the [CollisionGuard] is limiting forward driving.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub struct Intervention {
+    pub action: GuardAction,
+    pub reason: GuardReason,
+    /// How far away the obstacle is, in centimeters, if it was seen by the proximity sensor.
+    pub distance_cm: Option<f32>,
+    /// The fraction of the requested speed that the robot is allowed to drive forwards at.
+    pub speed_factor: f32,
+}
+
+impl Intervention {
+    /// Returns `true` if the specified [Intervention]s would drive the motors the same way,
+    ///   e.g. even though the obstacle's distance changed a little.
+    pub fn same_effect(a: &Option<Intervention>, b: &Option<Intervention>) -> bool {
+        let effect =
+            |intervention: &Option<Intervention>| intervention.map(|i| (i.action, i.speed_factor));
+        effect(a) == effect(b)
+    }
+
+    /// Describes why the [CollisionGuard] intervened, e.g. "Obstacle 12.0 cm ahead".
+    pub fn describe(&self) -> String {
+        match (self.reason, self.distance_cm) {
+            (GuardReason::Proximity, Some(distance_cm)) => {
+                format!("Obstacle {:.1} cm ahead", distance_cm)
+            }
+            (GuardReason::Proximity, None) => "Obstacle ahead".to_string(),
+            (GuardReason::Touch, _) => "Touch sensor pressed".to_string(),
+        }
+    }
+}
@@ -0,0 +1,22 @@
+//! The EV3 device code shared by the driving routines in this repository: the drive motors, the
+//!   optional sensors, the autonomous driving behaviors, and (with the `web` feature) the pieces
+//!   shared by the web servers.
+//!
+//! Each routine still owns its devices; this just keeps them from each carrying their own copy
+//!   of the code for them.
+
+pub mod behaviors;
+pub mod buttons;
+pub mod cliff;
+#[cfg(feature = "web")]
+pub mod device;
+pub mod env;
+#[cfg(feature = "web")]
+pub mod events;
+pub mod guard;
+pub mod motors;
+pub mod moves;
+#[cfg(feature = "web")]
+pub mod pairing;
+pub mod proximity;
+pub mod telemetry;
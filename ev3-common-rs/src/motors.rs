@@ -0,0 +1,179 @@
+//! Manages the robot's two drive motors in concert.
+
+use std::time::Duration;
+
+use ev3dev_lang_rust::motors::{LargeMotor, MotorPort};
+use ev3dev_lang_rust::{Ev3Error, Ev3Result};
+
+use crate::telemetry::MotorTelemetry;
+
+/// Represents a set of [LargeMotor]s that ought to be managed in concert.
+pub struct LargeMotorSet {
+    pub motors: Vec<LargeMotor>,
+}
+
+impl LargeMotorSet {
+    /// Constructs a [LargeMotorSet] for the robot's wheels: left (port B) then right (port C).
+    pub fn new() -> Ev3Result<LargeMotorSet> {
+        Ok(LargeMotorSet {
+            motors: vec![
+                LargeMotor::get(MotorPort::OutB)?,
+                LargeMotor::get(MotorPort::OutC)?,
+            ],
+        })
+    }
+
+    /// Proxies [LargeMotor::set_stop_action].
+    pub fn set_stop_action(&self, stop_action: &str) -> Ev3Result<()> {
+        for motor in &self.motors {
+            motor.set_stop_action(stop_action)?;
+        }
+
+        Ok(())
+    }
+
+    /// Proxies [LargeMotor::stop].
+    pub fn stop(&self) -> Ev3Result<()> {
+        for motor in &self.motors {
+            motor.stop()?;
+        }
+
+        Ok(())
+    }
+
+    /// Proxies [LargeMotor::set_speed_sp].
+    pub fn set_speed_sp(&self, speed_sp: i32) -> Ev3Result<()> {
+        for motor in &self.motors {
+            motor.set_speed_sp(speed_sp)?;
+        }
+
+        Ok(())
+    }
+
+    /// Proxies [LargeMotor::set_duty_cycle_sp].
+    pub fn set_duty_cycle_sp(&self, duty_cycle: i32) -> Ev3Result<()> {
+        for motor in &self.motors {
+            motor.set_duty_cycle_sp(duty_cycle)?;
+        }
+
+        Ok(())
+    }
+
+    /// Proxies [LargeMotor::run_forever].
+    pub fn run_forever(&self) -> Ev3Result<()> {
+        for motor in &self.motors {
+            motor.run_forever()?;
+        }
+
+        Ok(())
+    }
+
+    /// Proxies [LargeMotor::run_direct].
+    pub fn run_direct(&self) -> Ev3Result<()> {
+        for motor in &self.motors {
+            motor.run_direct()?;
+        }
+
+        Ok(())
+    }
+
+    /// Proxies [LargeMotor::run_timed].
+    pub fn run_timed(&self, time_sp: Option<Duration>) -> Ev3Result<()> {
+        for motor in &self.motors {
+            motor.run_timed(time_sp)?;
+        }
+
+        Ok(())
+    }
+
+    /// Starts rotating each motor by the specified number of degrees, via
+    ///   [LargeMotor::run_to_rel_pos].
+    ///
+    /// Parameters:
+    /// * `degrees`: how far to rotate each motor, in the same order as [LargeMotorSet::motors]
+    /// * `speed`: the speed to rotate the motors at, in degrees per second
+    pub fn run_to_rel_degrees(&self, degrees: &[i32], speed: i32) -> Ev3Result<()> {
+        for (motor, degrees) in self.motors.iter().zip(degrees) {
+            let counts_per_rot = motor.get_count_per_rot()?;
+            motor.set_speed_sp(degrees_to_counts(speed, counts_per_rot)?)?;
+            motor.set_position_sp(degrees_to_counts(*degrees, counts_per_rot)?)?;
+        }
+        for motor in &self.motors {
+            motor.run_to_rel_pos(None)?;
+        }
+
+        Ok(())
+    }
+
+    /// Returns the current (left, right) positions of the motors, in degrees.
+    pub fn get_wheel_degrees(&self) -> Ev3Result<(f32, f32)> {
+        let mut degrees = Vec::with_capacity(self.motors.len());
+        for motor in &self.motors {
+            degrees.push(motor.get_position()? as f32 * 360.0 / motor.get_count_per_rot()? as f32);
+        }
+
+        Ok((degrees[0], degrees[1]))
+    }
+
+    /// Reads the actual speed, position, and state flags of each motor.
+    pub fn read_telemetry(&self) -> Ev3Result<Vec<MotorTelemetry>> {
+        let mut telemetry = Vec::with_capacity(self.motors.len());
+        for motor in &self.motors {
+            telemetry.push(MotorTelemetry::read(motor)?);
+        }
+
+        Ok(telemetry)
+    }
+
+    /// Returns `true` if any of the motors is still running.
+    pub fn is_running(&self) -> Ev3Result<bool> {
+        for motor in &self.motors {
+            let state = motor.get_state()?;
+            if state.iter().any(|state| state == LargeMotor::STATE_RUNNING) {
+                return Ok(true);
+            }
+        }
+
+        Ok(false)
+    }
+
+    /// Proxies [LargeMotor::wait_until].
+    pub fn wait_until(&self, state: &str, timeout: Option<Duration>) -> bool {
+        let mut result = true;
+        for motor in &self.motors {
+            result = match motor.wait_until(state, timeout) {
+                true => result,
+                false => false,
+            };
+        }
+
+        result
+    }
+
+    /// Proxies [LargeMotor::wait_until_not_moving].
+    pub fn wait_until_not_moving(&self, timeout: Option<Duration>) -> bool {
+        let mut result = true;
+        for motor in &self.motors {
+            result = match motor.wait_until_not_moving(timeout) {
+                true => result,
+                false => false,
+            };
+        }
+
+        result
+    }
+}
+
+/// Converts degrees (or degrees per second) into a motor's tacho counts (or counts per second).
+///
+/// Parameters:
+/// * `degrees`: the degrees to convert
+/// * `counts_per_rot`: how many tacho counts the motor reports per full rotation
+fn degrees_to_counts(degrees: i32, counts_per_rot: i32) -> Ev3Result<i32> {
+    degrees
+        .checked_mul(counts_per_rot)
+        .map(|counts| counts / 360)
+        .ok_or_else(|| Ev3Error::InternalError {
+            msg: format!("{} degrees is too far for the motors.", degrees),
+        })
+}
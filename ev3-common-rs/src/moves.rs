@@ -0,0 +1,125 @@
+//! Supports distance-based moves, which are run via `run_to_rel_pos` rather than for a fixed
+//!   amount of time, so that the distance covered doesn't vary with battery level or surface.
+
+use std::time::{Duration, Instant};
+
+use crate::behaviors::MotorCommand;
+
+/// The diameter of the wheels, which is used to convert distances into wheel rotations.
+/// This matches the standard tires included in the EV3 education kit.
+pub const WHEEL_DIAMETER_CM: f32 = 5.6;
+
+/// The distance between the centers of the two wheels, which is used to convert robot turns into
+///   wheel rotations.
+pub const AXLE_TRACK_CM: f32 = 12.0;
+
+/// How close (in degrees) each wheel must get to its target for a [WheelMove] to be considered
+///   done.
+const POSITION_TOLERANCE_DEGREES: f32 = 5.0;
+
+/// Converts a distance over the ground into the number of degrees the wheels must turn.
+pub fn cm_to_wheel_degrees(cm: f32) -> f32 {
+    cm / (std::f32::consts::PI * WHEEL_DIAMETER_CM) * 360.0
+}
+
+/// Converts a turn of the robot in place into the number of degrees each wheel must turn.
+pub fn turn_to_wheel_degrees(degrees: f32) -> f32 {
+    cm_to_wheel_degrees(std::f32::consts::PI * AXLE_TRACK_CM * degrees / 360.0)
+}
+
+/// Tracks the progress of a [MotorCommand::Move], via the wheel positions, e.g. from each
+///   tick's [crate::behaviors::SensorReadings].
+#[derive(Clone, Copy, Debug)]
+pub struct WheelMove {
+    command: MotorCommand,
+    start: (f32, f32),
+    target: (f32, f32),
+    deadline: Instant,
+}
+
+impl WheelMove {
+    /// Starts tracking a new [WheelMove], which will time out if it takes much longer than it
+    ///   ought to at the specified speed.
+    ///
+    /// Parameters:
+    /// * `wheel_degrees`: the current (left, right) wheel positions, as the starting positions
+    /// * `left_degrees`: how far to rotate the left wheel
+    /// * `right_degrees`: how far to rotate the right wheel
+    /// * `speed`: the speed to rotate both wheels at, in degrees per second
+    pub fn new(
+        wheel_degrees: (f32, f32),
+        left_degrees: f32,
+        right_degrees: f32,
+        speed: i32,
+    ) -> WheelMove {
+        let degrees_max = left_degrees.abs().max(right_degrees.abs());
+        let expected = Duration::from_secs_f32(degrees_max / (speed.abs().max(1) as f32));
+
+        WheelMove {
+            command: MotorCommand::Move {
+                left_degrees: left_degrees.round() as i32,
+                right_degrees: right_degrees.round() as i32,
+                speed,
+            },
+            start: wheel_degrees,
+            target: (left_degrees, right_degrees),
+            deadline: Instant::now() + expected.mul_f32(1.5) + Duration::from_secs(1),
+        }
+    }
+
+    /// The [MotorCommand] that will run this move.
+    pub fn command(&self) -> MotorCommand {
+        self.command
+    }
+
+    /// Returns how far each wheel has rotated since the move started, in degrees.
+    ///
+    /// Parameters:
+    /// * `wheel_degrees`: the current (left, right) wheel positions
+    pub fn traveled(&self, wheel_degrees: (f32, f32)) -> (f32, f32) {
+        (
+            wheel_degrees.0 - self.start.0,
+            wheel_degrees.1 - self.start.1,
+        )
+    }
+
+    /// Returns `true` once both wheels have reached their targets.
+    ///
+    /// Parameters:
+    /// * `wheel_degrees`: the current (left, right) wheel positions
+    pub fn is_arrived(&self, wheel_degrees: (f32, f32)) -> bool {
+        let (left_traveled, right_traveled) = self.traveled(wheel_degrees);
+
+        (left_traveled - self.target.0).abs() <= POSITION_TOLERANCE_DEGREES
+            && (right_traveled - self.target.1).abs() <= POSITION_TOLERANCE_DEGREES
+    }
+
+    /// Returns `true` once the move has taken so long that it's probably stuck.
+    /// Check [WheelMove::is_arrived()] first, as a move that has arrived may still be past its
+    ///   deadline.
+    pub fn is_timed_out(&self) -> bool {
+        Instant::now() >= self.deadline
+    }
+
+    /// Returns `true` once both wheels have reached their targets, or once the move has taken so
+    ///   long that it's probably stuck, for callers that just move on either way.
+    ///
+    /// Parameters:
+    /// * `wheel_degrees`: the current (left, right) wheel positions
+    pub fn is_done(&self, wheel_degrees: (f32, f32)) -> bool {
+        if self.is_arrived(wheel_degrees) {
+            return true;
+        }
+
+        if self.is_timed_out() {
+            let (left_traveled, right_traveled) = self.traveled(wheel_degrees);
+            println!(
+                "Moves: move timed out, after {:.0}/{:.0} degrees.",
+                left_traveled, right_traveled
+            );
+            return true;
+        }
+
+        false
+    }
+}
@@ -0,0 +1,317 @@
+//! Requires clients to pair with the robot before they can drive it, so that not just anyone on
+//!   the network can.
+//!
+//! At startup, a short random pairing code is shown on the brick's screen (and printed to
+//!   stdout). A client submits that code once via `POST /pair`, and gets a session cookie in
+//!   return, which all of the motion endpoints then require.
+//!
+//! The code stays the same for as long as the server runs. To keep it from being guessed by brute
+//!   force instead, each client address is locked out for a while after too many wrong codes,
+//!   with the lockout doubling for every further wrong code.
+
+use std::collections::{HashMap, VecDeque};
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use actix_web::cookie::{Cookie, SameSite};
+use actix_web::{post, web, HttpMessage, HttpRequest, HttpResponse};
+use ev3dev_lang_rust::Screen;
+use rand::Rng;
+use serde::Deserialize;
+
+/// The name of the cookie that holds the client's session token.
+const SESSION_COOKIE: &str = "ev3_session";
+
+/// How many digits long pairing codes are.
+const CODE_DIGITS: usize = 6;
+
+/// How many wrong codes a client address can submit before it gets locked out.
+const CODE_ATTEMPTS_MAX: u32 = 5;
+
+/// How long a client address is first locked out for, once it has submitted [CODE_ATTEMPTS_MAX]
+///   wrong codes. Each further wrong code doubles this, up to [LOCKOUT_MAX].
+const LOCKOUT_BASE: Duration = Duration::from_secs(30);
+
+/// The longest that a client address will be locked out for.
+const LOCKOUT_MAX: Duration = Duration::from_secs(60 * 60);
+
+/// How many client addresses' wrong codes are tracked separately. Beyond this, any new addresses
+///   all share a single [Lockout], so that the tracking can't be used to exhaust memory.
+const PEERS_MAX: usize = 64;
+
+/// How many sessions are kept at once. Beyond this, pairing another client ends the oldest
+///   session, which will then have to pair again.
+const SESSIONS_MAX: usize = 32;
+
+/// Tracks the pairing code and all of the paired clients' sessions.
+pub struct Pairing {
+    state: Mutex<PairingState>,
+}
+
+struct PairingState {
+    code: String,
+    lockouts: HashMap<IpAddr, Lockout>,
+    /// The [Lockout] shared by all of the client addresses past [PEERS_MAX], and any whose
+    ///   address is unknown.
+    overflow_lockout: Lockout,
+    /// The session tokens, from oldest to newest.
+    sessions: VecDeque<String>,
+}
+
+/// Tracks the wrong codes submitted from a client address.
+#[derive(Default)]
+struct Lockout {
+    failed_attempts: u32,
+    locked_until: Option<Instant>,
+}
+
+impl Lockout {
+    /// Returns `true` if this client address isn't currently allowed to submit codes.
+    fn is_locked(&self) -> bool {
+        match self.locked_until {
+            Some(locked_until) => Instant::now() < locked_until,
+            None => false,
+        }
+    }
+
+    /// Records a wrong code, locking the client address out once it has had too many.
+    fn record_failure(&mut self) {
+        self.failed_attempts = self.failed_attempts.saturating_add(1);
+        if self.failed_attempts < CODE_ATTEMPTS_MAX {
+            return;
+        }
+
+        let doublings = (self.failed_attempts - CODE_ATTEMPTS_MAX).min(16);
+        let lockout = LOCKOUT_BASE
+            .checked_mul(1 << doublings)
+            .map_or(LOCKOUT_MAX, |lockout| lockout.min(LOCKOUT_MAX));
+        println!(
+            "Pairing: too many wrong codes; locking the client out for {}s.",
+            lockout.as_secs()
+        );
+        self.locked_until = Some(Instant::now() + lockout);
+    }
+}
+
+impl PairingState {
+    /// Returns the [Lockout] for the specified client address.
+    ///
+    /// Parameters:
+    /// * `peer`: the client's address, if known
+    fn lockout(&mut self, peer: Option<IpAddr>) -> &mut Lockout {
+        let peer = match peer {
+            Some(peer) if self.lockouts.contains_key(&peer) || self.lockouts.len() < PEERS_MAX => {
+                peer
+            }
+            _ => return &mut self.overflow_lockout,
+        };
+
+        self.lockouts.entry(peer).or_default()
+    }
+}
+
+impl Pairing {
+    /// Constructs a new [Pairing], generating and showing the first pairing code.
+    pub fn new() -> Pairing {
+        Pairing {
+            state: Mutex::new(PairingState {
+                code: new_code(),
+                lockouts: HashMap::new(),
+                overflow_lockout: Lockout::default(),
+                sessions: VecDeque::new(),
+            }),
+        }
+    }
+
+    /// Returns `true` if the request has a valid session cookie.
+    pub fn is_paired(&self, request: &HttpRequest) -> bool {
+        match request.cookie(SESSION_COOKIE) {
+            Some(cookie) => self
+                .lock()
+                .sessions
+                .iter()
+                .any(|session| constant_time_eq(session, cookie.value())),
+            None => false,
+        }
+    }
+
+    /// Returns an error unless the request has a valid session cookie.
+    pub fn require_session(&self, request: &HttpRequest) -> actix_web::Result<()> {
+        if self.is_paired(request) {
+            Ok(())
+        } else {
+            Err(actix_web::error::ErrorUnauthorized(
+                "Not paired: enter the code shown on the robot's screen first.",
+            ))
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, PairingState> {
+        // The state is always left consistent, so it's fine to keep going if a holder panicked.
+        self.state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl Default for Pairing {
+    fn default() -> Pairing {
+        Pairing::new()
+    }
+}
+
+/// Models the form parameters for [pair()].
+#[derive(Deserialize)]
+pub struct PairData {
+    code: String,
+}
+
+/// This API endpoint is called when the user submits the pairing form.
+/// If the code matches the one on the robot's screen, the client is given a session cookie.
+/// Either way, it's sent back to the home page.
+///
+/// While the client's address is locked out for having sent too many wrong codes, the code isn't
+///   even checked.
+///
+/// Parameters:
+/// * `request`: the HTTP request, for the client's address
+/// * `pairing`: the [Pairing] instance managed/shared by the application
+/// * `pair_data`: the [PairData] parameters specified in the API call
+#[post("/pair")]
+pub async fn pair(
+    request: HttpRequest,
+    pairing: web::Data<Pairing>,
+    pair_data: web::Form<PairData>,
+) -> actix_web::Result<HttpResponse> {
+    let peer = request.peer_addr().map(|addr| addr.ip());
+    let mut state = pairing.lock();
+    if state.lockout(peer).is_locked() {
+        return Ok(HttpResponse::Found()
+            .header(actix_web::http::header::LOCATION, "/?locked_out")
+            .finish()
+            .into_body());
+    }
+
+    if !constant_time_eq(pair_data.code.trim(), &state.code) {
+        state.lockout(peer).record_failure();
+
+        return Ok(HttpResponse::Found()
+            .header(actix_web::http::header::LOCATION, "/?wrong_code")
+            .finish()
+            .into_body());
+    }
+
+    let token = format!("{:032x}", rand::random::<u128>());
+    if state.sessions.len() >= SESSIONS_MAX {
+        println!("Pairing: too many sessions; ending the oldest one.");
+        state.sessions.pop_front();
+    }
+    state.sessions.push_back(token.clone());
+    if let Some(peer) = peer {
+        state.lockouts.remove(&peer);
+    }
+    println!("Pairing: client paired.");
+
+    Ok(HttpResponse::Found()
+        .header(actix_web::http::header::LOCATION, "/")
+        .cookie(
+            Cookie::build(SESSION_COOKIE, token)
+                .path("/")
+                .http_only(true)
+                .same_site(SameSite::Strict)
+                .finish(),
+        )
+        .finish()
+        .into_body())
+}
+
+/// Compares two strings in constant time (for strings of the same length), so that how long a
+///   comparison takes doesn't give away how much of a code or session token was right.
+fn constant_time_eq(a: &str, b: &str) -> bool {
+    let (a, b) = (a.as_bytes(), b.as_bytes());
+    if a.len() != b.len() {
+        return false;
+    }
+
+    a.iter().zip(b).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+/// Generates a new random pairing code, and shows it on the brick's screen and stdout.
+fn new_code() -> String {
+    let mut rng = rand::thread_rng();
+    let code: String = (0..CODE_DIGITS)
+        .map(|_| char::from(b'0' + rng.gen_range(0..10)))
+        .collect();
+
+    println!("Pairing: code is {}.", code);
+    if let Err(err) = show_code(&code) {
+        eprintln!("Pairing: unable to show code on screen: {:?}", err);
+    }
+
+    code
+}
+
+/// The segments of a seven-segment display that are lit for each digit, as bits `0` through `6`:
+///   top, upper right, lower right, bottom, lower left, upper left, and middle.
+const DIGIT_SEGMENTS: [u8; 10] = [
+    0b011_1111, 0b000_0110, 0b101_1011, 0b100_1111, 0b110_0110, 0b110_1101, 0b111_1101, 0b000_0111,
+    0b111_1111, 0b110_1111,
+];
+
+/// The width of each digit drawn on the screen, in pixels.
+const DIGIT_WIDTH: u32 = 22;
+
+/// The height of each digit drawn on the screen, in pixels.
+const DIGIT_HEIGHT: u32 = 40;
+
+/// The thickness of each digit's segments, in pixels.
+const SEGMENT_THICKNESS: u32 = 4;
+
+/// The gap between digits, in pixels.
+const DIGIT_GAP: u32 = 6;
+
+/// Draws the specified code across the middle of the brick's screen, as big seven-segment digits,
+///   which avoids needing a font.
+fn show_code(code: &str) -> ev3dev_lang_rust::Ev3Result<()> {
+    let mut screen = Screen::new()?;
+    let (width, height) = (screen.xres, screen.yres);
+    for pixel in screen.image.pixels_mut() {
+        *pixel = [255, 255, 255].into();
+    }
+
+    let code_width = code.len() as u32 * (DIGIT_WIDTH + DIGIT_GAP) - DIGIT_GAP;
+    let top = height.saturating_sub(DIGIT_HEIGHT) / 2;
+    for (index, digit) in code.chars().filter_map(|c| c.to_digit(10)).enumerate() {
+        let left = width.saturating_sub(code_width) / 2 + index as u32 * (DIGIT_WIDTH + DIGIT_GAP);
+        let (t, w, h) = (SEGMENT_THICKNESS, DIGIT_WIDTH, DIGIT_HEIGHT);
+        let middle = (h - t) / 2;
+
+        // Each segment is a (left, top, width, height) rectangle, relative to the digit.
+        let segments = [
+            (0, 0, w, t),
+            (w - t, 0, t, middle + t),
+            (w - t, middle, t, h - middle),
+            (0, h - t, w, t),
+            (0, middle, t, h - middle),
+            (0, 0, t, middle + t),
+            (0, middle, w, t),
+        ];
+        for (segment, (x, y, segment_width, segment_height)) in segments.iter().enumerate() {
+            if DIGIT_SEGMENTS[digit as usize] & (1 << segment) == 0 {
+                continue;
+            }
+            for dx in 0..*segment_width {
+                for dy in 0..*segment_height {
+                    let (px, py) = (left + x + dx, top + y + dy);
+                    if px < width && py < height {
+                        screen.image.put_pixel(px, py, [0, 0, 0].into());
+                    }
+                }
+            }
+        }
+    }
+
+    screen.update();
+    Ok(())
+}
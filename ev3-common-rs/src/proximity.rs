@@ -0,0 +1,103 @@
+//! Abstracts over the different sensors that can be used to measure the distance to obstacles
+//!   in front of the robot, so that whichever one is plugged in can be used.
+
+use ev3dev_lang_rust::sensors::{InfraredSensor, Sensor, UltrasonicSensor};
+use ev3dev_lang_rust::Ev3Result;
+
+use crate::env::env_var_or;
+
+/// The default number of centimeters that each percent of an [InfraredSensor]'s proximity reading
+///   corresponds to.
+/// The sensor's readings aren't calibrated, and vary with the color and shape of the obstacle,
+///   but 100% is roughly 70 cm.
+const IR_CM_PER_PERCENT_DEFAULT: f32 = 0.7;
+
+/// A sensor that can measure the distance to whatever is in front of the robot.
+pub trait ProximitySensor {
+    /// A short name for this sensor, used in log output.
+    fn name(&self) -> &'static str;
+
+    /// Returns the distance to the nearest obstacle, in centimeters.
+    fn get_distance_cm(&self) -> Ev3Result<f32>;
+
+    /// Returns `true` if this is the [InfraredSensor], which can't measure proximity while it's
+    ///   in any other mode, e.g. seeking the beacon.
+    fn is_infrared(&self) -> bool;
+}
+
+impl ProximitySensor for UltrasonicSensor {
+    fn name(&self) -> &'static str {
+        "ultrasonic"
+    }
+
+    fn get_distance_cm(&self) -> Ev3Result<f32> {
+        self.get_distance_centimeters()
+    }
+
+    fn is_infrared(&self) -> bool {
+        false
+    }
+}
+
+/// An [InfraredSensor] in proximity mode, which only reports a (rough) percentage rather than an
+///   actual distance, and so needs a percent-to-cm mapping.
+pub struct InfraredProximitySensor {
+    sensor: InfraredSensor,
+    cm_per_percent: f32,
+}
+
+impl InfraredProximitySensor {
+    /// Constructs a new [InfraredProximitySensor], switching the sensor into proximity mode.
+    ///
+    /// Parameters:
+    /// * `sensor`: the [InfraredSensor] to use
+    /// * `cm_per_percent`: the number of centimeters that each percent of proximity corresponds to
+    pub fn new(sensor: InfraredSensor, cm_per_percent: f32) -> Ev3Result<InfraredProximitySensor> {
+        sensor.set_mode_ir_prox()?;
+
+        Ok(InfraredProximitySensor {
+            sensor,
+            cm_per_percent,
+        })
+    }
+}
+
+impl ProximitySensor for InfraredProximitySensor {
+    fn name(&self) -> &'static str {
+        "infrared"
+    }
+
+    fn get_distance_cm(&self) -> Ev3Result<f32> {
+        Ok(self.sensor.get_value0()? as f32 * self.cm_per_percent)
+    }
+
+    fn is_infrared(&self) -> bool {
+        true
+    }
+}
+
+/// Finds whichever [ProximitySensor] is plugged in, preferring the ultrasonic sensor if both are.
+/// Returns an error if the infrared sensor's percent-to-cm mapping is set, but isn't a positive
+///   number.
+///
+/// Parameters:
+/// * `cm_per_percent_var`: the environment variable that can be used to override the infrared
+///   sensor's percent-to-cm mapping, e.g. `REMOTE_CONTROL_IR_CM_PER_PERCENT`
+pub fn find_proximity_sensor(
+    cm_per_percent_var: &str,
+) -> Ev3Result<Box<dyn ProximitySensor + Send>> {
+    let sensor: Box<dyn ProximitySensor + Send> = match UltrasonicSensor::find() {
+        Ok(sensor) => Box::new(sensor),
+        Err(_) => {
+            let cm_per_percent =
+                env_var_or(cm_per_percent_var, IR_CM_PER_PERCENT_DEFAULT, |v| *v > 0.0)?;
+            Box::new(InfraredProximitySensor::new(
+                InfraredSensor::find()?,
+                cm_per_percent,
+            )?)
+        }
+    };
+
+    println!("Proximity: using {} sensor.", sensor.name());
+    Ok(sensor)
+}
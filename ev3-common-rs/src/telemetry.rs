@@ -0,0 +1,115 @@
+//! Reads telemetry from the motors and the (optional) sensors, for display.
+
+use ev3dev_lang_rust::motors::LargeMotor;
+use ev3dev_lang_rust::sensors::TouchSensor;
+use ev3dev_lang_rust::{Ev3Error, Ev3Result, PowerSupply};
+use serde::Serialize;
+
+use crate::cliff::CliffDetector;
+use crate::proximity::{find_proximity_sensor, ProximitySensor};
+
+/// The sensors that may or may not be plugged in, none of which are required for driving.
+pub struct Sensors {
+    pub cliff_detector: Option<CliffDetector>,
+    pub proximity_sensor: Option<Box<dyn ProximitySensor + Send>>,
+    pub touch_sensor: Option<TouchSensor>,
+    pub power_supply: Option<PowerSupply>,
+}
+
+impl Sensors {
+    /// Finds whichever of the sensors are plugged in.
+    ///
+    /// Parameters:
+    /// * `ir_cm_per_percent_var`: the environment variable that can be used to override the
+    ///   infrared sensor's percent-to-cm mapping (see [find_proximity_sensor])
+    pub fn find(ir_cm_per_percent_var: &str) -> Sensors {
+        let proximity_sensor = match find_proximity_sensor(ir_cm_per_percent_var) {
+            Ok(proximity_sensor) => Some(proximity_sensor),
+            Err(Ev3Error::InternalError { msg }) => {
+                eprintln!("Proximity: {}; disabled.", msg);
+                None
+            }
+            Err(_) => {
+                println!("Proximity: no ultrasonic or infrared sensor found; disabled.");
+                None
+            }
+        };
+
+        Sensors {
+            cliff_detector: CliffDetector::find(),
+            proximity_sensor,
+            touch_sensor: TouchSensor::find().ok(),
+            power_supply: PowerSupply::new().ok(),
+        }
+    }
+
+    /// Reads all of the sensors that are plugged in.
+    pub fn read(&self) -> Ev3Result<SensorTelemetry> {
+        // Only read the color sensor once, and leave the cliff detector's baseline to the
+        //   monitors that actually act on cliffs.
+        let (reflected_light, cliff) = match &self.cliff_detector {
+            Some(cliff_detector) => {
+                let reflected = cliff_detector.get_reflected()?;
+                (
+                    Some(reflected),
+                    Some(cliff_detector.is_cliff_reading(reflected)),
+                )
+            }
+            None => (None, None),
+        };
+
+        Ok(SensorTelemetry {
+            reflected_light,
+            cliff,
+            distance_cm: match &self.proximity_sensor {
+                Some(proximity_sensor) => Some(proximity_sensor.get_distance_cm()?),
+                None => None,
+            },
+            touch_pressed: match &self.touch_sensor {
+                Some(touch_sensor) => Some(touch_sensor.get_pressed_state()?),
+                None => None,
+            },
+            battery_volts: match &self.power_supply {
+                Some(power_supply) => Some(power_supply.get_voltage_now()? as f32 / 1_000_000.0),
+                None => None,
+            },
+        })
+    }
+}
+
+/// The actual state of a motor, as reported by the motor itself.
+#[derive(Clone, Serialize)]
+pub struct MotorTelemetry {
+    /// The motor's current speed, in tacho counts per second.
+    pub speed: i32,
+    /// The motor's current position, in tacho counts.
+    pub position: i32,
+    /// The motor's state flags, e.g. `running` or `stalled`.
+    pub state: Vec<String>,
+}
+
+impl MotorTelemetry {
+    /// Reads the current state of the specified motor.
+    pub fn read(motor: &LargeMotor) -> Ev3Result<MotorTelemetry> {
+        Ok(MotorTelemetry {
+            speed: motor.get_speed()?,
+            position: motor.get_position()?,
+            state: motor.get_state()?,
+        })
+    }
+}
+
+/// The current readings of the [Sensors], each of which is `None` if that sensor isn't plugged in.
+#[derive(Clone, Serialize)]
+pub struct SensorTelemetry {
+    /// The cliff detector's reflected light reading, as a percentage.
+    pub reflected_light: Option<f32>,
+    /// Whether or not a cliff is detected.
+    pub cliff: Option<bool>,
+    /// The distance to the nearest obstacle in front of the robot, in centimeters.
+    pub distance_cm: Option<f32>,
+    /// Whether or not the touch sensor is pressed.
+    pub touch_pressed: Option<bool>,
+    /// The battery's current voltage.
+    pub battery_volts: Option<f32>,
+}
@@ -0,0 +1,336 @@
+//! Runs parsed motion [Script]s on the robot.
+
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use ev3_common::motors::LargeMotorSet;
+use ev3_common::moves::{cm_to_wheel_degrees, turn_to_wheel_degrees};
+use ev3dev_lang_rust::motors::LargeMotor;
+use ev3dev_lang_rust::sensors::{TouchSensor, UltrasonicSensor};
+use ev3dev_lang_rust::{sound, Ev3Button, Ev3Error};
+
+use crate::parser::{
+    Amount, Capabilities, Condition, Direction, Script, Statement, StatementKind, TurnDirection,
+};
+
+/// The absolute motor speed (in tacho counts per second) that a `speed 100` corresponds to.
+const SPEED_MAX_ABSOLUTE: f32 = 900.0;
+
+/// The `speed` used by scripts that don't specify one.
+const SPEED_DEFAULT: u8 = 50;
+
+/// How often to re-check the sensors and buttons while a motion is running.
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+/// The EV3 devices that scripts can use.
+pub struct Devices {
+    pub motors: LargeMotorSet,
+    pub touch_sensor: Option<TouchSensor>,
+    pub ultrasonic_sensor: Option<UltrasonicSensor>,
+    pub buttons: Ev3Button,
+}
+
+impl Devices {
+    /// Returns the [Capabilities] that scripts will be validated against.
+    pub fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            touch_sensor: self.touch_sensor.is_some(),
+            ultrasonic_sensor: self.ultrasonic_sensor.is_some(),
+        }
+    }
+}
+
+/// The ways in which running a [Script] can fail.
+#[derive(Debug)]
+pub enum RunError {
+    /// One of the brick's buttons was pressed while the script was running.
+    Aborted { line: usize },
+    /// A motion didn't complete within the expected amount of time, e.g. because the robot got
+    ///   stuck.
+    Timeout { line: usize },
+    /// An error was returned by one of the EV3 devices.
+    Ev3 { line: usize, cause: Ev3Error },
+}
+
+impl fmt::Display for RunError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RunError::Aborted { line } => write!(f, "line {}: aborted by button press", line),
+            RunError::Timeout { line } => {
+                write!(f, "line {}: motion did not complete in time", line)
+            }
+            RunError::Ev3 { line, cause } => write!(f, "line {}: EV3 error: {:?}", line, cause),
+        }
+    }
+}
+
+/// Runs [Script]s against the specified [Devices].
+pub struct Interpreter<'a> {
+    devices: &'a Devices,
+    speed: u8,
+}
+
+impl<'a> Interpreter<'a> {
+    /// Constructs a new [Interpreter].
+    ///
+    /// Parameters:
+    /// * `devices`: the [Devices] to run scripts against
+    pub fn new(devices: &'a Devices) -> Interpreter<'a> {
+        Interpreter {
+            devices,
+            speed: SPEED_DEFAULT,
+        }
+    }
+
+    /// Runs the specified [Script], which should already have been validated.
+    ///
+    /// Parameters:
+    /// * `script`: the [Script] to run
+    pub fn run(&mut self, script: &Script) -> Result<(), RunError> {
+        self.run_statements(&script.statements)
+    }
+
+    fn run_statements(&mut self, statements: &[Statement]) -> Result<(), RunError> {
+        for statement in statements {
+            self.run_statement(statement)?;
+        }
+
+        Ok(())
+    }
+
+    fn run_statement(&mut self, statement: &Statement) -> Result<(), RunError> {
+        let line = statement.line;
+        let ev3 = |cause| RunError::Ev3 { line, cause };
+        println!("Motion script: line {}: {:?}", line, statement.kind);
+
+        match &statement.kind {
+            StatementKind::Drive {
+                direction,
+                amount: Some(amount),
+            } => {
+                let sign = match direction {
+                    Direction::Forward => 1.0,
+                    Direction::Backward => -1.0,
+                };
+                match amount {
+                    Amount::Centimeters(cm) => {
+                        let degrees = sign * cm_to_wheel_degrees(*cm);
+                        self.run_to_rel_degrees(line, degrees, degrees)?;
+                    }
+                    Amount::WheelDegrees(degrees) => {
+                        self.run_to_rel_degrees(line, sign * degrees, sign * degrees)?;
+                    }
+                    Amount::Time(duration) => {
+                        let speed_sp = (sign * self.speed_sp()) as i32;
+                        self.devices.motors.set_speed_sp(speed_sp).map_err(ev3)?;
+                        self.devices
+                            .motors
+                            .run_timed(Some(*duration))
+                            .map_err(ev3)?;
+                        self.wait_for_motion(line, *duration)?;
+                    }
+                }
+            }
+            StatementKind::Turn {
+                direction,
+                degrees: Some(degrees),
+            } => {
+                let wheel_degrees = turn_to_wheel_degrees(*degrees);
+                match direction {
+                    TurnDirection::Left => {
+                        self.run_to_rel_degrees(line, -wheel_degrees, wheel_degrees)?
+                    }
+                    TurnDirection::Right => {
+                        self.run_to_rel_degrees(line, wheel_degrees, -wheel_degrees)?
+                    }
+                }
+            }
+            StatementKind::Drive { amount: None, .. }
+            | StatementKind::Turn { degrees: None, .. } => {
+                // The validator only allows these inside of an `until`, which runs them itself.
+                unreachable!("unbounded motion outside of 'until' on line {}", line);
+            }
+            StatementKind::Speed(speed) => self.speed = *speed,
+            StatementKind::Wait(duration) => {
+                let start = Instant::now();
+                while start.elapsed() < *duration {
+                    self.check_abort(line)?;
+                    std::thread::sleep(POLL_INTERVAL);
+                }
+            }
+            StatementKind::Beep => {
+                sound::beep()
+                    .map_err(ev3)?
+                    .wait()
+                    .map_err(|cause| ev3(cause.into()))?;
+            }
+            StatementKind::Until { condition, body } => {
+                self.run_until(line, *condition, body)?;
+            }
+            StatementKind::Repeat { count, body } => {
+                for _ in 0..*count {
+                    self.run_statements(body)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Runs the specified unbounded motion until the specified [Condition] holds.
+    fn run_until(
+        &mut self,
+        line: usize,
+        condition: Condition,
+        body: &Statement,
+    ) -> Result<(), RunError> {
+        let ev3 = |cause| RunError::Ev3 { line, cause };
+        let speed_sp = self.speed_sp() as i32;
+        let speeds = match &body.kind {
+            StatementKind::Drive {
+                direction: Direction::Forward,
+                ..
+            } => vec![speed_sp, speed_sp],
+            StatementKind::Drive {
+                direction: Direction::Backward,
+                ..
+            } => vec![-speed_sp, -speed_sp],
+            StatementKind::Turn {
+                direction: TurnDirection::Left,
+                ..
+            } => vec![-speed_sp, speed_sp],
+            StatementKind::Turn {
+                direction: TurnDirection::Right,
+                ..
+            } => vec![speed_sp, -speed_sp],
+            _ => unreachable!("bounded motion inside of 'until' on line {}", line),
+        };
+
+        for (motor, speed_sp) in self.devices.motors.motors.iter().zip(speeds) {
+            motor.set_speed_sp(speed_sp).map_err(ev3)?;
+        }
+        self.devices.motors.run_forever().map_err(ev3)?;
+
+        let result = loop {
+            match self.check_condition(condition) {
+                Ok(true) => break Ok(()),
+                Ok(false) => {}
+                Err(cause) => break Err(ev3(cause)),
+            }
+            if let Err(err) = self.check_abort(line) {
+                break Err(err);
+            }
+            std::thread::sleep(POLL_INTERVAL);
+        };
+
+        self.devices.motors.set_stop_action("brake").map_err(ev3)?;
+        self.devices.motors.stop().map_err(ev3)?;
+
+        // Don't let the button press that ended an `until button` abort the next command.
+        if let (Ok(()), Condition::Button) = (&result, condition) {
+            while !self.devices.buttons.get_pressed_buttons().is_empty() {
+                std::thread::sleep(POLL_INTERVAL);
+                self.devices.buttons.process();
+            }
+        }
+
+        result
+    }
+
+    /// Rotates each wheel by the specified number of degrees and waits for them to get there.
+    fn run_to_rel_degrees(
+        &self,
+        line: usize,
+        left_degrees: f32,
+        right_degrees: f32,
+    ) -> Result<(), RunError> {
+        let ev3 = |cause| RunError::Ev3 { line, cause };
+        let speed_sp = self.speed_sp();
+
+        for (motor, degrees) in self
+            .devices
+            .motors
+            .motors
+            .iter()
+            .zip(vec![left_degrees, right_degrees])
+        {
+            let counts_per_degree = motor.get_count_per_rot().map_err(ev3)? as f32 / 360.0;
+            motor.set_speed_sp(speed_sp as i32).map_err(ev3)?;
+            motor.set_stop_action("hold").map_err(ev3)?;
+            motor
+                .run_to_rel_pos(Some((degrees * counts_per_degree).round() as i32))
+                .map_err(ev3)?;
+        }
+
+        // Allow for half again as long as the move ought to take, plus a bit for acceleration.
+        let degrees_max = left_degrees.abs().max(right_degrees.abs());
+        let expected = Duration::from_secs_f32(degrees_max / speed_sp);
+        self.wait_for_motion(line, expected)
+    }
+
+    /// Waits for the motors to finish running, aborting if a button is pressed, or timing out
+    ///   if they take much longer than the specified expected duration.
+    fn wait_for_motion(&self, line: usize, expected: Duration) -> Result<(), RunError> {
+        let timeout = expected.mul_f32(1.5) + Duration::from_secs(2);
+        let motors = &self.devices.motors;
+        motors.wait_until(LargeMotor::STATE_RUNNING, Some(POLL_INTERVAL * 5));
+
+        let start = Instant::now();
+        loop {
+            if let Err(err) = self.check_abort(line) {
+                motors
+                    .stop()
+                    .map_err(|cause| RunError::Ev3 { line, cause })?;
+                return Err(err);
+            }
+            if motors.wait_until_not_moving(Some(POLL_INTERVAL)) {
+                return Ok(());
+            }
+            if start.elapsed() > timeout {
+                motors
+                    .stop()
+                    .map_err(|cause| RunError::Ev3 { line, cause })?;
+                return Err(RunError::Timeout { line });
+            }
+        }
+    }
+
+    /// Returns an error if any of the brick's buttons have been pressed.
+    fn check_abort(&self, line: usize) -> Result<(), RunError> {
+        let buttons = &self.devices.buttons;
+        buttons.process();
+        if buttons.get_pressed_buttons().is_empty() {
+            Ok(())
+        } else {
+            Err(RunError::Aborted { line })
+        }
+    }
+
+    fn check_condition(&self, condition: Condition) -> Result<bool, Ev3Error> {
+        Ok(match condition {
+            Condition::Touch => self
+                .devices
+                .touch_sensor
+                .as_ref()
+                .expect("touch sensor missing")
+                .get_pressed_state()?,
+            Condition::Button => {
+                self.devices.buttons.process();
+                !self.devices.buttons.get_pressed_buttons().is_empty()
+            }
+            Condition::DistanceBelow(cm) => {
+                self.devices
+                    .ultrasonic_sensor
+                    .as_ref()
+                    .expect("ultrasonic sensor missing")
+                    .get_distance_centimeters()?
+                    < cm
+            }
+        })
+    }
+
+    /// Returns the absolute motor speed for the current `speed` setting.
+    fn speed_sp(&self) -> f32 {
+        SPEED_MAX_ABSOLUTE * (self.speed as f32) / 100.0
+    }
+}
@@ -0,0 +1,107 @@
+//! Runs a motion script, which describes a driving routine in a small text language,
+//!   on the [ev3dev platform](https://www.ev3dev.org/).
+//!
+//! Usage:
+//!
+//! ```text
+//! motion-script-rs [--check] <script-file>
+//! ```
+//!
+//! With `--check`, the script is only parsed and checked for errors, which doesn't require an
+//!   EV3 brick, so it can be done on any computer.
+//!
+//! The script language itself is implemented in the [parser] and [interpreter] modules.
+
+mod interpreter;
+mod parser;
+
+use std::time::Duration;
+
+use anyhow::{anyhow, Context, Result};
+use ev3_common::motors::LargeMotorSet;
+use ev3dev_lang_rust::sensors::{TouchSensor, UltrasonicSensor};
+use ev3dev_lang_rust::{Ev3Button, Ev3Result};
+
+use interpreter::{Devices, Interpreter};
+use parser::{Capabilities, ScriptError};
+
+fn main() -> Result<()> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let (check_only, script_path) = match args.as_slice() {
+        [flag, path] if flag == "--check" => (true, path),
+        [path] => (false, path),
+        _ => return Err(anyhow!("Usage: motion-script-rs [--check] <script-file>")),
+    };
+
+    let source = std::fs::read_to_string(script_path)
+        .with_context(|| format!("Unable to read motion script '{}'.", script_path))?;
+    let script = parser::parse(&source).map_err(|errors| report_errors(script_path, &errors))?;
+
+    if check_only {
+        // Without a brick to check against, assume that all of the sensors are available.
+        let capabilities = Capabilities {
+            touch_sensor: true,
+            ultrasonic_sensor: true,
+        };
+        let errors = parser::validate(&script, &capabilities);
+        if !errors.is_empty() {
+            return Err(report_errors(script_path, &errors));
+        }
+
+        println!("Motion script '{}': OK.", script_path);
+        return Ok(());
+    }
+
+    let devices = find_devices().map_err(|err| anyhow!("Unable to find EV3 devices: {:?}", err))?;
+    let errors = parser::validate(&script, &devices.capabilities());
+    if !errors.is_empty() {
+        return Err(report_errors(script_path, &errors));
+    }
+
+    println!(
+        "Motion script '{}': running. Press any brick button to abort.",
+        script_path
+    );
+    let result = Interpreter::new(&devices).run(&script);
+
+    // Whatever happened, make sure the motors end up stopped.
+    devices
+        .motors
+        .set_stop_action("brake")
+        .and_then(|_| devices.motors.stop())
+        .map_err(|err| anyhow!("Unable to stop motors: {:?}", err))?;
+    devices
+        .motors
+        .wait_until_not_moving(Some(Duration::from_secs(2)));
+
+    match result {
+        Ok(()) => {
+            println!("Motion script '{}': done.", script_path);
+            Ok(())
+        }
+        Err(err) => Err(anyhow!("Motion script '{}': {}", script_path, err)),
+    }
+}
+
+/// Prints the specified [ScriptError]s and returns an error summarizing them.
+fn report_errors(script_path: &str, errors: &[ScriptError]) -> anyhow::Error {
+    for error in errors {
+        eprintln!("{}: {}", script_path, error);
+    }
+
+    anyhow!(
+        "Motion script '{}' has {} error(s).",
+        script_path,
+        errors.len()
+    )
+}
+
+/// Finds the motors, along with whichever of the optional sensors are plugged in.
+fn find_devices() -> Ev3Result<Devices> {
+    Ok(Devices {
+        motors: LargeMotorSet::new()?,
+        touch_sensor: TouchSensor::find().ok(),
+        ultrasonic_sensor: UltrasonicSensor::find().ok(),
+        buttons: Ev3Button::new()?,
+    })
+}
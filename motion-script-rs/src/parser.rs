@@ -0,0 +1,704 @@
+//! Parses and validates motion scripts.
+//!
+//! A motion script is a plain text file with one or more commands per line,
+//!   separated by `;`, where anything after a `#` is a comment:
+//!
+//! ```text
+//! # Drive a square.
+//! speed 50
+//! repeat 4:
+//!   forward 30cm; turn right 90
+//! end
+//! until touch: forward
+//! beep
+//! ```
+//!
+//! See the project's `README.md` for the full list of commands.
+
+use std::fmt;
+use std::time::Duration;
+
+/// The largest allowed number of iterations for a single `repeat` block.
+const REPEAT_COUNT_MAX: u32 = 1000;
+
+/// The longest allowed time for a single drive or `wait`, in seconds.
+const TIME_SECS_MAX: f32 = 3600.0;
+
+/// The farthest allowed distance for a single drive, in centimeters.
+const DRIVE_CM_MAX: f32 = 10_000.0;
+
+/// The farthest allowed wheel rotation for a single drive, in degrees.
+const DRIVE_WHEEL_DEGREES_MAX: f32 = 360_000.0;
+
+/// A parsed (and possibly validated) motion script.
+#[derive(Debug)]
+pub struct Script {
+    pub statements: Vec<Statement>,
+}
+
+/// A single command in a [Script], along with the line it came from.
+#[derive(Debug)]
+pub struct Statement {
+    pub line: usize,
+    pub kind: StatementKind,
+}
+
+/// The different commands that are supported in a [Script].
+#[derive(Debug)]
+pub enum StatementKind {
+    /// Drives straight forwards or backwards, for the specified [Amount], or without end if
+    ///   inside of an `until`.
+    Drive {
+        direction: Direction,
+        amount: Option<Amount>,
+    },
+    /// Turns the robot in place, by the specified number of degrees, or without end if inside of
+    ///   an `until`.
+    Turn {
+        direction: TurnDirection,
+        degrees: Option<f32>,
+    },
+    /// Sets the speed (as a percentage of the max) for all following motion commands.
+    Speed(u8),
+    /// Holds still for the specified amount of time.
+    Wait(Duration),
+    /// Plays a short beep.
+    Beep,
+    /// Runs the (unbounded) body command until the [Condition] holds.
+    Until {
+        condition: Condition,
+        body: Box<Statement>,
+    },
+    /// Runs the body commands the specified number of times.
+    Repeat { count: u32, body: Vec<Statement> },
+}
+
+/// The directions that the robot can drive in.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Direction {
+    Forward,
+    Backward,
+}
+
+/// The directions that the robot can turn in.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum TurnDirection {
+    Left,
+    Right,
+}
+
+/// How far to drive for, in the various supported units.
+#[derive(Clone, Copy, Debug)]
+pub enum Amount {
+    /// A distance over the ground, e.g. `30cm` or `300mm`.
+    Centimeters(f32),
+    /// A rotation of the wheels, e.g. `360deg`.
+    WheelDegrees(f32),
+    /// A length of time, e.g. `2s` or `500ms`.
+    Time(Duration),
+}
+
+/// The conditions that an `until` can wait for.
+#[derive(Clone, Copy, Debug)]
+pub enum Condition {
+    /// The touch sensor is pressed.
+    Touch,
+    /// Any of the brick's buttons is pressed.
+    Button,
+    /// The proximity sensor reads less than the specified number of centimeters.
+    DistanceBelow(f32),
+}
+
+/// The sensors (beyond the motors and the brick's buttons) that a [Script] may require.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Capabilities {
+    pub touch_sensor: bool,
+    pub ultrasonic_sensor: bool,
+}
+
+/// A problem found while parsing or validating a [Script].
+#[derive(Debug)]
+pub struct ScriptError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl ScriptError {
+    fn new<S: Into<String>>(line: usize, message: S) -> ScriptError {
+        ScriptError {
+            line,
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for ScriptError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+/// Parses the specified motion script source.
+///
+/// Returns the parsed [Script], or every [ScriptError] that was found (rather than just the
+///   first one), so that they can all be fixed in one go.
+///
+/// Parameters:
+/// * `source`: the text of the motion script to parse
+pub fn parse(source: &str) -> Result<Script, Vec<ScriptError>> {
+    let mut errors = Vec::new();
+
+    // Each open `repeat` block gets its own frame, with the script itself at the bottom.
+    let mut frames: Vec<(usize, u32, Vec<Statement>)> = vec![(0, 1, Vec::new())];
+
+    for (index, line) in source.lines().enumerate() {
+        let line_number = index + 1;
+        let line = match line.find('#') {
+            Some(comment_start) => &line[..comment_start],
+            None => line,
+        };
+
+        for command in line.split(';').map(str::trim).filter(|c| !c.is_empty()) {
+            let words: Vec<&str> = command.split_whitespace().collect();
+            match words.as_slice() {
+                ["repeat", ..] => match parse_repeat(line_number, command) {
+                    Ok(count) => frames.push((line_number, count, Vec::new())),
+                    Err(err) => errors.push(err),
+                },
+                ["end"] => {
+                    if frames.len() > 1 {
+                        let (repeat_line, count, body) = frames.pop().expect("frame missing");
+                        frames.last_mut().expect("frame missing").2.push(Statement {
+                            line: repeat_line,
+                            kind: StatementKind::Repeat { count, body },
+                        });
+                    } else {
+                        errors.push(ScriptError::new(
+                            line_number,
+                            "'end' without a matching 'repeat'",
+                        ));
+                    }
+                }
+                _ => match parse_statement(line_number, command) {
+                    Ok(statement) => frames.last_mut().expect("frame missing").2.push(statement),
+                    Err(err) => errors.push(err),
+                },
+            }
+        }
+    }
+
+    while frames.len() > 1 {
+        let (repeat_line, _, _) = frames.pop().expect("frame missing");
+        errors.push(ScriptError::new(
+            repeat_line,
+            "'repeat' is missing its closing 'end'",
+        ));
+    }
+
+    if errors.is_empty() {
+        Ok(Script {
+            statements: frames.pop().expect("frame missing").2,
+        })
+    } else {
+        Err(errors)
+    }
+}
+
+/// Checks the specified [Script] for problems that aren't syntax errors, e.g. values that are out
+///   of range or sensors that aren't plugged in.
+///
+/// Returns every [ScriptError] that was found, which will be empty if the script is valid.
+///
+/// Parameters:
+/// * `script`: the [Script] to validate
+/// * `capabilities`: the [Capabilities] of the robot that the script will be run on
+pub fn validate(script: &Script, capabilities: &Capabilities) -> Vec<ScriptError> {
+    let mut errors = Vec::new();
+    validate_statements(&script.statements, capabilities, &mut errors);
+    errors.sort_by_key(|err| err.line);
+
+    errors
+}
+
+fn validate_statements(
+    statements: &[Statement],
+    capabilities: &Capabilities,
+    errors: &mut Vec<ScriptError>,
+) {
+    for statement in statements {
+        let line = statement.line;
+        match &statement.kind {
+            StatementKind::Drive { amount: None, .. }
+            | StatementKind::Turn { degrees: None, .. } => {
+                errors.push(ScriptError::new(
+                    line,
+                    "motion without an amount is only allowed inside of 'until', e.g. \
+                     'until touch: forward'",
+                ));
+            }
+            StatementKind::Drive {
+                amount: Some(amount),
+                ..
+            } => {
+                let positive = match amount {
+                    Amount::Centimeters(value) | Amount::WheelDegrees(value) => *value > 0.0,
+                    Amount::Time(duration) => *duration > Duration::from_millis(0),
+                };
+                if !positive {
+                    errors.push(ScriptError::new(line, "drive amount must be more than 0"));
+                }
+                match amount {
+                    Amount::Centimeters(cm) if *cm > DRIVE_CM_MAX => {
+                        errors.push(ScriptError::new(
+                            line,
+                            format!("drive of {}cm must be at most {}cm", cm, DRIVE_CM_MAX),
+                        ));
+                    }
+                    Amount::WheelDegrees(degrees) if *degrees > DRIVE_WHEEL_DEGREES_MAX => {
+                        errors.push(ScriptError::new(
+                            line,
+                            format!(
+                                "drive of {}deg must be at most {}deg",
+                                degrees, DRIVE_WHEEL_DEGREES_MAX
+                            ),
+                        ));
+                    }
+                    _ => {}
+                }
+            }
+            StatementKind::Turn {
+                degrees: Some(degrees),
+                ..
+            } => {
+                if *degrees <= 0.0 || *degrees > 3600.0 {
+                    errors.push(ScriptError::new(
+                        line,
+                        format!("turn of {} degrees must be between 0 and 3600", degrees),
+                    ));
+                }
+            }
+            StatementKind::Speed(speed) => {
+                if *speed == 0 || *speed > 100 {
+                    errors.push(ScriptError::new(
+                        line,
+                        format!("speed of {} must be between 1 and 100", speed),
+                    ));
+                }
+            }
+            StatementKind::Wait(_) | StatementKind::Beep => {}
+            StatementKind::Until { condition, body } => {
+                match condition {
+                    Condition::Touch if !capabilities.touch_sensor => {
+                        errors.push(ScriptError::new(
+                            line,
+                            "'until touch' requires a touch sensor, but none is plugged in",
+                        ));
+                    }
+                    Condition::DistanceBelow(_) if !capabilities.ultrasonic_sensor => {
+                        errors.push(ScriptError::new(
+                            line,
+                            "'until distance' requires an ultrasonic sensor, but none is plugged in",
+                        ));
+                    }
+                    Condition::DistanceBelow(cm) if *cm <= 0.0 => {
+                        errors.push(ScriptError::new(line, "distance must be more than 0cm"));
+                    }
+                    _ => {}
+                }
+                match &body.kind {
+                    StatementKind::Drive { amount: None, .. }
+                    | StatementKind::Turn { degrees: None, .. } => {}
+                    _ => errors.push(ScriptError::new(
+                        line,
+                        "the command after 'until ...:' must be 'forward', 'backward', \
+                         'turn left', or 'turn right', without an amount",
+                    )),
+                }
+            }
+            StatementKind::Repeat { count, body } => {
+                if *count == 0 || *count > REPEAT_COUNT_MAX {
+                    errors.push(ScriptError::new(
+                        line,
+                        format!(
+                            "repeat count of {} must be between 1 and {}",
+                            count, REPEAT_COUNT_MAX
+                        ),
+                    ));
+                }
+                validate_statements(body, capabilities, errors);
+            }
+        }
+    }
+}
+
+/// Parses a `repeat N:` line, returning its count.
+fn parse_repeat(line: usize, command: &str) -> Result<u32, ScriptError> {
+    let count = command
+        .trim_start_matches("repeat")
+        .trim()
+        .strip_suffix(':')
+        .ok_or_else(|| ScriptError::new(line, "expected 'repeat <count>:'"))?
+        .trim();
+
+    count
+        .parse::<u32>()
+        .map_err(|_| ScriptError::new(line, format!("'{}' is not a valid repeat count", count)))
+}
+
+/// Parses a single (non-block) command.
+fn parse_statement(line: usize, command: &str) -> Result<Statement, ScriptError> {
+    let words: Vec<&str> = command.split_whitespace().collect();
+    let kind =
+        match words.as_slice() {
+            ["until", ..] => {
+                let colon = command.find(':').ok_or_else(|| {
+                    ScriptError::new(line, "expected 'until <condition>: <command>'")
+                })?;
+                let condition = parse_condition(line, command["until".len()..colon].trim())?;
+                let body = parse_statement(line, command[colon + 1..].trim())?;
+
+                StatementKind::Until {
+                    condition,
+                    body: Box::new(body),
+                }
+            }
+            ["forward", amount @ ..] | ["backward", amount @ ..] => StatementKind::Drive {
+                direction: if words[0] == "forward" {
+                    Direction::Forward
+                } else {
+                    Direction::Backward
+                },
+                amount: if amount.is_empty() {
+                    None
+                } else {
+                    Some(parse_amount(line, &amount.concat())?)
+                },
+            },
+            ["turn", direction, degrees @ ..] => StatementKind::Turn {
+                direction: match *direction {
+                    "left" => TurnDirection::Left,
+                    "right" => TurnDirection::Right,
+                    other => {
+                        return Err(ScriptError::new(
+                            line,
+                            format!("expected 'left' or 'right' after 'turn', found '{}'", other),
+                        ))
+                    }
+                },
+                degrees: if degrees.is_empty() {
+                    None
+                } else {
+                    Some(parse_number(
+                        line,
+                        degrees.concat().trim_end_matches("deg"),
+                    )?)
+                },
+            },
+            ["speed", speed] => StatementKind::Speed(speed.parse::<u8>().map_err(|_| {
+                ScriptError::new(line, format!("'{}' is not a valid speed", speed))
+            })?),
+            ["wait", amount @ ..] if !amount.is_empty() => {
+                match parse_amount(line, &amount.concat())? {
+                    Amount::Time(duration) => StatementKind::Wait(duration),
+                    _ => {
+                        return Err(ScriptError::new(
+                            line,
+                            "'wait' needs a time, e.g. 'wait 2s' or 'wait 500ms'",
+                        ))
+                    }
+                }
+            }
+            ["beep"] => StatementKind::Beep,
+            [] => return Err(ScriptError::new(line, "expected a command")),
+            ["turn"] => {
+                return Err(ScriptError::new(
+                    line,
+                    "expected 'turn left' or 'turn right'",
+                ))
+            }
+            [other, ..] => {
+                return Err(ScriptError::new(
+                    line,
+                    format!("'{}' is not a valid command", other),
+                ))
+            }
+        };
+
+    Ok(Statement { line, kind })
+}
+
+/// Parses the condition of an `until`, e.g. `touch` or `distance < 20cm`.
+fn parse_condition(line: usize, condition: &str) -> Result<Condition, ScriptError> {
+    let words: Vec<&str> = condition.split_whitespace().collect();
+    match words.as_slice() {
+        ["touch"] => Ok(Condition::Touch),
+        ["button"] => Ok(Condition::Button),
+        ["distance", rest @ ..] => {
+            let rest = rest.concat();
+            let distance = rest.strip_prefix('<').ok_or_else(|| {
+                ScriptError::new(
+                    line,
+                    "expected 'distance < <amount>', e.g. 'distance < 20cm'",
+                )
+            })?;
+            match parse_amount(line, distance)? {
+                Amount::Centimeters(cm) => Ok(Condition::DistanceBelow(cm)),
+                _ => Err(ScriptError::new(
+                    line,
+                    "distance must be in 'cm' or 'mm', e.g. 'distance < 20cm'",
+                )),
+            }
+        }
+        _ => Err(ScriptError::new(
+            line,
+            format!(
+                "'{}' is not a valid condition; expected 'touch', 'button', or 'distance < ...'",
+                condition
+            ),
+        )),
+    }
+}
+
+/// Parses an amount with a unit, e.g. `30cm`, `300mm`, `360deg`, `2s`, or `500ms`.
+fn parse_amount(line: usize, amount: &str) -> Result<Amount, ScriptError> {
+    let unit_start = amount
+        .find(|c: char| !(c.is_ascii_digit() || c == '.' || c == '-'))
+        .ok_or_else(|| {
+            ScriptError::new(
+                line,
+                format!(
+                    "'{}' is missing a unit; use 'cm', 'mm', 'deg', 's', or 'ms'",
+                    amount
+                ),
+            )
+        })?;
+    let value = parse_number(line, &amount[..unit_start])?;
+
+    // Check times before converting them, as [Duration] can't hold just any number of seconds.
+    let time = |secs: f32| {
+        if secs < 0.0 {
+            Err(ScriptError::new(line, "times must not be negative"))
+        } else if secs > TIME_SECS_MAX {
+            Err(ScriptError::new(
+                line,
+                format!("time of {} must be at most {}s", amount, TIME_SECS_MAX),
+            ))
+        } else {
+            Ok(Amount::Time(Duration::from_secs_f32(secs)))
+        }
+    };
+
+    match &amount[unit_start..] {
+        "cm" => Ok(Amount::Centimeters(value)),
+        "mm" => Ok(Amount::Centimeters(value / 10.0)),
+        "deg" => Ok(Amount::WheelDegrees(value)),
+        "s" => time(value),
+        "ms" => time(value / 1000.0),
+        unit => Err(ScriptError::new(
+            line,
+            format!(
+                "'{}' is not a valid unit; use 'cm', 'mm', 'deg', 's', or 'ms'",
+                unit
+            ),
+        )),
+    }
+}
+
+fn parse_number(line: usize, number: &str) -> Result<f32, ScriptError> {
+    number
+        .parse::<f32>()
+        .ok()
+        .filter(|value| value.is_finite())
+        .ok_or_else(|| ScriptError::new(line, format!("'{}' is not a valid number", number)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The [Capabilities] of a robot with every optional sensor plugged in.
+    const ALL_SENSORS: Capabilities = Capabilities {
+        touch_sensor: true,
+        ultrasonic_sensor: true,
+    };
+
+    /// Parses and validates the specified source, returning the messages of every
+    ///   [ScriptError] found.
+    fn check(source: &str, capabilities: &Capabilities) -> Vec<String> {
+        let errors = match parse(source) {
+            Ok(script) => validate(&script, capabilities),
+            Err(errors) => errors,
+        };
+
+        errors.into_iter().map(|err| err.to_string()).collect()
+    }
+
+    #[test]
+    fn parses_valid_script() {
+        let source = "\
+            # Drive a square.\n\
+            speed 50\n\
+            repeat 4:\n\
+              forward 30cm; turn right 90\n\
+            end\n\
+            until touch: forward\n\
+            until distance < 20cm: turn left\n\
+            backward 300mm; forward 360deg; forward 2s; wait 500ms\n\
+            beep\n";
+        let script = parse(source).expect("script should parse");
+        assert!(validate(&script, &ALL_SENSORS).is_empty());
+
+        assert_eq!(script.statements.len(), 9);
+        match &script.statements[1].kind {
+            StatementKind::Repeat { count, body } => {
+                assert_eq!(*count, 4);
+                assert_eq!(body.len(), 2);
+            }
+            other => panic!("expected a repeat, found {:?}", other),
+        }
+        match &script.statements[4].kind {
+            StatementKind::Drive {
+                direction: Direction::Backward,
+                amount: Some(Amount::Centimeters(cm)),
+            } => assert_eq!(*cm, 30.0),
+            other => panic!("expected a backward drive, found {:?}", other),
+        }
+        match &script.statements[7].kind {
+            StatementKind::Wait(duration) => assert_eq!(*duration, Duration::from_millis(500)),
+            other => panic!("expected a wait, found {:?}", other),
+        }
+    }
+
+    #[test]
+    fn parses_bundled_script() {
+        let script = parse(include_str!("../scripts/square.motion")).expect("should parse");
+        assert!(validate(&script, &ALL_SENSORS).is_empty());
+    }
+
+    #[test]
+    fn reports_each_error() {
+        let cases = [
+            ("end", "line 1: 'end' without a matching 'repeat'"),
+            ("repeat 2:", "line 1: 'repeat' is missing its closing 'end'"),
+            ("repeat 2", "line 1: expected 'repeat <count>:'"),
+            ("repeat x:", "line 1: 'x' is not a valid repeat count"),
+            ("until touch forward", "line 1: expected 'until <condition>: <command>'"),
+            ("until touch:", "line 1: expected a command"),
+            ("turn around 90", "line 1: expected 'left' or 'right' after 'turn', found 'around'"),
+            ("turn", "line 1: expected 'turn left' or 'turn right'"),
+            ("speed fast", "line 1: 'fast' is not a valid speed"),
+            ("wait 30cm", "line 1: 'wait' needs a time, e.g. 'wait 2s' or 'wait 500ms'"),
+            ("fly 30cm", "line 1: 'fly' is not a valid command"),
+            ("until smell: forward", "line 1: 'smell' is not a valid condition; expected 'touch', 'button', or 'distance < ...'"),
+            ("until distance 20cm: forward", "line 1: expected 'distance < <amount>', e.g. 'distance < 20cm'"),
+            ("until distance < 2s: forward", "line 1: distance must be in 'cm' or 'mm', e.g. 'distance < 20cm'"),
+            ("forward 30", "line 1: '30' is missing a unit; use 'cm', 'mm', 'deg', 's', or 'ms'"),
+            ("forward 30in", "line 1: 'in' is not a valid unit; use 'cm', 'mm', 'deg', 's', or 'ms'"),
+            ("wait -1s", "line 1: times must not be negative"),
+            ("forward 1.2.3cm", "line 1: '1.2.3' is not a valid number"),
+            ("forward", "line 1: motion without an amount is only allowed inside of 'until', e.g. 'until touch: forward'"),
+            ("forward 0cm", "line 1: drive amount must be more than 0"),
+            ("turn left 0", "line 1: turn of 0 degrees must be between 0 and 3600"),
+            ("speed 0", "line 1: speed of 0 must be between 1 and 100"),
+            ("until button: beep", "line 1: the command after 'until ...:' must be 'forward', 'backward', 'turn left', or 'turn right', without an amount"),
+            ("until distance < 0cm: forward", "line 1: distance must be more than 0cm"),
+            ("repeat 0:\nend", "line 1: repeat count of 0 must be between 1 and 1000"),
+        ];
+        for (source, expected) in cases.iter() {
+            assert_eq!(
+                check(source, &ALL_SENSORS),
+                vec![expected.to_string()],
+                "{}",
+                source
+            );
+        }
+    }
+
+    #[test]
+    fn reports_missing_sensors() {
+        let capabilities = Capabilities::default();
+        assert_eq!(
+            check("until touch: forward", &capabilities),
+            vec!["line 1: 'until touch' requires a touch sensor, but none is plugged in"]
+        );
+        assert_eq!(
+            check("until distance < 20cm: forward", &capabilities),
+            vec!["line 1: 'until distance' requires an ultrasonic sensor, but none is plugged in"]
+        );
+    }
+
+    #[test]
+    fn reports_every_error_by_line() {
+        assert_eq!(
+            check("fly\nspeed 0\nturn left 9999", &ALL_SENSORS),
+            vec!["line 1: 'fly' is not a valid command"]
+        );
+        assert_eq!(
+            check("speed 0\nforward 1cm\nturn left 9999", &ALL_SENSORS),
+            vec![
+                "line 1: speed of 0 must be between 1 and 100",
+                "line 3: turn of 9999 degrees must be between 0 and 3600",
+            ]
+        );
+    }
+
+    #[test]
+    fn bounds_amounts() {
+        // Right at the limits is fine.
+        assert!(check("wait 3600s; forward 3600000ms", &ALL_SENSORS).is_empty());
+        assert!(check("forward 10000cm; forward 100000mm", &ALL_SENSORS).is_empty());
+        assert!(check("forward 360000deg; turn left 3600", &ALL_SENSORS).is_empty());
+        assert!(check("speed 100; speed 1", &ALL_SENSORS).is_empty());
+        assert!(check("repeat 1000:\nbeep\nend", &ALL_SENSORS).is_empty());
+
+        // Just past them isn't, and nor are values too big for a [Duration].
+        assert_eq!(
+            check("wait 3601s", &ALL_SENSORS),
+            vec!["line 1: time of 3601s must be at most 3600s"]
+        );
+        assert_eq!(
+            check("forward 1000000000000000000000000000000s", &ALL_SENSORS),
+            vec!["line 1: time of 1000000000000000000000000000000s must be at most 3600s"]
+        );
+        assert_eq!(
+            check("wait 3600001ms", &ALL_SENSORS),
+            vec!["line 1: time of 3600001ms must be at most 3600s"]
+        );
+        assert_eq!(
+            check("forward 10001cm", &ALL_SENSORS),
+            vec!["line 1: drive of 10001cm must be at most 10000cm"]
+        );
+        assert_eq!(
+            check("forward 1000000000000000000000000000000mm", &ALL_SENSORS),
+            vec!["line 1: drive of 100000000000000000000000000000cm must be at most 10000cm"]
+        );
+        assert_eq!(
+            check("backward 360001deg", &ALL_SENSORS),
+            vec!["line 1: drive of 360001deg must be at most 360000deg"]
+        );
+        assert_eq!(
+            check("turn right 3601", &ALL_SENSORS),
+            vec!["line 1: turn of 3601 degrees must be between 0 and 3600"]
+        );
+        assert_eq!(
+            check("speed 101", &ALL_SENSORS),
+            vec!["line 1: speed of 101 must be between 1 and 100"]
+        );
+        assert_eq!(
+            check("repeat 1001:\nbeep\nend", &ALL_SENSORS),
+            vec!["line 1: repeat count of 1001 must be between 1 and 1000"]
+        );
+
+        // Non-finite numbers aren't numbers at all.
+        for source in [
+            "forward infcm",
+            "wait NaNs",
+            "turn left inf",
+            "forward 1000000000000000000000000000000000000000s",
+        ]
+        .iter()
+        {
+            assert_eq!(check(source, &ALL_SENSORS).len(), 1, "{}", source);
+        }
+    }
+}
@@ -0,0 +1,250 @@
+//! Lets the robot drive itself, via the same [behaviors](ev3_common::behaviors) as `auto-drive-rs`,
+//!   while still being hosted by (and sharing the motors with) this server.
+//!
+//! The driver starts the autopilot via `POST /autopilot` and stops it via `DELETE /autopilot`.
+//! While it's running, the device actor re-reads the sensors and re-picks a winning behavior on
+//!   every tick.
+//! Any manual driving command takes over from it immediately (see
+//!   [crate::ControlState::command_received()]), as does a change of the driving lease.
+
+use actix_web::{delete, post, web, HttpRequest, HttpResponse};
+use ev3_common::behaviors::{
+    Arbiter, Avoid, Behavior, Bump, Cliff, Cruise, Escape, LowBattery, SensorReadings,
+};
+use ev3_common::pairing::Pairing;
+use serde::Serialize;
+
+use crate::device::{Device, DeviceCommand, Robot};
+use crate::lease::{self, require_lease};
+use crate::{brake_to_stop, Ev3Devices, Ev3ErrorWrapper, Mode};
+
+/// The state of the autopilot, which is reported to clients as part of [crate::StateData].
+#[derive(Clone, Serialize)]
+pub struct Autopilot {
+    /// Whether or not the autopilot is currently driving.
+    pub running: bool,
+    /// The name of the behavior that's currently in control, if any.
+    pub behavior: Option<&'static str>,
+    /// Why the last run ended, if one has.
+    pub ended: Option<String>,
+}
+
+impl Autopilot {
+    /// Constructs a new [Autopilot], which isn't running.
+    pub fn new() -> Autopilot {
+        Autopilot {
+            running: false,
+            behavior: None,
+            ended: None,
+        }
+    }
+
+    /// Starts a new run.
+    fn start(&mut self) {
+        self.running = true;
+        self.behavior = None;
+        self.ended = None;
+    }
+
+    /// Ends the current run, if the autopilot is running.
+    /// The motors are left as they are, for the caller to take over.
+    ///
+    /// Parameters:
+    /// * `reason`: why the run is ending, which is reported to clients
+    pub fn stop(&mut self, reason: &str) {
+        if !self.running {
+            return;
+        }
+
+        println!("Autopilot: stopped ({}).", reason);
+        self.running = false;
+        self.behavior = None;
+        self.ended = Some(reason.to_string());
+    }
+}
+
+/// This API endpoint is called when the user clicks the "autopilot" button in the web
+///   application.
+/// Stops any manual driving (or replay, or homing), and has the robot drive itself until stopped or taken
+///   over.
+/// Does nothing if the autopilot is already running.
+///
+/// Parameters:
+/// * `request`: the HTTP request, which must include the client's session cookie and lease token
+/// * `pairing`: the [Pairing] instance managed/shared by the application
+/// * `device`: the [Device] handle managed/shared by the application
+#[post("/autopilot")]
+pub async fn start_autopilot(
+    request: HttpRequest,
+    pairing: web::Data<Pairing>,
+    device: web::Data<Device>,
+) -> actix_web::Result<HttpResponse> {
+    pairing.require_session(&request)?;
+    let token = lease::request_token(&request);
+    device
+        .send(|reply| DeviceCommand::StartAutopilot { token, reply })
+        .await?;
+
+    Ok(HttpResponse::Ok().finish().into_body())
+}
+
+/// This API endpoint is called when the user clicks the "stop autopilot" button in the web
+///   application.
+/// Stops the autopilot (if it's running) and brakes the robot.
+///
+/// Parameters:
+/// * `request`: the HTTP request, which must include the client's session cookie and lease token
+/// * `pairing`: the [Pairing] instance managed/shared by the application
+/// * `device`: the [Device] handle managed/shared by the application
+#[delete("/autopilot")]
+pub async fn stop_autopilot(
+    request: HttpRequest,
+    pairing: web::Data<Pairing>,
+    device: web::Data<Device>,
+) -> actix_web::Result<HttpResponse> {
+    pairing.require_session(&request)?;
+    let token = lease::request_token(&request);
+    device
+        .send(|reply| DeviceCommand::StopAutopilot { token, reply })
+        .await?;
+
+    Ok(HttpResponse::Ok().finish().into_body())
+}
+
+impl Robot {
+    /// Starts the autopilot, for [start_autopilot()].
+    ///
+    /// Parameters:
+    /// * `token`: the lease token that the client specified, if any
+    pub fn start_autopilot(&mut self, token: Option<&str>) -> actix_web::Result<()> {
+        let control_state = &mut self.control_state;
+        require_lease(control_state, token)?;
+        control_state.estop.require_clear()?;
+        if control_state.autopilot.running {
+            return Ok(());
+        }
+
+        // Build the behaviors first, so that nothing changes if that fails.
+        let arbiter = Arbiter::new(behaviors()).map_err(|cause| Ev3ErrorWrapper { cause })?;
+
+        // Come to a stop before handing over, so that the behaviors start from a standstill.
+        brake_to_stop(control_state, &self.ev3_devices)?;
+
+        control_state.replay.stop("autopilot started");
+        control_state.homing.stop("autopilot started");
+        control_state.mode = Mode::Stop;
+        control_state.guard = None;
+        control_state.timed_out = false;
+
+        control_state.autopilot.start();
+        self.arbiter = Some(arbiter);
+        println!("Autopilot: started.");
+
+        Ok(())
+    }
+
+    /// Stops the autopilot (if it's running) and brakes the robot, for [stop_autopilot()].
+    ///
+    /// Parameters:
+    /// * `token`: the lease token that the client specified, if any
+    pub fn stop_autopilot(&mut self, token: Option<&str>) -> actix_web::Result<()> {
+        let control_state = &mut self.control_state;
+        require_lease(control_state, token)?;
+        if !control_state.autopilot.running {
+            return Ok(());
+        }
+
+        control_state.autopilot.stop("stopped by the driver");
+        let motor_set = &self.ev3_devices.motor_set;
+        motor_set
+            .set_stop_action("brake")
+            .and_then(|_| motor_set.stop())
+            .map_err(Ev3ErrorWrapper::from)?;
+
+        Ok(())
+    }
+
+    /// Ticks the [Arbiter] with fresh [SensorReadings], on every tick of the device actor that
+    ///   the autopilot is running for.
+    ///
+    /// Once the autopilot has been stopped or taken over, its [Arbiter] is dropped without
+    ///   touching the motors again, as whoever stopped it is now in charge of them.
+    /// If an error occurs, the robot is braked and the autopilot is stopped.
+    pub fn drive_autopilot(&mut self) {
+        if !self.control_state.autopilot.running {
+            if let Some(arbiter) = self.arbiter.take() {
+                if let Err(err) = arbiter.reset_lights() {
+                    eprintln!("Autopilot: {:?}", err);
+                }
+            }
+            return;
+        }
+        let arbiter = match self.arbiter.as_mut() {
+            Some(arbiter) => arbiter,
+            None => return,
+        };
+
+        let ev3_devices = &self.ev3_devices;
+        let result = read_sensors(ev3_devices).and_then(|readings| {
+            arbiter
+                .tick(&readings, &ev3_devices.motor_set)
+                .map_err(Ev3ErrorWrapper::from)
+        });
+        if let Err(err) = result {
+            eprintln!("Autopilot: {}", err);
+            let motor_set = &ev3_devices.motor_set;
+            if let Err(err) = motor_set
+                .set_stop_action("brake")
+                .and_then(|_| motor_set.stop())
+                .map_err(Ev3ErrorWrapper::from)
+            {
+                eprintln!("Autopilot: {}", err);
+            }
+            self.control_state.autopilot.stop(&err.to_string());
+            return;
+        }
+        self.control_state.autopilot.behavior = arbiter.in_control();
+    }
+}
+
+/// Returns the [Behavior]s that the autopilot drives with, which are the same ones that
+///   `auto-drive-rs` uses, minus the ones that watch the brick's buttons: here, the web
+///   application is what stops the autopilot.
+fn behaviors() -> Vec<Box<dyn Behavior>> {
+    vec![
+        Box::new(Cruise),
+        Box::new(Avoid),
+        Box::new(Escape::new()),
+        Box::new(Bump::new()),
+        Box::new(Cliff::new()),
+        Box::new(LowBattery::new()),
+    ]
+}
+
+/// Reads the [SensorReadings] for a tick of the [Arbiter].
+///
+/// Parameters:
+/// * `ev3_devices`: the [Ev3Devices] to read
+fn read_sensors(ev3_devices: &Ev3Devices) -> Result<SensorReadings, Ev3ErrorWrapper> {
+    let sensors = &ev3_devices.sensors;
+
+    Ok(SensorReadings {
+        distance_cm: match &sensors.proximity_sensor {
+            Some(proximity_sensor) => proximity_sensor.get_distance_cm()?,
+            None => f32::INFINITY,
+        },
+        touch_pressed: match &sensors.touch_sensor {
+            Some(touch_sensor) => touch_sensor.get_pressed_state()?,
+            None => false,
+        },
+        // The brick's buttons are the e-stop here, not a way to end the autopilot.
+        button_pressed: false,
+        battery_volts: match &sensors.power_supply {
+            Some(power_supply) => power_supply.get_voltage_now()? as f32 / 1_000_000.0,
+            None => f32::INFINITY,
+        },
+        wheel_degrees: ev3_devices.motor_set.get_wheel_degrees()?,
+        cliff: ev3_devices.is_cliff()?,
+        beacon: None,
+    })
+}
@@ -0,0 +1,395 @@
+//! Provides the commands for the device actor (see [ev3_common::device]), which owns the motors
+//!   and sensors, along with the [ControlState] and everything else that drives them (the
+//!   autopilot, and any replay or homing run).
+//!
+//! Request handlers (and the WebSocket clients) send the actor a [DeviceCommand] via its [Device]
+//!   handle, and wait for its reply.
+//! Between requests, the actor is also ticked every [TICK_INTERVAL], on which it runs everything
+//!   that has to happen periodically, in a fixed order: the brick's buttons, the odometry, the
+//!   cliff, obstacle, and heartbeat monitors, and then whichever of the autopilot, homing, or
+//!   replay is driving.
+//! After every command, it publishes the current [StateData], so that anything that only needs to
+//!   read the state doesn't have to wait on the actor at all.
+
+use std::time::Duration;
+
+use ev3_common::behaviors::Arbiter;
+use ev3_common::buttons::BrickButtons;
+use ev3_common::device::{respond, Actor, DeviceError, Reply};
+use ev3_common::guard::Intervention;
+use ev3_common::telemetry::{MotorTelemetry, SensorTelemetry};
+
+use crate::drive::DriveResult;
+use crate::estop::{self, EStop};
+use crate::homing::{Homing, HomingRun, Route};
+use crate::lease::{AcquiredData, LeaseData};
+use crate::recording::{Recording, ReplayRun, Step};
+use crate::socket::DriveCommand;
+use crate::{ControlState, Ev3Devices, Ev3ErrorWrapper, Mode, StateData, STOP_TIMEOUT};
+
+/// How often the device actor is ticked.
+const TICK_INTERVAL: Duration = Duration::from_millis(50);
+
+/// The handle that the rest of the application uses to talk to the device actor.
+pub type Device = ev3_common::device::Device<Robot>;
+
+/// The commands that the device actor handles.
+///
+/// Each one that comes from a client carries that client's lease token (if any), which the actor
+///   checks against the driving lease.
+pub enum DeviceCommand {
+    /// Shifts gears, via [Robot::set_mode()].
+    SetMode {
+        token: Option<String>,
+        mode: Mode,
+        reply: Reply<Option<Intervention>>,
+    },
+    /// Sets the speed, via [Robot::set_speed()].
+    SetSpeed {
+        token: Option<String>,
+        speed: u8,
+        reply: Reply<Option<Intervention>>,
+    },
+    /// Sets the direction, via [Robot::set_direction()].
+    SetDirection {
+        token: Option<String>,
+        direction: i8,
+        reply: Reply<Option<Intervention>>,
+    },
+    /// Records a heartbeat from the driver, via [Robot::heartbeat()].
+    Heartbeat {
+        token: Option<String>,
+        reply: Reply<()>,
+    },
+    /// Applies an (already curved) throttle and steering, via [Robot::drive()].
+    Drive {
+        token: Option<String>,
+        throttle: f32,
+        steering: f32,
+        reply: Reply<DriveResult>,
+    },
+    /// Smooths and applies an (already curved) gamepad sample, via [Robot::drive_input()].
+    DriveInput {
+        token: Option<String>,
+        throttle: f32,
+        steering: f32,
+        connected: bool,
+        reply: Reply<DriveResult>,
+    },
+    /// Applies a [DriveCommand] sent over a WebSocket, via [Robot::apply_drive_command()].
+    SocketDrive {
+        token: Option<String>,
+        command: DriveCommand,
+        reply: Reply<()>,
+    },
+    /// Returns who holds the driving lease, via [Robot::get_lease()].
+    GetLease {
+        token: Option<String>,
+        reply: Reply<LeaseData>,
+    },
+    /// Acquires (or takes over) the driving lease, via [Robot::acquire_lease()].
+    AcquireLease {
+        token: Option<String>,
+        name: Option<String>,
+        takeover: bool,
+        peer: String,
+        reply: Reply<AcquiredData>,
+    },
+    /// Releases the driving lease, via [Robot::release_lease()].
+    ReleaseLease {
+        token: Option<String>,
+        reply: Reply<()>,
+    },
+    /// Engages the emergency stop, via [Robot::engage_estop()].
+    EngageEStop {
+        token: Option<String>,
+        peer: String,
+        reply: Reply<EStop>,
+    },
+    /// Clears the emergency stop, via [Robot::clear_estop()].
+    ClearEStop {
+        token: Option<String>,
+        reply: Reply<EStop>,
+    },
+    /// Starts the autopilot, via [Robot::start_autopilot()].
+    StartAutopilot {
+        token: Option<String>,
+        reply: Reply<()>,
+    },
+    /// Stops the autopilot, via [Robot::stop_autopilot()].
+    StopAutopilot {
+        token: Option<String>,
+        reply: Reply<()>,
+    },
+    /// Starts recording, via [Robot::start_recording()].
+    StartRecording {
+        token: Option<String>,
+        reply: Reply<()>,
+    },
+    /// Stops recording, and returns the [Recording], via [Robot::finish_recording()].
+    FinishRecording {
+        token: Option<String>,
+        reply: Reply<Recording>,
+    },
+    /// Lets go of a [Recording] once it's been saved, via [Robot::recording_saved()].
+    RecordingSaved {
+        token: Option<String>,
+        reply: Reply<()>,
+    },
+    /// Stops recording, and throws the recording away, via [Robot::discard_recording()].
+    DiscardRecording {
+        token: Option<String>,
+        reply: Reply<()>,
+    },
+    /// Starts replaying the specified [Step]s, via [Robot::start_replay()].
+    StartReplay {
+        token: Option<String>,
+        name: String,
+        steps: Vec<Step>,
+        duration: Duration,
+        reply: Reply<()>,
+    },
+    /// Stops the replay, via [Robot::stop_replay()].
+    StopReplay {
+        token: Option<String>,
+        reply: Reply<()>,
+    },
+    /// Resets the odometry, via [Robot::reset_odometry()].
+    ResetOdometry {
+        token: Option<String>,
+        reply: Reply<()>,
+    },
+    /// Starts driving home, via [Robot::go_home()].
+    GoHome {
+        token: Option<String>,
+        route: Route,
+        reply: Reply<Homing>,
+    },
+    /// Stops driving home, via [Robot::stop_home()].
+    StopHome {
+        token: Option<String>,
+        reply: Reply<()>,
+    },
+}
+
+/// Everything that's owned by the device actor.
+pub struct Robot {
+    pub control_state: ControlState,
+    pub ev3_devices: Ev3Devices,
+    /// The [Arbiter] that the autopilot drives with, while it's running.
+    pub arbiter: Option<Arbiter>,
+    /// The waypoints and current leg of the way home, while homing.
+    pub homing_run: Option<HomingRun>,
+    /// The [Step]s being replayed, while replaying.
+    pub replay_run: Option<ReplayRun>,
+    /// The brick's buttons, which engage the emergency stop, if they can be read.
+    pub buttons: Option<BrickButtons>,
+    /// How long the robot will keep driving without hearing from the client.
+    pub heartbeat_timeout: Duration,
+    /// The state of the motors, as of the last tick.
+    pub motors: Vec<MotorTelemetry>,
+    /// The state of the sensors, as of the last tick.
+    pub sensors: SensorTelemetry,
+}
+
+impl Robot {
+    /// Constructs a [Robot] for the device actor to own.
+    ///
+    /// Parameters:
+    /// * `heartbeat_timeout`: how long to wait for a command or heartbeat before stopping
+    pub fn new(heartbeat_timeout: Duration) -> Result<Robot, Ev3ErrorWrapper> {
+        let ev3_devices = Ev3Devices::new()?;
+        let motors = ev3_devices.motor_set.read_telemetry()?;
+        let sensors = ev3_devices.sensors.read()?;
+
+        Ok(Robot {
+            control_state: ControlState::new(),
+            ev3_devices,
+            arbiter: None,
+            homing_run: None,
+            replay_run: None,
+            buttons: BrickButtons::find(),
+            heartbeat_timeout,
+            motors,
+            sensors,
+        })
+    }
+
+    /// Reads the current state of the motors and sensors, for the next [StateData] to report.
+    fn read_telemetry(&mut self) -> Result<(), Ev3ErrorWrapper> {
+        self.motors = self.ev3_devices.motor_set.read_telemetry()?;
+        self.sensors = self.ev3_devices.sensors.read()?;
+
+        Ok(())
+    }
+}
+
+impl Actor for Robot {
+    type Command = DeviceCommand;
+    type State = StateData;
+
+    const TICK_INTERVAL: Duration = TICK_INTERVAL;
+    const STOP_TIMEOUT: Duration = STOP_TIMEOUT;
+
+    fn state(&self) -> StateData {
+        self.state_data()
+    }
+
+    fn handle(&mut self, command: DeviceCommand) {
+        match command {
+            DeviceCommand::SetMode { token, mode, reply } => {
+                respond(reply, self.set_mode(token.as_deref(), mode))
+            }
+            DeviceCommand::SetSpeed {
+                token,
+                speed,
+                reply,
+            } => respond(reply, self.set_speed(token.as_deref(), speed)),
+            DeviceCommand::SetDirection {
+                token,
+                direction,
+                reply,
+            } => respond(reply, self.set_direction(token.as_deref(), direction)),
+            DeviceCommand::Heartbeat { token, reply } => {
+                respond(reply, self.heartbeat(token.as_deref()))
+            }
+            DeviceCommand::Drive {
+                token,
+                throttle,
+                steering,
+                reply,
+            } => respond(reply, self.drive(token.as_deref(), throttle, steering)),
+            DeviceCommand::DriveInput {
+                token,
+                throttle,
+                steering,
+                connected,
+                reply,
+            } => respond(
+                reply,
+                self.drive_input(token.as_deref(), throttle, steering, connected),
+            ),
+            DeviceCommand::SocketDrive {
+                token,
+                command,
+                reply,
+            } => respond(reply, self.apply_drive_command(token.as_deref(), &command)),
+            DeviceCommand::GetLease { token, reply } => {
+                let _ = reply.send(Ok(self.get_lease(token.as_deref())));
+            }
+            DeviceCommand::AcquireLease {
+                token,
+                name,
+                takeover,
+                peer,
+                reply,
+            } => respond(
+                reply,
+                self.acquire_lease(token.as_deref(), name.as_deref(), takeover, &peer),
+            ),
+            DeviceCommand::ReleaseLease { token, reply } => {
+                respond(reply, self.release_lease(token.as_deref()))
+            }
+            DeviceCommand::EngageEStop { token, peer, reply } => {
+                respond(reply, self.engage_estop(token.as_deref(), &peer))
+            }
+            DeviceCommand::ClearEStop { token, reply } => {
+                respond(reply, self.clear_estop(token.as_deref()))
+            }
+            DeviceCommand::StartAutopilot { token, reply } => {
+                respond(reply, self.start_autopilot(token.as_deref()))
+            }
+            DeviceCommand::StopAutopilot { token, reply } => {
+                respond(reply, self.stop_autopilot(token.as_deref()))
+            }
+            DeviceCommand::StartRecording { token, reply } => {
+                respond(reply, self.start_recording(token.as_deref()))
+            }
+            DeviceCommand::FinishRecording { token, reply } => {
+                respond(reply, self.finish_recording(token.as_deref()))
+            }
+            DeviceCommand::RecordingSaved { token, reply } => {
+                respond(reply, self.recording_saved(token.as_deref()))
+            }
+            DeviceCommand::DiscardRecording { token, reply } => {
+                respond(reply, self.discard_recording(token.as_deref()))
+            }
+            DeviceCommand::StartReplay {
+                token,
+                name,
+                steps,
+                duration,
+                reply,
+            } => respond(
+                reply,
+                self.start_replay(token.as_deref(), &name, steps, duration),
+            ),
+            DeviceCommand::StopReplay { token, reply } => {
+                respond(reply, self.stop_replay(token.as_deref()))
+            }
+            DeviceCommand::ResetOdometry { token, reply } => {
+                respond(reply, self.reset_odometry(token.as_deref()))
+            }
+            DeviceCommand::GoHome {
+                token,
+                route,
+                reply,
+            } => respond(reply, self.go_home(token.as_deref(), route)),
+            DeviceCommand::StopHome { token, reply } => {
+                respond(reply, self.stop_home(token.as_deref()))
+            }
+        }
+    }
+
+    /// Runs everything that has to happen periodically.
+    ///
+    /// The safety checks come first, so that nothing drives on for another tick once the
+    ///   emergency stop is engaged, or once a cliff or obstacle is detected.
+    fn tick(&mut self) {
+        if self
+            .buttons
+            .as_mut()
+            .map_or(false, BrickButtons::newly_pressed)
+        {
+            if let Err(err) = estop::engage(
+                &mut self.control_state,
+                &self.ev3_devices,
+                Some("brick button"),
+            ) {
+                eprintln!("E-stop: {}", err);
+            }
+        }
+
+        self.finish_stopping();
+        self.track_odometry();
+        self.monitor_cliff();
+        self.monitor_obstacles();
+        self.monitor_heartbeat();
+
+        // Nothing drives on until the robot has finished stopping.
+        if self.control_state.stopping_since.is_none() {
+            self.drive_autopilot();
+            self.drive_home();
+            self.drive_replay();
+        }
+
+        if let Err(err) = self.read_telemetry() {
+            eprintln!("Telemetry: {}", err);
+        }
+    }
+
+    /// Engages the emergency stop, ahead of the [DeviceCommand::EngageEStop] that goes with it.
+    fn stop_signalled(&mut self) {
+        if let Err(err) = estop::engage(&mut self.control_state, &self.ev3_devices, None) {
+            eprintln!("E-stop: {}", err);
+        }
+    }
+
+    fn stop(&mut self) -> Result<(), DeviceError> {
+        Ok(self.ev3_devices.motor_set.stop()?)
+    }
+
+    fn is_running(&self) -> Result<bool, DeviceError> {
+        Ok(self.ev3_devices.motor_set.is_running()?)
+    }
+}
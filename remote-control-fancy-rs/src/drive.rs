@@ -0,0 +1,506 @@
+//! Provides a combined throttle/steering endpoint, for analog joystick-style clients.
+//!
+//! Rather than shifting gears and setting the speed and direction separately, clients send signed
+//!   throttle and steering values, which are run through [ResponseCurve]s and then mapped onto
+//!   the usual [ControlState] mode, speed, and direction.
+//! Each curve has a deadzone, so that a joystick resting slightly off-center doesn't creep, and
+//!   an exponential factor, which gives finer control near the center of the stick while still
+//!   allowing full speed/lock at its edges.
+//!
+//! Gamepads stream their samples to a separate endpoint, [drive_input()], which also rate limits
+//!   and smooths them.
+
+use std::time::{Duration, Instant};
+
+use actix_web::{post, web, HttpRequest, HttpResponse};
+use ev3_common::env::env_var_or;
+use ev3_common::guard::Intervention;
+use ev3_common::pairing::Pairing;
+use serde::{Deserialize, Serialize};
+
+use crate::device::{Device, DeviceCommand, Robot};
+use crate::lease::{self, require_lease};
+use crate::{apply_control_state, ControlState, Ev3Devices, Mode};
+
+/// The environment variable that can be used to override [DEADZONE_DEFAULT].
+const DEADZONE_VAR: &str = "REMOTE_CONTROL_DRIVE_DEADZONE";
+
+/// How far (as a fraction of full deflection) the throttle and steering have to move from center
+///   before they have any effect.
+const DEADZONE_DEFAULT: f32 = 0.05;
+
+/// The environment variable that can be used to override [EXPO_DEFAULT] for the throttle.
+const THROTTLE_EXPO_VAR: &str = "REMOTE_CONTROL_THROTTLE_EXPO";
+
+/// The environment variable that can be used to override [EXPO_DEFAULT] for the steering.
+const STEERING_EXPO_VAR: &str = "REMOTE_CONTROL_STEERING_EXPO";
+
+/// How exponential the throttle and steering response is, from `0.0` (linear) to `1.0` (cubic).
+const EXPO_DEFAULT: f32 = 0.3;
+
+/// The shortest allowed gap between [DriveInput] samples; anything faster is dropped.
+const INPUT_MIN_INTERVAL: Duration = Duration::from_millis(40);
+
+/// How long a gap between [DriveInput] samples resets the [InputFilter].
+const INPUT_STALE_AFTER: Duration = Duration::from_millis(500);
+
+/// How far (as a fraction) each [DriveInput] sample moves the [InputFilter] towards a faster
+///   throttle or steering.
+const INPUT_SMOOTHING: f32 = 0.5;
+
+/// Maps raw throttle or steering input onto the output actually used to drive.
+#[derive(Clone, Copy)]
+pub struct ResponseCurve {
+    /// Inputs smaller than this (in either direction) are treated as centered.
+    deadzone: f32,
+    /// How much of the output is cubic, rather than linear.
+    expo: f32,
+}
+
+impl ResponseCurve {
+    /// Applies this [ResponseCurve] to the specified input.
+    ///
+    /// Parameters:
+    /// * `input`: the raw input, from `-1.0` to `1.0`
+    ///
+    /// Returns the curved output, also from `-1.0` to `1.0`.
+    pub fn apply(&self, input: f32) -> f32 {
+        let input = input.max(-1.0).min(1.0);
+        if input.abs() <= self.deadzone {
+            return 0.0;
+        }
+
+        // Rescale what's left outside of the deadzone, so that the output still starts at zero.
+        let scaled = input.signum() * (input.abs() - self.deadzone) / (1.0 - self.deadzone);
+        (1.0 - self.expo) * scaled + self.expo * scaled.powi(3)
+    }
+}
+
+/// The [ResponseCurve]s used by [drive()] and [drive_input()].
+#[derive(Clone, Copy)]
+pub struct ResponseCurves {
+    throttle: ResponseCurve,
+    steering: ResponseCurve,
+}
+
+impl ResponseCurves {
+    /// Constructs the [ResponseCurves], which can be configured via the
+    ///   `REMOTE_CONTROL_DRIVE_DEADZONE`, `REMOTE_CONTROL_THROTTLE_EXPO`, and
+    ///   `REMOTE_CONTROL_STEERING_EXPO` environment variables.
+    pub fn from_env() -> anyhow::Result<ResponseCurves> {
+        // The deadzone has to leave some range to rescale, or everything would be centered.
+        let deadzone = env_var_or(DEADZONE_VAR, DEADZONE_DEFAULT, |value| {
+            (0.0..1.0).contains(value)
+        })?;
+        let throttle_expo = env_var_or(THROTTLE_EXPO_VAR, EXPO_DEFAULT, |value| {
+            (0.0..=1.0).contains(value)
+        })?;
+        let steering_expo = env_var_or(STEERING_EXPO_VAR, EXPO_DEFAULT, |value| {
+            (0.0..=1.0).contains(value)
+        })?;
+
+        Ok(ResponseCurves {
+            throttle: ResponseCurve {
+                deadzone,
+                expo: throttle_expo,
+            },
+            steering: ResponseCurve {
+                deadzone,
+                expo: steering_expo,
+            },
+        })
+    }
+}
+
+/// Models the JSON parameters for [drive()].
+#[derive(Deserialize)]
+pub struct DriveData {
+    /// How hard to drive, from `-1.0` (full speed backward) to `1.0` (full speed forward).
+    throttle: f32,
+    /// Which way to steer, from `-1.0` (full left) to `1.0` (full right).
+    steering: f32,
+}
+
+/// Models the JSON response for [drive()]: the mode, speed, and direction that the
+///   [DriveData] was mapped onto.
+#[derive(Serialize)]
+pub struct DriveResult {
+    mode: Mode,
+    speed: u8,
+    direction: i8,
+    /// How the collision guard is limiting forward driving, if it is.
+    guard: Option<Intervention>,
+}
+
+/// This API endpoint is called by analog joystick-style clients, to set the robot's throttle and
+///   steering in one go.
+/// Changing directions doesn't need a separate stop: the robot is shifted into
+///   [Mode::Forward], [Mode::Backward], or [Mode::Stop] to match the sign of the throttle.
+///
+/// Parameters:
+/// * `request`: the HTTP request, which must include the client's session cookie and lease token
+/// * `pairing`: the [Pairing] instance managed/shared by the application
+/// * `curves`: the [ResponseCurves] to apply to the [DriveData]
+/// * `device`: the [Device] handle managed/shared by the application
+/// * `drive_data`: the [DriveData] parameters specified in the API call
+#[post("/drive")]
+pub async fn drive(
+    request: HttpRequest,
+    pairing: web::Data<Pairing>,
+    curves: web::Data<ResponseCurves>,
+    device: web::Data<Device>,
+    drive_data: web::Json<DriveData>,
+) -> actix_web::Result<HttpResponse> {
+    pairing.require_session(&request)?;
+    if !drive_data.throttle.is_finite() || !drive_data.steering.is_finite() {
+        return Err(actix_web::error::ErrorBadRequest(
+            "The throttle and steering must be numbers from -1.0 to 1.0.",
+        ));
+    }
+
+    let token = lease::request_token(&request);
+    let throttle = curves.throttle.apply(drive_data.throttle);
+    let steering = curves.steering.apply(drive_data.steering);
+    let result = device
+        .send(|reply| DeviceCommand::Drive {
+            token,
+            throttle,
+            steering,
+            reply,
+        })
+        .await?;
+
+    Ok(HttpResponse::Ok().json(result))
+}
+
+/// Models the JSON parameters for [drive_input()]: a single sample of a gamepad's sticks.
+#[derive(Deserialize)]
+pub struct DriveInput {
+    /// How hard to drive, from `-1.0` (full speed backward) to `1.0` (full speed forward).
+    throttle: f32,
+    /// Which way to steer, from `-1.0` (full left) to `1.0` (full right).
+    steering: f32,
+    /// Whether the gamepad is still connected: if not, the robot is stopped.
+    #[serde(default = "connected_default")]
+    connected: bool,
+}
+
+/// Returns the default value for [DriveInput]'s `connected` field.
+fn connected_default() -> bool {
+    true
+}
+
+/// Smooths the stream of [DriveInput]s from a gamepad before it's applied.
+///
+/// Speeding up (in either direction) is eased in over a few samples, so that a stick slammed to
+///   its edge doesn't jerk the robot around, but slowing down is always applied immediately.
+pub struct InputFilter {
+    /// When the last sample was applied, if ever.
+    last_applied: Option<Instant>,
+    throttle: f32,
+    steering: f32,
+}
+
+impl InputFilter {
+    /// Constructs a new [InputFilter], centered.
+    pub fn new() -> InputFilter {
+        InputFilter {
+            last_applied: None,
+            throttle: 0.0,
+            steering: 0.0,
+        }
+    }
+
+    /// Re-centers this [InputFilter], e.g. when the gamepad is disconnected.
+    pub fn reset(&mut self) {
+        *self = InputFilter::new();
+    }
+
+    /// Filters the specified (already curved) sample.
+    ///
+    /// Parameters:
+    /// * `throttle`: the sample's throttle, from `-1.0` to `1.0`
+    /// * `steering`: the sample's steering, from `-1.0` to `1.0`
+    /// * `now`: when the sample arrived
+    ///
+    /// Returns the smoothed throttle and steering, or [None] if the sample arrived too soon after
+    ///   the previous one, and should be dropped.
+    fn filter(&mut self, throttle: f32, steering: f32, now: Instant) -> Option<(f32, f32)> {
+        match self.last_applied.map(|last_applied| now - last_applied) {
+            Some(elapsed) if elapsed < INPUT_MIN_INTERVAL => return None,
+            // Don't ease in from wherever the stick was before a long pause.
+            Some(elapsed) if elapsed > INPUT_STALE_AFTER => self.reset(),
+            _ => {}
+        }
+
+        self.last_applied = Some(now);
+        self.throttle = smooth(self.throttle, throttle);
+        self.steering = smooth(self.steering, steering);
+        Some((self.throttle, self.steering))
+    }
+}
+
+/// Eases the specified value towards its target, if the target is faster (further from center).
+///
+/// Parameters:
+/// * `current`: the current value
+/// * `target`: the value being moved towards
+fn smooth(current: f32, target: f32) -> f32 {
+    // When reversing, ease in from a standstill.
+    let current = if current * target < 0.0 { 0.0 } else { current };
+    if target.abs() <= current.abs() {
+        target
+    } else {
+        current + INPUT_SMOOTHING * (target - current)
+    }
+}
+
+/// This API endpoint is called by the web application many times per second while a gamepad is
+///   connected, streaming the positions of its sticks.
+/// Samples are run through the [ResponseCurves] (which handle the sticks' deadzones), smoothed by
+///   the [ControlState]'s [InputFilter], and then applied just like [drive()].
+/// Samples that arrive faster than the robot can usefully respond to are rejected with a
+///   `429 Too Many Requests`, except for disconnects, which always stop the robot immediately.
+///
+/// Parameters:
+/// * `request`: the HTTP request, which must include the client's session cookie and lease token
+/// * `pairing`: the [Pairing] instance managed/shared by the application
+/// * `curves`: the [ResponseCurves] to apply to the [DriveInput]
+/// * `device`: the [Device] handle managed/shared by the application
+/// * `input`: the [DriveInput] parameters specified in the API call
+#[post("/drive/input")]
+pub async fn drive_input(
+    request: HttpRequest,
+    pairing: web::Data<Pairing>,
+    curves: web::Data<ResponseCurves>,
+    device: web::Data<Device>,
+    input: web::Json<DriveInput>,
+) -> actix_web::Result<HttpResponse> {
+    pairing.require_session(&request)?;
+    let in_range = |value: f32| value.is_finite() && value.abs() <= 1.0;
+    if !in_range(input.throttle) || !in_range(input.steering) {
+        return Err(actix_web::error::ErrorBadRequest(
+            "The throttle and steering must be numbers from -1.0 to 1.0.",
+        ));
+    }
+
+    let token = lease::request_token(&request);
+    let throttle = curves.throttle.apply(input.throttle);
+    let steering = curves.steering.apply(input.steering);
+    let connected = input.connected;
+    let result = device
+        .send(|reply| DeviceCommand::DriveInput {
+            token,
+            throttle,
+            steering,
+            connected,
+            reply,
+        })
+        .await?;
+
+    Ok(HttpResponse::Ok().json(result))
+}
+
+impl Robot {
+    /// Applies the specified (already curved) throttle and steering, for [drive()].
+    ///
+    /// Parameters:
+    /// * `token`: the lease token that the client specified, if any
+    /// * `throttle`: the curved throttle, from `-1.0` to `1.0`
+    /// * `steering`: the curved steering, from `-1.0` to `1.0`
+    pub fn drive(
+        &mut self,
+        token: Option<&str>,
+        throttle: f32,
+        steering: f32,
+    ) -> actix_web::Result<DriveResult> {
+        let control_state = &mut self.control_state;
+        require_lease(control_state, token)?;
+        control_state.command_received();
+
+        apply_throttle_steering(control_state, &self.ev3_devices, throttle, steering)
+    }
+
+    /// Smooths and applies the specified (already curved) gamepad sample, for [drive_input()].
+    ///
+    /// Parameters:
+    /// * `token`: the lease token that the client specified, if any
+    /// * `throttle`: the curved throttle, from `-1.0` to `1.0`
+    /// * `steering`: the curved steering, from `-1.0` to `1.0`
+    /// * `connected`: whether the gamepad is still connected: if not, the robot is stopped
+    pub fn drive_input(
+        &mut self,
+        token: Option<&str>,
+        throttle: f32,
+        steering: f32,
+        connected: bool,
+    ) -> actix_web::Result<DriveResult> {
+        let control_state = &mut self.control_state;
+        require_lease(control_state, token)?;
+
+        let (throttle, steering) = if connected {
+            control_state
+                .input_filter
+                .filter(throttle, steering, Instant::now())
+                .ok_or_else(|| {
+                    actix_web::error::ErrorTooManyRequests("Gamepad input is arriving too quickly.")
+                })?
+        } else {
+            println!("Drive input: gamepad disconnected, stopping.");
+            control_state.input_filter.reset();
+            (0.0, 0.0)
+        };
+
+        control_state.command_received();
+        apply_throttle_steering(control_state, &self.ev3_devices, throttle, steering)
+    }
+}
+
+/// Maps the specified throttle and steering onto the [ControlState]'s mode, speed, and direction,
+///   and applies them.
+///
+/// Parameters:
+/// * `control_state`: the [ControlState] to update
+/// * `ev3_devices`: the [Ev3Devices] to update
+/// * `throttle`: the (already curved) throttle, from `-1.0` to `1.0`
+/// * `steering`: the (already curved) steering, from `-1.0` to `1.0`
+fn apply_throttle_steering(
+    control_state: &mut ControlState,
+    ev3_devices: &Ev3Devices,
+    throttle: f32,
+    steering: f32,
+) -> actix_web::Result<DriveResult> {
+    control_state.mode = if throttle > 0.0 {
+        Mode::Forward
+    } else if throttle < 0.0 {
+        Mode::Backward
+    } else {
+        Mode::Stop
+    };
+    control_state.speed = (throttle.abs() * 100.0).round() as u8;
+    control_state.direction = (steering * 100.0).round() as i8;
+    control_state.record_controls();
+    apply_control_state(control_state, ev3_devices)?;
+
+    Ok(DriveResult {
+        mode: control_state.mode,
+        speed: control_state.speed,
+        direction: control_state.direction,
+        guard: control_state.guard,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Asserts that the specified values are within a rounding error of each other.
+    fn assert_close(actual: f32, expected: f32) {
+        assert!(
+            (actual - expected).abs() < 0.0001,
+            "expected {}, found {}",
+            expected,
+            actual
+        );
+    }
+
+    #[test]
+    fn applies_deadzone() {
+        let curve = ResponseCurve {
+            deadzone: 0.1,
+            expo: 0.0,
+        };
+
+        assert_close(curve.apply(0.0), 0.0);
+        assert_close(curve.apply(0.1), 0.0);
+        assert_close(curve.apply(-0.1), 0.0);
+
+        // Just past the edge, the output starts from zero, rather than jumping to the deadzone.
+        assert_close(curve.apply(0.1 + 0.009), 0.01);
+        assert_close(curve.apply(-0.1 - 0.009), -0.01);
+        assert_close(curve.apply(0.55), 0.5);
+    }
+
+    #[test]
+    fn applies_expo() {
+        let linear = ResponseCurve {
+            deadzone: 0.0,
+            expo: 0.0,
+        };
+        assert_close(linear.apply(0.5), 0.5);
+        assert_close(linear.apply(-0.5), -0.5);
+
+        let cubic = ResponseCurve {
+            deadzone: 0.0,
+            expo: 1.0,
+        };
+        assert_close(cubic.apply(0.5), 0.125);
+        assert_close(cubic.apply(-0.5), -0.125);
+
+        // Full stick is always full output, whatever the curve, and anything past it is clamped.
+        for &expo in &[0.0, 0.3, 1.0] {
+            let curve = ResponseCurve {
+                deadzone: 0.05,
+                expo,
+            };
+            assert_close(curve.apply(1.0), 1.0);
+            assert_close(curve.apply(-1.0), -1.0);
+            assert_close(curve.apply(2.0), 1.0);
+            assert_close(curve.apply(-2.0), -1.0);
+        }
+    }
+
+    #[test]
+    fn rejects_samples_too_soon() {
+        let mut filter = InputFilter::new();
+        let start = Instant::now();
+
+        assert!(filter.filter(1.0, 0.0, start).is_some());
+        assert!(filter
+            .filter(1.0, 0.0, start + INPUT_MIN_INTERVAL / 2)
+            .is_none());
+
+        // The rejected sample doesn't push back when the next one is allowed.
+        assert!(filter
+            .filter(1.0, 0.0, start + INPUT_MIN_INTERVAL)
+            .is_some());
+    }
+
+    #[test]
+    fn eases_in_acceleration() {
+        let mut filter = InputFilter::new();
+        let start = Instant::now();
+
+        let (throttle, steering) = filter.filter(1.0, -1.0, start).unwrap();
+        assert_close(throttle, 0.5);
+        assert_close(steering, -0.5);
+
+        let (throttle, steering) = filter
+            .filter(1.0, -1.0, start + INPUT_MIN_INTERVAL)
+            .unwrap();
+        assert_close(throttle, 0.75);
+        assert_close(steering, -0.75);
+
+        // After a long pause, it eases in from the center again.
+        let (throttle, _) = filter
+            .filter(1.0, 0.0, start + Duration::from_secs(5))
+            .unwrap();
+        assert_close(throttle, 0.5);
+    }
+
+    #[test]
+    fn brakes_immediately() {
+        assert_close(smooth(0.8, 0.2), 0.2);
+        assert_close(smooth(-0.8, 0.0), 0.0);
+
+        // Speeding up is eased in, and reversing eases in from a standstill.
+        assert_close(smooth(0.2, 1.0), 0.6);
+        assert_close(smooth(0.8, -1.0), -0.5);
+
+        let mut filter = InputFilter::new();
+        let start = Instant::now();
+        filter.filter(1.0, 0.0, start).unwrap();
+        let (throttle, _) = filter.filter(0.0, 0.0, start + INPUT_MIN_INTERVAL).unwrap();
+        assert_close(throttle, 0.0);
+    }
+}
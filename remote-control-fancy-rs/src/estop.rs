@@ -0,0 +1,178 @@
+//! Provides an emergency stop, which brakes the robot and then refuses to drive it at all until
+//!   it's explicitly cleared.
+//!
+//! Any paired client can engage the emergency stop via `POST /estop`, whether or not it holds the
+//!   driving lease, and so can anyone standing next to the robot, by pressing any of the brick's
+//!   buttons.
+//! Only the driver can clear it, via `DELETE /estop`.
+//! While it's engaged, anything that would make the robot move is refused with `409 Conflict`.
+//!
+//! A client's request jumps the device actor's queue (via [Device::signal_stop()]), so that the
+//!   robot is braked before any of the commands already waiting on the actor can drive it.
+
+use actix_web::{delete, post, web, HttpRequest, HttpResponse};
+use ev3_common::pairing::Pairing;
+use serde::Serialize;
+
+use crate::device::{Device, DeviceCommand, Robot};
+use crate::lease::{self, require_lease};
+use crate::{ControlState, Ev3Devices, Ev3ErrorWrapper, Mode};
+
+/// The state of the emergency stop, which is reported to clients as part of [crate::StateData].
+#[derive(Clone, Serialize)]
+pub struct EStop {
+    /// Whether or not the emergency stop is engaged.
+    pub engaged: bool,
+    /// What engaged the emergency stop, e.g. `"brick button"` or the name of a client, if it's
+    ///   engaged.
+    pub source: Option<String>,
+}
+
+impl EStop {
+    /// Constructs a new [EStop], which isn't engaged.
+    pub fn new() -> EStop {
+        EStop {
+            engaged: false,
+            source: None,
+        }
+    }
+
+    /// Returns an error if the emergency stop is engaged.
+    pub fn require_clear(&self) -> actix_web::Result<()> {
+        if !self.engaged {
+            return Ok(());
+        }
+
+        Err(actix_web::error::ErrorConflict(format!(
+            "Emergency stop engaged by {}: clear it to drive.",
+            self.source.as_deref().unwrap_or("unknown")
+        )))
+    }
+}
+
+/// Engages the emergency stop: stops the autopilot and any replay or homing run, shifts the
+///   robot into [Mode::Stop], and brakes it.
+///
+/// The emergency stop is latched even if braking fails, so that nothing else can drive the robot.
+///
+/// Parameters:
+/// * `control_state`: the current [ControlState]
+/// * `ev3_devices`: the [Ev3Devices] to brake
+/// * `source`: what engaged the emergency stop, which is reported to clients, or `None` if it
+///   was signalled ahead of the request that says who engaged it
+pub fn engage(
+    control_state: &mut ControlState,
+    ev3_devices: &Ev3Devices,
+    source: Option<&str>,
+) -> Result<(), Ev3ErrorWrapper> {
+    let estop = &mut control_state.estop;
+    if !estop.engaged || (estop.source.is_none() && source.is_some()) {
+        match source {
+            Some(source) => println!("E-stop: engaged by {}.", source),
+            None => println!("E-stop: engaged, ahead of the request."),
+        }
+        estop.engaged = true;
+        estop.source = source.map(String::from);
+    }
+
+    control_state.autopilot.stop("emergency stop");
+    control_state.replay.stop("emergency stop");
+    control_state.homing.stop("emergency stop");
+    control_state.mode = Mode::Stop;
+    control_state.guard = None;
+    control_state.input_filter.reset();
+
+    let motor_set = &ev3_devices.motor_set;
+    motor_set.set_stop_action("brake")?;
+    Ok(motor_set.stop()?)
+}
+
+/// This API endpoint is called when the user clicks the "emergency stop" button in the web
+///   application.
+/// Brakes the robot and refuses to drive it again until the emergency stop is cleared.
+/// Any paired client can call this, whether or not it holds the driving lease.
+///
+/// Parameters:
+/// * `request`: the HTTP request, which must include the client's session cookie, and may include
+///   its lease token
+/// * `pairing`: the [Pairing] instance managed/shared by the application
+/// * `device`: the [Device] handle managed/shared by the application
+#[post("/estop")]
+pub async fn engage_estop(
+    request: HttpRequest,
+    pairing: web::Data<Pairing>,
+    device: web::Data<Device>,
+) -> actix_web::Result<HttpResponse> {
+    pairing.require_session(&request)?;
+    device.signal_stop();
+    let token = lease::request_token(&request);
+    let peer = request
+        .peer_addr()
+        .map(|address| address.ip().to_string())
+        .unwrap_or_else(|| "unknown".to_string());
+    let estop = device
+        .send(|reply| DeviceCommand::EngageEStop { token, peer, reply })
+        .await?;
+
+    Ok(HttpResponse::Ok().json(estop))
+}
+
+/// This API endpoint is called when the user clicks the "clear emergency stop" button in the web
+///   application.
+/// Lets the robot be driven again, though it stays stopped until the driver says otherwise.
+///
+/// Parameters:
+/// * `request`: the HTTP request, which must include the client's session cookie and lease token
+/// * `pairing`: the [Pairing] instance managed/shared by the application
+/// * `device`: the [Device] handle managed/shared by the application
+#[delete("/estop")]
+pub async fn clear_estop(
+    request: HttpRequest,
+    pairing: web::Data<Pairing>,
+    device: web::Data<Device>,
+) -> actix_web::Result<HttpResponse> {
+    pairing.require_session(&request)?;
+    let token = lease::request_token(&request);
+    let estop = device
+        .send(|reply| DeviceCommand::ClearEStop { token, reply })
+        .await?;
+
+    Ok(HttpResponse::Ok().json(estop))
+}
+
+impl Robot {
+    /// Engages the emergency stop, for [engage_estop()], and returns its state.
+    ///
+    /// Parameters:
+    /// * `token`: the lease token that the client specified, if any
+    /// * `peer`: the client's IP address, which is reported as what engaged the emergency stop,
+    ///   unless the client holds the driving lease
+    pub fn engage_estop(
+        &mut self,
+        token: Option<&str>,
+        peer: &str,
+    ) -> Result<EStop, Ev3ErrorWrapper> {
+        let source = match &self.control_state.lease {
+            Some(lease) if lease.is_held_by(token) => lease.driver.clone(),
+            _ => peer.to_string(),
+        };
+        engage(&mut self.control_state, &self.ev3_devices, Some(&source))?;
+
+        Ok(self.control_state.estop.clone())
+    }
+
+    /// Clears the emergency stop, for [clear_estop()], and returns its state.
+    ///
+    /// Parameters:
+    /// * `token`: the lease token that the client specified, if any
+    pub fn clear_estop(&mut self, token: Option<&str>) -> actix_web::Result<EStop> {
+        require_lease(&self.control_state, token)?;
+
+        if self.control_state.estop.engaged {
+            println!("E-stop: cleared.");
+            self.control_state.estop = EStop::new();
+        }
+
+        Ok(self.control_state.estop.clone())
+    }
+}
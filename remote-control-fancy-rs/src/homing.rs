@@ -0,0 +1,611 @@
+//! Drives the robot back to where the session started, per its [Odometry](crate::odometry).
+//!
+//! There are two ways home:
+//! * [Route::Retrace]: back along the breadcrumbs dropped on the way out, in reverse, which
+//!   avoids whatever the robot steered around on the way out.
+//! * [Route::Direct]: a straight line back to the start, which is only a good idea if no
+//!   obstacles were seen on the way out.
+//!
+//! Either way, the robot turns in place to face each waypoint, drives straight to it, and finally
+//!   turns back to the heading it started with. Each leg is planned from wherever the odometry
+//!   says the robot is at the time, so small errors in one leg get corrected in the next.
+//! The device actor checks on the progress of each leg on every tick, and any manual driving
+//!   command takes over from homing immediately, just like for the [autopilot](crate::autopilot).
+
+use actix_web::{delete, post, web, HttpRequest, HttpResponse};
+use ev3_common::guard::GuardAction;
+use ev3_common::moves::{cm_to_wheel_degrees, turn_to_wheel_degrees, WheelMove};
+use ev3_common::pairing::Pairing;
+use serde::{Deserialize, Serialize};
+
+use crate::device::{Device, DeviceCommand, Robot};
+use crate::lease::{self, require_lease};
+use crate::odometry::{normalize_degrees, Pose};
+use crate::{brake_to_stop, Ev3Devices, Ev3ErrorWrapper, Mode};
+
+/// How fast the wheels are turned while homing, in degrees per second.
+const HOMING_SPEED: i32 = 500;
+
+/// Waypoints closer than this (in centimeters) are considered reached already.
+const ARRIVED_CM: f32 = 3.0;
+
+/// Turns smaller than this (in degrees) are skipped.
+const TURN_TOLERANCE_DEG: f32 = 5.0;
+
+/// The ways that the robot can get back to the start.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Route {
+    /// Retrace the path in reverse.
+    Retrace,
+    /// Drive straight back to the start.
+    Direct,
+    /// Drive straight back to the start if no obstacles were seen, and retrace the path
+    ///   otherwise.
+    Auto,
+}
+
+/// The state of homing, which is reported to clients as part of [crate::StateData].
+#[derive(Clone, Serialize)]
+pub struct Homing {
+    /// Whether or not the robot is currently driving home.
+    pub running: bool,
+    /// The route being taken (or last taken) home, if any: never [Route::Auto].
+    pub route: Option<Route>,
+    /// How many waypoints are left to reach, including the start itself.
+    pub waypoints_left: usize,
+    /// How far is left to drive along the route, in centimeters.
+    pub remaining_cm: f32,
+    /// Why the last homing run ended, if one has, e.g. "arrived".
+    pub ended: Option<String>,
+}
+
+impl Homing {
+    /// Constructs a new [Homing], which isn't running.
+    pub fn new() -> Homing {
+        Homing {
+            running: false,
+            route: None,
+            waypoints_left: 0,
+            remaining_cm: 0.0,
+            ended: None,
+        }
+    }
+
+    /// Starts a new homing run.
+    fn start(&mut self, route: Route) {
+        self.running = true;
+        self.route = Some(route);
+        self.ended = None;
+    }
+
+    /// Ends the current homing run, if one is running.
+    /// The motors are left as they are, for the caller to take over.
+    ///
+    /// Parameters:
+    /// * `reason`: why the run is ending, which is reported to clients
+    pub fn stop(&mut self, reason: &str) {
+        if !self.running {
+            return;
+        }
+
+        println!("Homing: stopped ({}).", reason);
+        self.running = false;
+        self.ended = Some(reason.to_string());
+    }
+
+    /// Updates the progress along the specified waypoints.
+    ///
+    /// Parameters:
+    /// * `pose`: the robot's current [Pose]
+    /// * `waypoints`: the waypoints still to reach, in order
+    fn update_progress(&mut self, pose: &Pose, waypoints: &[(f32, f32)]) {
+        self.waypoints_left = waypoints.len();
+        self.remaining_cm = match waypoints.first() {
+            Some(first) => {
+                pose.distance_to(*first)
+                    + waypoints
+                        .windows(2)
+                        .map(|leg| (leg[1].0 - leg[0].0).hypot(leg[1].1 - leg[0].1))
+                        .sum::<f32>()
+            }
+            None => 0.0,
+        };
+    }
+}
+
+/// This API endpoint resets the robot's odometry, making wherever it is now the start that
+///   [go_home()] drives back to.
+///
+/// Parameters:
+/// * `request`: the HTTP request, which must include the client's session cookie and lease token
+/// * `pairing`: the [Pairing] instance managed/shared by the application
+/// * `device`: the [Device] handle managed/shared by the application
+#[post("/odometry/reset")]
+pub async fn reset_odometry(
+    request: HttpRequest,
+    pairing: web::Data<Pairing>,
+    device: web::Data<Device>,
+) -> actix_web::Result<HttpResponse> {
+    pairing.require_session(&request)?;
+    let token = lease::request_token(&request);
+    device
+        .send(|reply| DeviceCommand::ResetOdometry { token, reply })
+        .await?;
+
+    Ok(HttpResponse::Ok().finish().into_body())
+}
+
+/// Models the JSON parameters for [go_home()].
+#[derive(Deserialize)]
+struct HomeData {
+    #[serde(default = "route_default")]
+    route: Route,
+}
+
+/// Returns the default value for [HomeData]'s `route` field.
+fn route_default() -> Route {
+    Route::Auto
+}
+
+/// This API endpoint stops the robot, and then drives it back to the start.
+/// Any autopilot, replay, or homing run that's already running is stopped first.
+///
+/// Parameters:
+/// * `request`: the HTTP request, which must include the client's session cookie and lease token
+/// * `pairing`: the [Pairing] instance managed/shared by the application
+/// * `device`: the [Device] handle managed/shared by the application
+/// * `home_data`: the [HomeData] parameters specified in the API call
+#[post("/home")]
+pub async fn go_home(
+    request: HttpRequest,
+    pairing: web::Data<Pairing>,
+    device: web::Data<Device>,
+    home_data: web::Json<HomeData>,
+) -> actix_web::Result<HttpResponse> {
+    pairing.require_session(&request)?;
+    let token = lease::request_token(&request);
+    let route = home_data.route;
+    let homing = device
+        .send(|reply| DeviceCommand::GoHome {
+            token,
+            route,
+            reply,
+        })
+        .await?;
+
+    Ok(HttpResponse::Ok().json(homing))
+}
+
+/// This API endpoint stops driving home (if the robot is) and brakes the robot.
+///
+/// Parameters:
+/// * `request`: the HTTP request, which must include the client's session cookie and lease token
+/// * `pairing`: the [Pairing] instance managed/shared by the application
+/// * `device`: the [Device] handle managed/shared by the application
+#[delete("/home")]
+pub async fn stop_home(
+    request: HttpRequest,
+    pairing: web::Data<Pairing>,
+    device: web::Data<Device>,
+) -> actix_web::Result<HttpResponse> {
+    pairing.require_session(&request)?;
+    let token = lease::request_token(&request);
+    device
+        .send(|reply| DeviceCommand::StopHome { token, reply })
+        .await?;
+
+    Ok(HttpResponse::Ok().finish().into_body())
+}
+
+impl Robot {
+    /// Resets the robot's odometry, for [reset_odometry()].
+    ///
+    /// Parameters:
+    /// * `token`: the lease token that the client specified, if any
+    pub fn reset_odometry(&mut self, token: Option<&str>) -> actix_web::Result<()> {
+        let control_state = &mut self.control_state;
+        require_lease(control_state, token)?;
+        if control_state.homing.running {
+            return Err(actix_web::error::ErrorConflict(
+                "Already driving home: stop that first.",
+            ));
+        }
+
+        println!("Odometry: reset.");
+        control_state.odometry.reset();
+
+        Ok(())
+    }
+
+    /// Stops the robot, and then starts driving it back to the start, for [go_home()].
+    /// Returns the new state of [Homing].
+    ///
+    /// Parameters:
+    /// * `token`: the lease token that the client specified, if any
+    /// * `route`: the [Route] to take home
+    pub fn go_home(&mut self, token: Option<&str>, route: Route) -> actix_web::Result<Homing> {
+        let control_state = &mut self.control_state;
+        require_lease(control_state, token)?;
+        control_state.estop.require_clear()?;
+
+        control_state.autopilot.stop("homing started");
+        control_state.replay.stop("homing started");
+        control_state.homing.stop("homing restarted");
+
+        // Come to a stop before the first leg, which is planned from wherever the robot stops.
+        brake_to_stop(control_state, &self.ev3_devices)?;
+        control_state.mode = Mode::Stop;
+        control_state.guard = None;
+        control_state.timed_out = false;
+
+        let odometry = &control_state.odometry;
+        let route = match route {
+            Route::Auto if odometry.obstacles_seen() => Route::Retrace,
+            Route::Auto => Route::Direct,
+            route => route,
+        };
+        let waypoints: Vec<(f32, f32)> = match route {
+            Route::Retrace => odometry.path().iter().rev().copied().collect(),
+            _ => vec![(0.0, 0.0)],
+        };
+        let pose = odometry.pose();
+
+        control_state.homing.start(route);
+        control_state.homing.update_progress(&pose, &waypoints);
+        println!(
+            "Homing: started, via {:?} route ({} waypoints, {:.0} cm).",
+            route, control_state.homing.waypoints_left, control_state.homing.remaining_cm
+        );
+        self.homing_run = Some(HomingRun::new(waypoints));
+
+        Ok(self.control_state.homing.clone())
+    }
+
+    /// Stops driving home (if the robot is) and brakes the robot, for [stop_home()].
+    ///
+    /// Parameters:
+    /// * `token`: the lease token that the client specified, if any
+    pub fn stop_home(&mut self, token: Option<&str>) -> actix_web::Result<()> {
+        let control_state = &mut self.control_state;
+        require_lease(control_state, token)?;
+        if !control_state.homing.running {
+            return Ok(());
+        }
+
+        control_state.homing.stop("stopped by the driver");
+        let motor_set = &self.ev3_devices.motor_set;
+        motor_set
+            .set_stop_action("brake")
+            .and_then(|_| motor_set.stop())
+            .map_err(Ev3ErrorWrapper::from)?;
+
+        Ok(())
+    }
+
+    /// Checks on the progress of the [HomingRun], on every tick of the device actor that the
+    ///   robot is driving home for, starting on the next leg once the current one is done.
+    ///
+    /// Once the run has been stopped or taken over, it's dropped without touching the motors
+    ///   again, as whoever stopped it is now in charge of them.
+    /// Otherwise, the robot is braked once it's home, or once something goes wrong, e.g. an
+    ///   obstacle is in the way, a leg times out, or the last leg ends up short of the start.
+    pub fn drive_home(&mut self) {
+        let mut homing_run = match self.homing_run.take() {
+            Some(homing_run) => homing_run,
+            None => return,
+        };
+        if !self.control_state.homing.running {
+            return;
+        }
+
+        let result = self.advance_home(&mut homing_run);
+        if let Ok(true) = result {
+            self.homing_run = Some(homing_run);
+            return;
+        }
+
+        let motor_set = &self.ev3_devices.motor_set;
+        if let Err(err) = motor_set
+            .set_stop_action("brake")
+            .and_then(|_| motor_set.stop())
+            .map_err(Ev3ErrorWrapper::from)
+        {
+            eprintln!("Homing: {}", err);
+        }
+        let control_state = &mut self.control_state;
+        let pose = control_state.odometry.pose();
+        let result = result.and_then(|_| {
+            // The legs are planned from wherever the robot actually ended up, but that's only
+            //   home if the last one actually got there.
+            let distance_cm = pose.distance_to((0.0, 0.0));
+            if distance_cm < ARRIVED_CM {
+                Ok(())
+            } else {
+                Err(format!("Ended up {:.0} cm from the start", distance_cm))
+            }
+        });
+        match result {
+            Ok(()) => {
+                println!(
+                    "Homing: arrived, at ({:.0} cm, {:.0} cm), facing {:.0} degrees.",
+                    pose.x_cm, pose.y_cm, pose.heading_deg
+                );
+                control_state.odometry.arrived_home();
+                control_state.homing.update_progress(&pose, &[]);
+                control_state.homing.stop("arrived");
+            }
+            Err(reason) => control_state.homing.stop(&reason),
+        }
+    }
+
+    /// Checks on the current leg of the specified [HomingRun], and starts the next one once it's
+    ///   done.
+    ///
+    /// Returns `true` if there's still further to go, `false` once the robot is home, or an error
+    ///   describing why homing can't continue, if it can't, e.g. because there's an obstacle in
+    ///   the way.
+    ///
+    /// Parameters:
+    /// * `homing_run`: the [HomingRun] to advance
+    fn advance_home(&mut self, homing_run: &mut HomingRun) -> Result<bool, String> {
+        let pose = self.control_state.odometry.pose();
+        let ev3_devices = &self.ev3_devices;
+
+        if let Some(running_leg) = &homing_run.leg {
+            self.control_state
+                .homing
+                .update_progress(&pose, &homing_run.waypoints[running_leg.waypoint..]);
+
+            let wheel_degrees = ev3_devices
+                .motor_set
+                .get_wheel_degrees()
+                .map_err(|err| Ev3ErrorWrapper::from(err).to_string())?;
+            let wheel_move = &running_leg.wheel_move;
+            if !wheel_move.is_arrived(wheel_degrees) {
+                // A leg that's taking far too long is probably stuck, which leaves the robot
+                //   who-knows-where, so there's no point in planning further legs from here.
+                if wheel_move.is_timed_out() {
+                    let (left_traveled, right_traveled) = wheel_move.traveled(wheel_degrees);
+                    return Err(format!(
+                        "Leg timed out, after {:.0}/{:.0} degrees",
+                        left_traveled, right_traveled
+                    ));
+                }
+
+                // Don't drive into anything on the way.
+                if let Leg::Drive(_) = running_leg.leg {
+                    if ev3_devices.is_cliff().map_err(|err| err.to_string())? {
+                        return Err("Cliff detected".to_string());
+                    }
+                    if let Some(intervention) =
+                        ev3_devices.check_guard().map_err(|err| err.to_string())?
+                    {
+                        if intervention.action == GuardAction::Stop {
+                            return Err(intervention.describe());
+                        }
+                    }
+                }
+                return Ok(true);
+            }
+            homing_run.leg = None;
+        }
+
+        // Plan each leg from wherever the robot actually ended up after the last one.
+        match homing_run.next_leg(&pose) {
+            Some((leg, waypoint)) => {
+                homing_run.leg = Some(RunningLeg {
+                    leg,
+                    wheel_move: start_leg(ev3_devices, leg)?,
+                    waypoint,
+                });
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+}
+
+/// A single leg of the way home.
+#[derive(Clone, Copy, Debug)]
+enum Leg {
+    /// Turn in place by the specified number of degrees, counter-clockwise.
+    Turn(f32),
+    /// Drive straight ahead for the specified number of centimeters.
+    Drive(f32),
+}
+
+/// Where a [HomingRun] is up to.
+#[derive(Clone, Copy, Debug)]
+enum Stage {
+    /// Turn to face the waypoint at the specified index, unless it's been reached already.
+    TurnTo(usize),
+    /// Drive to the waypoint at the specified index.
+    DriveTo(usize),
+    /// Turn to face the way the robot started out.
+    FaceStart,
+    /// Nothing's left to do.
+    Done,
+}
+
+/// The [Leg] that a [HomingRun] is currently running.
+struct RunningLeg {
+    leg: Leg,
+    /// The move that the motors were told to make for the leg.
+    wheel_move: WheelMove,
+    /// The index of the first waypoint that's still to reach, for reporting progress.
+    waypoint: usize,
+}
+
+/// The waypoints and current leg of a homing run, which the device actor drives through one leg
+///   at a time, via [Robot::drive_home()].
+pub struct HomingRun {
+    /// The waypoints to drive through, ending with the start.
+    waypoints: Vec<(f32, f32)>,
+    stage: Stage,
+    /// The [Leg] that's running, if any.
+    leg: Option<RunningLeg>,
+}
+
+impl HomingRun {
+    /// Constructs a new [HomingRun], which hasn't started on its first leg yet.
+    ///
+    /// Parameters:
+    /// * `waypoints`: the waypoints to drive through, ending with the start
+    fn new(waypoints: Vec<(f32, f32)>) -> HomingRun {
+        HomingRun {
+            waypoints,
+            stage: Stage::TurnTo(0),
+            leg: None,
+        }
+    }
+
+    /// Plans the next [Leg], from the specified [Pose]: turning to face each waypoint in turn,
+    ///   driving straight to it, and finally turning to face the starting heading.
+    ///
+    /// Returns the [Leg], along with the index of the first waypoint that's still to reach, or
+    ///   [None] once there's nothing left to do.
+    ///
+    /// Parameters:
+    /// * `pose`: the robot's current [Pose]
+    fn next_leg(&mut self, pose: &Pose) -> Option<(Leg, usize)> {
+        loop {
+            match self.stage {
+                Stage::TurnTo(index) => {
+                    let waypoint = match self.waypoints.get(index) {
+                        Some(waypoint) => *waypoint,
+                        None => {
+                            self.stage = Stage::FaceStart;
+                            continue;
+                        }
+                    };
+                    if pose.distance_to(waypoint) < ARRIVED_CM {
+                        self.stage = Stage::TurnTo(index + 1);
+                        continue;
+                    }
+
+                    self.stage = Stage::DriveTo(index);
+                    let turn_deg = pose.bearing_to(waypoint);
+                    if turn_deg.abs() >= TURN_TOLERANCE_DEG {
+                        return Some((Leg::Turn(turn_deg), index));
+                    }
+                }
+                Stage::DriveTo(index) => {
+                    self.stage = Stage::TurnTo(index + 1);
+                    let distance_cm = pose.distance_to(self.waypoints[index]);
+                    return Some((Leg::Drive(distance_cm), index));
+                }
+                Stage::FaceStart => {
+                    self.stage = Stage::Done;
+                    let turn_deg = normalize_degrees(-pose.heading_deg);
+                    if turn_deg.abs() >= TURN_TOLERANCE_DEG {
+                        return Some((Leg::Turn(turn_deg), self.waypoints.len()));
+                    }
+                }
+                Stage::Done => return None,
+            }
+        }
+    }
+}
+
+/// Tells the motors to run the specified [Leg], and returns the [WheelMove] to watch it with.
+///
+/// Parameters:
+/// * `ev3_devices`: the [Ev3Devices] to drive
+/// * `leg`: the [Leg] to run
+fn start_leg(ev3_devices: &Ev3Devices, leg: Leg) -> Result<WheelMove, String> {
+    let (left_degrees, right_degrees) = match leg {
+        Leg::Turn(degrees) => {
+            // Turning counter-clockwise runs the left wheel backwards.
+            let wheel_degrees = turn_to_wheel_degrees(degrees);
+            (-wheel_degrees, wheel_degrees)
+        }
+        Leg::Drive(cm) => (cm_to_wheel_degrees(cm), cm_to_wheel_degrees(cm)),
+    };
+
+    let motor_set = &ev3_devices.motor_set;
+    let wheel_degrees = motor_set
+        .get_wheel_degrees()
+        .map_err(|err| Ev3ErrorWrapper::from(err).to_string())?;
+    let wheel_move = WheelMove::new(wheel_degrees, left_degrees, right_degrees, HOMING_SPEED);
+    motor_set
+        .run_to_rel_degrees(
+            &[left_degrees.round() as i32, right_degrees.round() as i32],
+            HOMING_SPEED,
+        )
+        .map_err(|err| Ev3ErrorWrapper::from(err).to_string())?;
+
+    Ok(wheel_move)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Asserts that the specified [Leg] matches the expected one, to within a rounding error, and
+    ///   was planned for the expected waypoint.
+    fn assert_leg(actual: Option<(Leg, usize)>, expected: Leg, expected_waypoint: usize) {
+        let (leg, waypoint) = actual.expect("expected another leg");
+        let close = match (leg, expected) {
+            (Leg::Turn(actual), Leg::Turn(expected)) => (actual - expected).abs() < 0.01,
+            (Leg::Drive(actual), Leg::Drive(expected)) => (actual - expected).abs() < 0.01,
+            _ => false,
+        };
+        assert!(close, "expected {:?}, found {:?}", expected, leg);
+        assert_eq!(waypoint, expected_waypoint);
+    }
+
+    /// Constructs a [Pose] from the specified position and heading.
+    fn pose(x_cm: f32, y_cm: f32, heading_deg: f32) -> Pose {
+        Pose {
+            x_cm,
+            y_cm,
+            heading_deg,
+        }
+    }
+
+    #[test]
+    fn plans_retrace_legs() {
+        // Out along the x axis for 10 cm, then 10 cm to the left, and retraced in reverse.
+        let path = vec![(0.0, 0.0), (10.0, 0.0), (10.0, 10.0)];
+        let mut run = HomingRun::new(path.into_iter().rev().collect());
+
+        // The first waypoint is where the robot already is, so it's skipped.
+        assert_leg(run.next_leg(&pose(10.0, 10.0, 90.0)), Leg::Turn(-180.0), 1);
+        assert_leg(run.next_leg(&pose(10.0, 10.0, -90.0)), Leg::Drive(10.0), 1);
+        assert_leg(run.next_leg(&pose(10.0, 0.0, -90.0)), Leg::Turn(-90.0), 2);
+        assert_leg(run.next_leg(&pose(10.0, 0.0, 180.0)), Leg::Drive(10.0), 2);
+
+        // Then it turns back around to face the way it started out.
+        assert_leg(run.next_leg(&pose(0.0, 0.0, 180.0)), Leg::Turn(-180.0), 3);
+        assert!(run.next_leg(&pose(0.0, 0.0, 0.0)).is_none());
+    }
+
+    #[test]
+    fn plans_direct_legs() {
+        let mut run = HomingRun::new(vec![(0.0, 0.0)]);
+
+        let turn_deg = -180.0 + (4.0f32).atan2(3.0).to_degrees();
+        assert_leg(run.next_leg(&pose(30.0, 40.0, 0.0)), Leg::Turn(turn_deg), 0);
+        assert_leg(
+            run.next_leg(&pose(30.0, 40.0, turn_deg)),
+            Leg::Drive(50.0),
+            0,
+        );
+        assert_leg(
+            run.next_leg(&pose(0.0, 0.0, turn_deg)),
+            Leg::Turn(-turn_deg),
+            1,
+        );
+        assert!(run.next_leg(&pose(0.0, 0.0, 0.0)).is_none());
+    }
+
+    #[test]
+    fn skips_small_turns() {
+        // Already (nearly) facing the start, and (nearly) facing the starting heading once there.
+        let mut run = HomingRun::new(vec![(0.0, 0.0)]);
+
+        assert_leg(run.next_leg(&pose(-20.0, 0.0, 2.0)), Leg::Drive(20.0), 0);
+        assert!(run.next_leg(&pose(0.0, 0.0, 2.0)).is_none());
+    }
+}
@@ -0,0 +1,264 @@
+//! Arbitrates control of the robot between clients: only the client holding the driving [Lease]
+//!   can drive, and every other client is a read-only spectator.
+//!
+//! Clients acquire the lease via `POST /lease`, and then include the token they're given in an
+//!   `X-Lease-Token` header on every request that drives the robot.
+//! The lease is only ever given up explicitly: by its holder via `DELETE /lease`, or by another
+//!   client taking it over via `POST /lease` with `takeover` set.
+
+use actix_web::{delete, get, post, web, HttpRequest, HttpResponse};
+use ev3_common::pairing::Pairing;
+use serde::{Deserialize, Serialize};
+
+use crate::device::{Device, DeviceCommand, Robot};
+use crate::{ControlState, Ev3Devices, Ev3ErrorWrapper, Mode};
+
+/// The HTTP header that clients pass their lease token in.
+const LEASE_TOKEN_HEADER: &str = "X-Lease-Token";
+
+/// The right to drive the robot, which only one client can hold at a time.
+pub struct Lease {
+    /// The secret that identifies the client holding this lease.
+    token: String,
+    /// A human-readable name for the client holding this lease, which is shared with everyone.
+    pub driver: String,
+}
+
+impl Lease {
+    /// Returns `true` if the specified token is the one for this [Lease].
+    pub fn is_held_by(&self, token: Option<&str>) -> bool {
+        token == Some(self.token.as_str())
+    }
+}
+
+/// Returns the lease token that was specified in the request's `X-Lease-Token` header, if any.
+///
+/// The token is returned as an owned [String], so that it can be sent along to the device actor.
+pub fn request_token(request: &HttpRequest) -> Option<String> {
+    request
+        .headers()
+        .get(LEASE_TOKEN_HEADER)
+        .and_then(|token| token.to_str().ok())
+        .map(str::to_string)
+}
+
+/// Returns an error unless the specified token holds the driving lease.
+///
+/// Parameters:
+/// * `control_state`: the current [ControlState]
+/// * `token`: the lease token that the client specified, if any
+pub fn require_lease(control_state: &ControlState, token: Option<&str>) -> actix_web::Result<()> {
+    match &control_state.lease {
+        Some(lease) if lease.is_held_by(token) => Ok(()),
+        Some(lease) => Err(actix_web::error::ErrorForbidden(format!(
+            "{} is driving: take over the lease to drive.",
+            lease.driver
+        ))),
+        None => Err(actix_web::error::ErrorForbidden(
+            "Nobody is driving: acquire the lease to drive.",
+        )),
+    }
+}
+
+/// Models the JSON response for [get_lease()].
+#[derive(Serialize)]
+pub struct LeaseData {
+    /// The name of the client holding the lease, if any.
+    driver: Option<String>,
+    /// Whether or not the requesting client is the one holding the lease.
+    you: bool,
+}
+
+/// This API endpoint returns who (if anyone) holds the driving lease.
+///
+/// Parameters:
+/// * `request`: the HTTP request, which may include the client's lease token
+/// * `device`: the [Device] handle managed/shared by the application
+#[get("/lease")]
+pub async fn get_lease(
+    request: HttpRequest,
+    device: web::Data<Device>,
+) -> actix_web::Result<HttpResponse> {
+    let token = request_token(&request);
+    let lease_data = device
+        .send(|reply| DeviceCommand::GetLease { token, reply })
+        .await?;
+
+    Ok(HttpResponse::Ok().json(lease_data))
+}
+
+/// Models the JSON parameters for [acquire_lease()].
+#[derive(Deserialize)]
+struct AcquireData {
+    /// A human-readable name for the client, which defaults to its IP address.
+    name: Option<String>,
+    /// Whether or not to take the lease over, if another client already holds it.
+    #[serde(default)]
+    takeover: bool,
+}
+
+/// Models the JSON response for [acquire_lease()].
+#[derive(Serialize)]
+pub struct AcquiredData {
+    /// The token to pass in the `X-Lease-Token` header of all driving requests.
+    token: String,
+    driver: String,
+}
+
+/// This API endpoint gives the driving lease to the requesting client, if nobody else holds it
+///   or if it's explicitly taking the lease over.
+/// Taking over the lease stops the robot, so the new driver always starts from a standstill.
+///
+/// Parameters:
+/// * `request`: the HTTP request, which must include the client's session cookie, and may include
+///   its current lease token
+/// * `pairing`: the [Pairing] instance managed/shared by the application
+/// * `device`: the [Device] handle managed/shared by the application
+/// * `acquire_data`: the [AcquireData] parameters specified in the API call
+#[post("/lease")]
+pub async fn acquire_lease(
+    request: HttpRequest,
+    pairing: web::Data<Pairing>,
+    device: web::Data<Device>,
+    acquire_data: web::Json<AcquireData>,
+) -> actix_web::Result<HttpResponse> {
+    pairing.require_session(&request)?;
+    let token = request_token(&request);
+    let AcquireData { name, takeover } = acquire_data.into_inner();
+    let peer = request
+        .peer_addr()
+        .map(|address| address.ip().to_string())
+        .unwrap_or_else(|| "unknown".to_string());
+    let acquired_data = device
+        .send(|reply| DeviceCommand::AcquireLease {
+            token,
+            name,
+            takeover,
+            peer,
+            reply,
+        })
+        .await?;
+
+    Ok(HttpResponse::Ok().json(acquired_data))
+}
+
+/// This API endpoint gives up the driving lease, stopping the robot.
+///
+/// Parameters:
+/// * `request`: the HTTP request, which must include the client's session cookie and lease token
+/// * `pairing`: the [Pairing] instance managed/shared by the application
+/// * `device`: the [Device] handle managed/shared by the application
+#[delete("/lease")]
+pub async fn release_lease(
+    request: HttpRequest,
+    pairing: web::Data<Pairing>,
+    device: web::Data<Device>,
+) -> actix_web::Result<HttpResponse> {
+    pairing.require_session(&request)?;
+    let token = request_token(&request);
+    device
+        .send(|reply| DeviceCommand::ReleaseLease { token, reply })
+        .await?;
+
+    Ok(HttpResponse::Ok().finish().into_body())
+}
+
+impl Robot {
+    /// Returns who (if anyone) holds the driving lease, for [get_lease()].
+    ///
+    /// Parameters:
+    /// * `token`: the lease token that the client specified, if any
+    pub fn get_lease(&self, token: Option<&str>) -> LeaseData {
+        let lease = &self.control_state.lease;
+
+        LeaseData {
+            driver: lease.as_ref().map(|lease| lease.driver.clone()),
+            you: matches!(lease, Some(lease) if lease.is_held_by(token)),
+        }
+    }
+
+    /// Gives the driving lease to the requesting client, for [acquire_lease()].
+    ///
+    /// Parameters:
+    /// * `token`: the lease token that the client specified, if any
+    /// * `name`: the name that the client asked to be known by, if any
+    /// * `takeover`: whether or not to take the lease over, if another client already holds it
+    /// * `peer`: the client's IP address, which is used as its name if it didn't specify one
+    pub fn acquire_lease(
+        &mut self,
+        token: Option<&str>,
+        name: Option<&str>,
+        takeover: bool,
+        peer: &str,
+    ) -> actix_web::Result<AcquiredData> {
+        if let Some(lease) = &self.control_state.lease {
+            if lease.is_held_by(token) {
+                return Ok(AcquiredData {
+                    token: lease.token.clone(),
+                    driver: lease.driver.clone(),
+                });
+            }
+            if !takeover {
+                return Err(actix_web::error::ErrorConflict(format!(
+                    "{} is already driving.",
+                    lease.driver
+                )));
+            }
+
+            println!("Lease: taking over from {}.", lease.driver);
+            stop_driving(&mut self.control_state, &self.ev3_devices)?;
+        }
+
+        let driver = match name.map(str::trim) {
+            Some(name) if !name.is_empty() => name.to_string(),
+            _ => peer.to_string(),
+        };
+        let lease = Lease {
+            token: format!("{:032x}", rand::random::<u128>()),
+            driver,
+        };
+        println!("Lease: acquired by {}.", lease.driver);
+
+        let acquired_data = AcquiredData {
+            token: lease.token.clone(),
+            driver: lease.driver.clone(),
+        };
+        self.control_state.lease = Some(lease);
+        Ok(acquired_data)
+    }
+
+    /// Gives up the driving lease, stopping the robot, for [release_lease()].
+    ///
+    /// Parameters:
+    /// * `token`: the lease token that the client specified, if any
+    pub fn release_lease(&mut self, token: Option<&str>) -> actix_web::Result<()> {
+        require_lease(&self.control_state, token)?;
+
+        stop_driving(&mut self.control_state, &self.ev3_devices)?;
+        if let Some(lease) = self.control_state.lease.take() {
+            println!("Lease: released by {}.", lease.driver);
+        }
+
+        Ok(())
+    }
+}
+
+/// Brakes the robot and shifts it into [Mode::Stop], stopping the autopilot if it's running.
+fn stop_driving(
+    control_state: &mut ControlState,
+    ev3_devices: &Ev3Devices,
+) -> actix_web::Result<()> {
+    control_state
+        .autopilot
+        .stop("the driving lease changed hands");
+    control_state.replay.stop("the driving lease changed hands");
+    control_state.homing.stop("the driving lease changed hands");
+    let motor_set = &ev3_devices.motor_set;
+    motor_set
+        .set_stop_action("brake")
+        .and_then(|_| motor_set.stop())
+        .map_err(Ev3ErrorWrapper::from)?;
+    control_state.mode = Mode::Stop;
+
+    Ok(())
+}
@@ -11,33 +11,136 @@
 //!   (and because it's fast).
 //! The frontend is just the webpage provided by the `./static/index.html` file.
 //!
-//! Everything here is kept to a single file as much as possible,
-//!   for simplicity's sake.
-
-use actix_web::{get, post, web, App, HttpResponse, HttpServer};
+//! The robot's devices and control state are owned by a single device actor (see [device]),
+//!   which every endpoint sends its commands to,
+//!   and each of the bigger features (the lease, the WebSocket, the autopilot, recordings, homing,
+//!   and the emergency stop) lives in its own module.
+
+mod autopilot;
+mod device;
+mod drive;
+mod estop;
+mod homing;
+mod lease;
+mod odometry;
+mod recording;
+mod socket;
+
+use actix_web::{get, post, web, App, HttpRequest, HttpResponse, HttpServer};
 use anyhow::{Context, Result};
-use ev3dev_lang_rust::motors::{LargeMotor, MotorPort};
-use serde::Deserialize;
-use std::time::Duration;
-use tokio::sync::Mutex;
+use autopilot::Autopilot;
+use device::{Device, DeviceCommand, Robot};
+use drive::InputFilter;
+use estop::EStop;
+use ev3_common::device::DeviceError;
+use ev3_common::env::env_var_or;
+use ev3_common::events::{self, Event, EventDetector};
+use ev3_common::guard::{CollisionGuard, GuardAction, Intervention};
+use ev3_common::motors::LargeMotorSet;
+use ev3_common::pairing::{self, Pairing};
+use ev3_common::telemetry::{MotorTelemetry, SensorTelemetry, Sensors};
+use homing::Homing;
+use lease::{require_lease, Lease};
+use odometry::{Odometry, OdometryData};
+use recording::{Recorder, Recording, Recordings, Replay};
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant};
+use tokio::sync::{broadcast, watch};
+
+/// The environment variable that can be used to override [HEARTBEAT_TIMEOUT_DEFAULT], in
+///   milliseconds.
+const HEARTBEAT_TIMEOUT_VAR: &str = "REMOTE_CONTROL_HEARTBEAT_TIMEOUT_MS";
+
+/// How long the robot will keep driving without hearing from the client before it stops itself.
+const HEARTBEAT_TIMEOUT_DEFAULT: Duration = Duration::from_millis(1500);
+
+/// How long the device actor gives the motors to stop before giving up on them, so that a motor
+///   that won't stop can't hold anything up.
+const STOP_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// The environment variable that can be used to adjust how the infrared sensor's proximity
+///   readings are mapped to centimeters, if it's the proximity sensor.
+const IR_CM_PER_PERCENT_VAR: &str = "REMOTE_CONTROL_IR_CM_PER_PERCENT";
 
 /// The main method for the application, which will be run when the application is launched.
 /// It mostly just configures and runs the backend Actix webserver.
 #[actix_web::main]
 async fn main() -> Result<()> {
-    // Initialize application data.
-    let control_state = web::Data::new(Mutex::new(ControlState::new()));
-    let ev3_devices_app = web::Data::new(Mutex::new(Ev3Devices::new()?));
-    let ev3_devices_server = ev3_devices_app.clone();
+    // Stop the robot if the client goes silent while it's driving, e.g. if it loses wifi.
+    // A zero timeout would stop the robot as soon as it started driving.
+    let heartbeat_timeout = Duration::from_millis(env_var_or(
+        HEARTBEAT_TIMEOUT_VAR,
+        HEARTBEAT_TIMEOUT_DEFAULT.as_millis() as u64,
+        |millis| *millis > 0,
+    )?);
+
+    // The device actor owns the Ev3 devices and the control state, and runs everything that
+    //   drives the robot, including the cliff, obstacle, and heartbeat monitors.
+    let device = Device::start(Robot::new(heartbeat_timeout)?);
+    let device_app = web::Data::new(device.clone());
+
+    // Only let clients that have entered the code shown on the brick's screen drive.
+    let pairing = web::Data::new(Pairing::new());
+
+    // Map joystick-style throttle and steering through the configured response curves.
+    let response_curves = web::Data::new(drive::ResponseCurves::from_env()?);
+
+    // Save recorded driving sessions, for replaying later.
+    let recordings = web::Data::new(Recordings::from_env());
+
+    // Sample the telemetry for all of the `/events` subscribers.
+    let events = web::Data::new(events::channel());
+    actix_web::rt::spawn(sample_events(
+        device.clone(),
+        events.clone(),
+        events::telemetry_interval()?,
+    ));
+
+    // Apply the drive commands sent over WebSockets, skipping any that are superseded before
+    //   they can be applied.
+    let (drive_commands_tx, drive_commands_rx) = watch::channel(None);
+    let drive_commands_tx = web::Data::new(drive_commands_tx);
+    actix_web::rt::spawn(socket::apply_drive_commands(
+        drive_commands_rx,
+        device.clone(),
+    ));
 
     HttpServer::new(move || {
         App::new()
-            .app_data(ev3_devices_server.clone())
-            .app_data(control_state.clone())
+            .app_data(device_app.clone())
+            .app_data(drive_commands_tx.clone())
+            .app_data(events.clone())
+            .app_data(pairing.clone())
+            .app_data(response_curves.clone())
+            .app_data(recordings.clone())
             .service(index)
+            .service(pairing::pair)
+            .service(get_state)
+            .service(socket::drive_socket)
             .service(set_mode)
             .service(set_speed)
             .service(set_direction)
+            .service(drive::drive)
+            .service(drive::drive_input)
+            .service(heartbeat)
+            .service(estop::engage_estop)
+            .service(estop::clear_estop)
+            .service(events::events)
+            .service(lease::get_lease)
+            .service(lease::acquire_lease)
+            .service(lease::release_lease)
+            .service(autopilot::start_autopilot)
+            .service(autopilot::stop_autopilot)
+            .service(recording::start_recording)
+            .service(recording::save_recording)
+            .service(recording::discard_recording)
+            .service(recording::list_recordings)
+            .service(recording::get_recording)
+            .service(recording::start_replay)
+            .service(recording::stop_replay)
+            .service(homing::reset_odometry)
+            .service(homing::go_home)
+            .service(homing::stop_home)
     })
     .bind("0.0.0.0:8080")?
     .run()
@@ -45,9 +148,7 @@ async fn main() -> Result<()> {
     .context("Actix server errored.")?;
 
     // Make sure motors get stopped on exit.
-    let motor_set = &ev3_devices_app.lock().await.motor_set;
-    motor_set.stop()?;
-    motor_set.wait_until_not_moving(None);
+    device.shutdown().await?;
 
     Ok(())
 }
@@ -57,11 +158,87 @@ async fn main() -> Result<()> {
 ///
 /// Accessible by browsing to <http://ev3dev.local:8080/> from
 ///   another device on the same network as the EV3.
+/// Clients that haven't paired yet get the `./static/pair.html` page instead.
+///
+/// Parameters:
+/// * `request`: the HTTP request, which may include the client's session cookie
+/// * `pairing`: the [Pairing] instance managed/shared by the application
 #[get("/")]
-async fn index() -> HttpResponse {
+async fn index(request: HttpRequest, pairing: web::Data<Pairing>) -> HttpResponse {
+    let page = if pairing.is_paired(&request) {
+        include_str!("../static/index.html")
+    } else {
+        include_str!("../static/pair.html")
+    };
+
     HttpResponse::Ok()
         .content_type("text/html; charset=utf-8")
-        .body(include_str!("../static/index.html"))
+        .body(page)
+}
+
+/// Models the JSON response for [get_state()], which the device actor publishes after every
+///   command.
+#[derive(Clone, Serialize)]
+pub struct StateData {
+    mode: Mode,
+    speed: u8,
+    direction: i8,
+    timed_out: bool,
+    /// The name of the client holding the driving lease, if any.
+    driver: Option<String>,
+    /// How the collision guard is limiting forward driving, if it is.
+    guard: Option<Intervention>,
+    /// Whether the emergency stop is engaged, and if so, by what.
+    estop: EStop,
+    /// Whether the autopilot is driving, and if so, which of its behaviors is in control.
+    autopilot: Autopilot,
+    /// Whether the driver's changes to the controls are being recorded.
+    recording: bool,
+    /// Whether a recording is being replayed, and if so, which one.
+    replay: Replay,
+    /// Where the robot is, relative to where it started.
+    odometry: OdometryData,
+    /// Whether the robot is driving home, and if so, how far it has left to go.
+    homing: Homing,
+    motors: Vec<MotorTelemetry>,
+    sensors: SensorTelemetry,
+}
+
+/// This API endpoint returns the current [ControlState], along with the actual state of the
+///   motors and sensors, so that clients can sync their controls on load and poll for telemetry.
+///
+/// Parameters:
+/// * `device`: the [Device] handle managed/shared by the application
+#[get("/state")]
+async fn get_state(device: web::Data<Device>) -> HttpResponse {
+    HttpResponse::Ok().json(device.state())
+}
+
+impl Robot {
+    /// Returns the current [StateData], with the motors and sensors as of the last tick.
+    fn state_data(&self) -> StateData {
+        let control_state = &self.control_state;
+
+        StateData {
+            mode: control_state.mode,
+            speed: control_state.speed,
+            direction: control_state.direction,
+            timed_out: control_state.timed_out,
+            driver: control_state
+                .lease
+                .as_ref()
+                .map(|lease| lease.driver.clone()),
+            guard: control_state.guard,
+            estop: control_state.estop.clone(),
+            autopilot: control_state.autopilot.clone(),
+            recording: control_state.recorder.is_some(),
+            replay: control_state.replay.clone(),
+            odometry: control_state.odometry.data(),
+            homing: control_state.homing.clone(),
+            motors: self.motors.clone(),
+            sensors: self.sensors.clone(),
+        }
+    }
 }
 
 /// Models the JSON parameters for [set_mode()].
@@ -74,51 +251,25 @@ struct ModeData {
 /// Switches/shifts the robot into stop, drive, or reverse.
 ///
 /// Parameters:
-/// * `control_state`: the [ControlState] instance managed/shared by the application
-/// * `ev3_devices`: the [Ev3Devices] instance managed/shared by the application
+/// * `request`: the HTTP request, which must include the client's session cookie and lease token
+/// * `pairing`: the [Pairing] instance managed/shared by the application
+/// * `device`: the [Device] handle managed/shared by the application
 /// * `mode_data`: the [ModeData] parameters specified in the API call
 #[post("/mode")]
 async fn set_mode(
-    control_state: web::Data<Mutex<ControlState>>,
-    ev3_devices: web::Data<Mutex<Ev3Devices>>,
+    request: HttpRequest,
+    pairing: web::Data<Pairing>,
+    device: web::Data<Device>,
     mode_data: web::Json<ModeData>,
 ) -> actix_web::Result<HttpResponse> {
-    let mut control_state = control_state.lock().await;
-    let ev3_devices = &ev3_devices.lock().await;
-    let motor_set = &ev3_devices.motor_set;
-
-    match &mode_data.mode {
-        Mode::Stop => {
-            motor_set.set_stop_action("brake")?;
-            motor_set.stop()?;
-
-            control_state.mode = Mode::Stop;
-        }
-        Mode::Forward => {
-            // If switching directions, stop first.
-            if control_state.mode == Mode::Backward {
-                motor_set.set_stop_action("brake")?;
-                motor_set.stop()?;
-                motor_set.wait_until_not_moving(None);
-            }
-
-            control_state.mode = Mode::Forward;
-            apply_control_state(&control_state, ev3_devices)?;
-        }
-        Mode::Backward => {
-            // If switching directions, stop first.
-            if control_state.mode == Mode::Forward {
-                motor_set.set_stop_action("brake")?;
-                motor_set.stop()?;
-                motor_set.wait_until_not_moving(None);
-            }
-
-            control_state.mode = Mode::Backward;
-            apply_control_state(&control_state, ev3_devices)?;
-        }
-    }
-
-    Ok(HttpResponse::Ok().finish().into_body())
+    pairing.require_session(&request)?;
+    let token = lease::request_token(&request);
+    let mode = mode_data.mode;
+    let guard = device
+        .send(|reply| DeviceCommand::SetMode { token, mode, reply })
+        .await?;
+
+    Ok(command_response(guard))
 }
 
 /// Models the JSON parameters for [set_speed()].
@@ -131,27 +282,29 @@ struct SpeedData {
 /// Speeds up or slows down the robot.
 ///
 /// Parameters:
-/// * `control_state`: the [ControlState] instance managed/shared by the application
-/// * `ev3_devices`: the [Ev3Devices] instance managed/shared by the application
+/// * `request`: the HTTP request, which must include the client's session cookie and lease token
+/// * `pairing`: the [Pairing] instance managed/shared by the application
+/// * `device`: the [Device] handle managed/shared by the application
 /// * `speed_data`: the [SpeedData] parameters specified in the API call
 #[post("/speed")]
 async fn set_speed(
-    control_state: web::Data<Mutex<ControlState>>,
-    ev3_devices: web::Data<Mutex<Ev3Devices>>,
+    request: HttpRequest,
+    pairing: web::Data<Pairing>,
+    device: web::Data<Device>,
     speed_data: web::Json<SpeedData>,
 ) -> actix_web::Result<HttpResponse> {
-    let mut control_state = control_state.lock().await;
-    let ev3_devices = &ev3_devices.lock().await;
-
-    // Clamp the specified speed to the allowed/expected range.
-    let mut speed = speed_data.speed;
-    speed = 100.min(speed);
-    speed = 0.max(speed);
-
-    control_state.speed = speed;
-    apply_control_state(&control_state, ev3_devices)?;
+    pairing.require_session(&request)?;
+    let token = lease::request_token(&request);
+    let speed = speed_data.speed;
+    let guard = device
+        .send(|reply| DeviceCommand::SetSpeed {
+            token,
+            speed,
+            reply,
+        })
+        .await?;
 
-    Ok(HttpResponse::Ok().finish().into_body())
+    Ok(command_response(guard))
 }
 
 /// Models the JSON parameters for [set_direction()].
@@ -165,47 +318,254 @@ struct DirectionData {
 ///   by slowing down one of the wheels relative to the other.
 ///
 /// Parameters:
-/// * `control_state`: the [ControlState] instance managed/shared by the application
-/// * `ev3_devices`: the [Ev3Devices] instance managed/shared by the application
+/// * `request`: the HTTP request, which must include the client's session cookie and lease token
+/// * `pairing`: the [Pairing] instance managed/shared by the application
+/// * `device`: the [Device] handle managed/shared by the application
 /// * `direction_data`: the [DirectionData] parameters specified in the API call
 #[post("/direction")]
 async fn set_direction(
-    control_state: web::Data<Mutex<ControlState>>,
-    ev3_devices: web::Data<Mutex<Ev3Devices>>,
+    request: HttpRequest,
+    pairing: web::Data<Pairing>,
+    device: web::Data<Device>,
     direction_data: web::Json<DirectionData>,
 ) -> actix_web::Result<HttpResponse> {
-    let mut control_state = control_state.lock().await;
-    let ev3_devices = &ev3_devices.lock().await;
+    pairing.require_session(&request)?;
+    let token = lease::request_token(&request);
+    let direction = direction_data.direction;
+    let guard = device
+        .send(|reply| DeviceCommand::SetDirection {
+            token,
+            direction,
+            reply,
+        })
+        .await?;
 
-    // Clamp the specified direction to the allowed/expected range.
-    let mut direction = direction_data.direction;
-    direction = 100.min(direction);
-    direction = -100.max(direction);
+    Ok(command_response(guard))
+}
 
-    control_state.direction = direction;
-    apply_control_state(&control_state, ev3_devices)?;
+/// This API endpoint is called periodically by the web application while the robot is driving,
+///   to let the server know that the client is still there.
+/// If neither this nor any other command is received for a while, the robot will stop itself.
+///
+/// Parameters:
+/// * `request`: the HTTP request, which must include the client's session cookie and lease token
+/// * `pairing`: the [Pairing] instance managed/shared by the application
+/// * `device`: the [Device] handle managed/shared by the application
+#[post("/heartbeat")]
+async fn heartbeat(
+    request: HttpRequest,
+    pairing: web::Data<Pairing>,
+    device: web::Data<Device>,
+) -> actix_web::Result<HttpResponse> {
+    pairing.require_session(&request)?;
+    let token = lease::request_token(&request);
+    device
+        .send(|reply| DeviceCommand::Heartbeat { token, reply })
+        .await?;
 
     Ok(HttpResponse::Ok().finish().into_body())
 }
 
+impl Robot {
+    /// Switches/shifts the robot into stop, drive, or reverse, for [set_mode()].
+    /// Returns how the collision guard is limiting forward driving, if it is.
+    ///
+    /// Parameters:
+    /// * `token`: the lease token that the client specified, if any
+    /// * `mode`: the [Mode] to shift into
+    fn set_mode(
+        &mut self,
+        token: Option<&str>,
+        mode: Mode,
+    ) -> actix_web::Result<Option<Intervention>> {
+        let control_state = &mut self.control_state;
+        require_lease(control_state, token)?;
+        let ev3_devices = &self.ev3_devices;
+        let motor_set = &ev3_devices.motor_set;
+        control_state.command_received();
+
+        match mode {
+            Mode::Stop => {
+                motor_set
+                    .set_stop_action("brake")
+                    .and_then(|_| motor_set.stop())
+                    .map_err(Ev3ErrorWrapper::from)?;
+
+                control_state.mode = Mode::Stop;
+                control_state.guard = None;
+                control_state.record_controls();
+            }
+            Mode::Forward => {
+                // If switching directions, stop first.
+                if control_state.mode == Mode::Backward {
+                    brake_to_stop(control_state, ev3_devices)?;
+                }
+
+                control_state.mode = Mode::Forward;
+                control_state.record_controls();
+                apply_control_state(control_state, ev3_devices)?;
+            }
+            Mode::Backward => {
+                // If switching directions, stop first.
+                if control_state.mode == Mode::Forward {
+                    brake_to_stop(control_state, ev3_devices)?;
+                }
+
+                control_state.mode = Mode::Backward;
+                control_state.record_controls();
+                apply_control_state(control_state, ev3_devices)?;
+            }
+        }
+
+        Ok(control_state.guard)
+    }
+
+    /// Speeds up or slows down the robot, for [set_speed()].
+    /// Returns how the collision guard is limiting forward driving, if it is.
+    ///
+    /// Parameters:
+    /// * `token`: the lease token that the client specified, if any
+    /// * `speed`: the speed to drive at, which is clamped to `0` to `100`
+    fn set_speed(
+        &mut self,
+        token: Option<&str>,
+        speed: u8,
+    ) -> actix_web::Result<Option<Intervention>> {
+        let control_state = &mut self.control_state;
+        require_lease(control_state, token)?;
+        control_state.command_received();
+
+        // Clamp the specified speed to the allowed/expected range.
+        let mut speed = speed;
+        speed = 100.min(speed);
+        speed = 0.max(speed);
+
+        control_state.speed = speed;
+        control_state.record_controls();
+        apply_control_state(control_state, &self.ev3_devices)?;
+
+        Ok(control_state.guard)
+    }
+
+    /// Adjusts the robot's vector to the left or right, for [set_direction()].
+    /// Returns how the collision guard is limiting forward driving, if it is.
+    ///
+    /// Parameters:
+    /// * `token`: the lease token that the client specified, if any
+    /// * `direction`: the direction to steer in, which is clamped to `-100` to `100`
+    fn set_direction(
+        &mut self,
+        token: Option<&str>,
+        direction: i8,
+    ) -> actix_web::Result<Option<Intervention>> {
+        let control_state = &mut self.control_state;
+        require_lease(control_state, token)?;
+        control_state.command_received();
+
+        // Clamp the specified direction to the allowed/expected range.
+        let direction = direction.max(-100).min(100);
+
+        control_state.direction = direction;
+        control_state.record_controls();
+        apply_control_state(control_state, &self.ev3_devices)?;
+
+        Ok(control_state.guard)
+    }
+
+    /// Records a heartbeat from the driver, for [heartbeat()].
+    ///
+    /// Parameters:
+    /// * `token`: the lease token that the client specified, if any
+    fn heartbeat(&mut self, token: Option<&str>) -> actix_web::Result<()> {
+        require_lease(&self.control_state, token)?;
+        self.control_state.heartbeat_received();
+
+        Ok(())
+    }
+}
+
+/// Returns the response for a driving command that was applied successfully.
+///
+/// If the collision guard is slowing the robot down, the [Intervention] is included as JSON,
+///   e.g. `{ "guard": { "action": "slow", ... } }`, so that the client can tell the user why.
+///
+/// Parameters:
+/// * `guard`: how the collision guard is limiting forward driving, if it is
+fn command_response(guard: Option<Intervention>) -> HttpResponse {
+    match guard {
+        Some(intervention) => HttpResponse::Ok().json(serde_json::json!({ "guard": intervention })),
+        None => HttpResponse::Ok().finish().into_body(),
+    }
+}
+
 /// Updates the motor settings to match the specified [ControlState].
 ///
+/// If the emergency stop is engaged, or the robot would drive forwards over a cliff or into an
+///   obstacle, it's stopped and shifted into [Mode::Stop] instead, and an error is returned.
+/// If it would drive forwards towards a nearby obstacle, it's slowed down, without changing the
+///   [ControlState]'s speed, and the reason is recorded in [ControlState::guard].
+///
 /// Parameters:
 /// * `control_state`: the desired [ControlState]
 /// * `ev3_devices`: the [Ev3Devices] to update
 fn apply_control_state(
-    control_state: &ControlState,
+    control_state: &mut ControlState,
     ev3_devices: &Ev3Devices,
-) -> std::result::Result<(), Ev3ErrorWrapper> {
+) -> actix_web::Result<()> {
     let motor_set = &ev3_devices.motor_set;
 
+    if control_state.mode != Mode::Stop && control_state.estop.engaged {
+        motor_set
+            .set_stop_action("brake")
+            .and_then(|_| motor_set.stop())
+            .map_err(Ev3ErrorWrapper::from)?;
+        control_state.mode = Mode::Stop;
+
+        return control_state.estop.require_clear();
+    }
+
+    if control_state.mode == Mode::Forward && ev3_devices.is_cliff()? {
+        motor_set
+            .set_stop_action("brake")
+            .and_then(|_| motor_set.stop())
+            .map_err(Ev3ErrorWrapper::from)?;
+        control_state.mode = Mode::Stop;
+
+        return Err(actix_web::error::ErrorConflict(
+            "Cliff detected: refusing to drive forward.",
+        ));
+    }
+
+    // Only forward driving can run into whatever's in front of the robot.
+    control_state.guard = match control_state.mode {
+        Mode::Forward => ev3_devices.check_guard()?,
+        _ => None,
+    };
+    if let Some(intervention) = control_state.guard {
+        if intervention.action == GuardAction::Stop {
+            motor_set
+                .set_stop_action("brake")
+                .and_then(|_| motor_set.stop())
+                .map_err(Ev3ErrorWrapper::from)?;
+            control_state.mode = Mode::Stop;
+
+            return Err(actix_web::error::ErrorConflict(format!(
+                "{}: refusing to drive forward.",
+                intervention.describe()
+            )));
+        }
+    }
+    let guard_factor = control_state
+        .guard
+        .map_or(1.0, |intervention| intervention.speed_factor);
+
     // Pre-calculate all of the wheel speed components.
     let speed_multipler = match control_state.mode {
         Mode::Backward => -1.0,
         _ => 1.0,
     };
     let speed_max_absolute = 900.0;
-    let speed_percent: f32 = 1.0f32.min((control_state.speed as f32) / 100.0f32);
+    let speed_percent: f32 = 1.0f32.min((control_state.speed as f32) / 100.0f32) * guard_factor;
     let direction_percents = if control_state.direction > 0 {
         let left_wheel_percent = 1.0;
         let right_wheel_percent =
@@ -228,24 +588,206 @@ fn apply_control_state(
         //    "speed_multipler: {}, speed_percent: {}, direction_percents: {:?}, speed_sp: {}",
         //    speed_multipler, speed_percent, direction_percents, speed_sp
         //);
-        motor.set_speed_sp(speed_sp)?;
+        motor
+            .set_speed_sp(speed_sp)
+            .map_err(Ev3ErrorWrapper::from)?;
     }
 
     // Stop/start the motors.
     match control_state.mode {
         Mode::Stop => {
-            motor_set.set_stop_action("brake")?;
-            motor_set.stop()?;
-            motor_set.wait_until_not_moving(None);
+            motor_set
+                .set_stop_action("brake")
+                .and_then(|_| motor_set.stop())
+                .map_err(Ev3ErrorWrapper::from)?;
         }
+        // Leave the motors be until they've finished stopping: [Robot::finish_stopping()] will
+        //   start them once they have.
+        _ if control_state.stopping_since.is_some() => {}
         _ => {
-            motor_set.run_forever()?;
+            motor_set.run_forever().map_err(Ev3ErrorWrapper::from)?;
         }
     }
 
     Ok(())
 }
 
+/// Brakes the robot, so that it comes to a stop before it drives again, e.g. before reversing.
+///
+/// This doesn't wait for the motors to stop, which would hold up the device actor: instead,
+///   [apply_control_state()] leaves the motors be (and the autopilot, replays, and homing all
+///   wait) until [Robot::finish_stopping()] sees that they have.
+///
+/// Parameters:
+/// * `control_state`: the current [ControlState]
+/// * `ev3_devices`: the [Ev3Devices] to brake
+fn brake_to_stop(
+    control_state: &mut ControlState,
+    ev3_devices: &Ev3Devices,
+) -> Result<(), Ev3ErrorWrapper> {
+    let motor_set = &ev3_devices.motor_set;
+    motor_set
+        .set_stop_action("brake")
+        .and_then(|_| motor_set.stop())?;
+    control_state.stopping_since = Some(Instant::now());
+
+    Ok(())
+}
+
+impl Robot {
+    /// Called on each tick, to pick up where [brake_to_stop()] left off once the motors have
+    ///   stopped, by applying the [ControlState] that was set in the meantime.
+    ///
+    /// If the motors still haven't stopped after [STOP_TIMEOUT], the robot is shifted into
+    ///   [Mode::Stop] instead, rather than driving on regardless.
+    fn finish_stopping(&mut self) {
+        let stopping_since = match self.control_state.stopping_since {
+            Some(stopping_since) => stopping_since,
+            None => return,
+        };
+        let stopped = match self.ev3_devices.motor_set.is_running() {
+            Ok(true) if stopping_since.elapsed() < STOP_TIMEOUT => return,
+            Ok(true) => Err(format!(
+                "the motors were still moving after {:?}",
+                STOP_TIMEOUT
+            )),
+            Ok(false) => Ok(()),
+            Err(err) => Err(Ev3ErrorWrapper::from(err).to_string()),
+        };
+
+        let control_state = &mut self.control_state;
+        control_state.stopping_since = None;
+        if let Err(err) = stopped {
+            eprintln!("Stopping: {}; staying stopped.", err);
+            control_state.mode = Mode::Stop;
+            control_state.guard = None;
+        }
+        if let Err(err) = apply_control_state(control_state, &self.ev3_devices) {
+            eprintln!("Stopping: {}", err);
+        }
+    }
+}
+
+impl Robot {
+    /// Called on each tick, to stop the robot whenever it's driving forwards towards a cliff, no
+    ///   matter which control brought it there.
+    fn monitor_cliff(&mut self) {
+        if self.ev3_devices.sensors.cliff_detector.is_none() {
+            return;
+        }
+
+        let cliff = match self.ev3_devices.is_cliff() {
+            Ok(cliff) => cliff,
+            Err(err) => {
+                eprintln!("Cliff monitor: {}", err);
+                return;
+            }
+        };
+
+        if cliff && self.control_state.mode == Mode::Forward {
+            println!("Cliff monitor: cliff detected, stopping.");
+            let motor_set = &self.ev3_devices.motor_set;
+            if let Err(err) = motor_set
+                .set_stop_action("brake")
+                .and_then(|_| motor_set.stop())
+                .map_err(Ev3ErrorWrapper::from)
+            {
+                eprintln!("Cliff monitor: {}", err);
+            }
+            self.control_state.mode = Mode::Stop;
+        }
+    }
+
+    /// Called on each tick, to slow down or stop the robot whenever it's driving forwards towards
+    ///   an obstacle, no matter which control brought it there.
+    fn monitor_obstacles(&mut self) {
+        if self.ev3_devices.guard.is_none() || self.control_state.mode != Mode::Forward {
+            return;
+        }
+
+        let intervention = match self.ev3_devices.check_guard() {
+            Ok(intervention) => intervention,
+            Err(err) => {
+                eprintln!("Collision guard: {}", err);
+                return;
+            }
+        };
+
+        // Re-apply the controls whenever the guard's verdict changes, e.g. to slow down further,
+        //   or to speed back up once the obstacle's gone.
+        if !Intervention::same_effect(&intervention, &self.control_state.guard) {
+            if let Err(err) = apply_control_state(&mut self.control_state, &self.ev3_devices) {
+                println!("Collision guard: {}", err);
+            }
+        }
+    }
+
+    /// Called on each tick, to stop the robot whenever it's driving but hasn't heard from the
+    ///   client within the [Robot::heartbeat_timeout].
+    fn monitor_heartbeat(&mut self) {
+        let control_state = &mut self.control_state;
+        if control_state.mode == Mode::Stop
+            || control_state.last_heard.elapsed() < self.heartbeat_timeout
+        {
+            return;
+        }
+        // Replays drive without a client, just like the autopilot.
+        if control_state.replay.running {
+            return;
+        }
+
+        println!(
+            "Heartbeat monitor: nothing heard from the client in {:?}, stopping.",
+            self.heartbeat_timeout
+        );
+        let motor_set = &self.ev3_devices.motor_set;
+        if let Err(err) = motor_set
+            .set_stop_action("brake")
+            .and_then(|_| motor_set.stop())
+            .map_err(Ev3ErrorWrapper::from)
+        {
+            eprintln!("Heartbeat monitor: {}", err);
+        }
+        control_state.mode = Mode::Stop;
+        control_state.timed_out = true;
+    }
+}
+
+/// Runs forever in the background, sampling the telemetry and publishing it (along with any
+///   discrete events detected in it) to all of the `/events` subscribers.
+///
+/// The samples are taken from the [StateData] that the device actor publishes, so sampling never
+///   has to wait on it.
+///
+/// Parameters:
+/// * `device`: the [Device] handle to read the [StateData] from
+/// * `events`: the channel to publish [Event]s to
+/// * `sample_interval`: how often to sample the telemetry
+async fn sample_events(
+    device: Device,
+    events: web::Data<broadcast::Sender<Event>>,
+    sample_interval: Duration,
+) {
+    let mut detector = EventDetector::new();
+    let mut interval = actix_web::rt::time::interval(sample_interval);
+    loop {
+        interval.tick().await;
+
+        // Don't bother reading anything if nobody's listening.
+        if events.receiver_count() == 0 {
+            continue;
+        }
+
+        let state = device.state();
+
+        // Sending only fails if all of the subscribers have since disconnected, which is fine.
+        for event in detector.detect(&state.mode, &state.motors, &state.sensors) {
+            let _ = events.send(event);
+        }
+        let _ = events.send(Event::new("telemetry", &state));
+    }
+}
+
 /// A local wrapper of [ev3dev_lang_rust::Ev3Error], which is required so that we can implement
 /// Actix's [actix_web::error::ResponseError] for it.
 #[derive(Debug)]
@@ -274,73 +816,48 @@ impl std::error::Error for Ev3ErrorWrapper {
 
 impl actix_web::error::ResponseError for Ev3ErrorWrapper {}
 
-/// The EV3 devices that will be used and shared by the application..
+impl From<Ev3ErrorWrapper> for DeviceError {
+    fn from(err: Ev3ErrorWrapper) -> Self {
+        err.cause.into()
+    }
+}
+
+/// The EV3 devices used by the application, which are owned by the device actor.
 struct Ev3Devices {
     motor_set: LargeMotorSet,
+    sensors: Sensors,
+    guard: Option<CollisionGuard>,
 }
 
 impl Ev3Devices {
     /// Constructs an [Ev3Devices] for the application to use.
     pub fn new() -> std::result::Result<Ev3Devices, Ev3ErrorWrapper> {
+        let sensors = Sensors::find(IR_CM_PER_PERCENT_VAR);
+        let guard = CollisionGuard::find(&sensors);
+
         Ok(Ev3Devices {
-            motor_set: LargeMotorSet {
-                motors: vec![
-                    LargeMotor::get(MotorPort::OutB).map_err(|cause| Ev3ErrorWrapper { cause })?,
-                    LargeMotor::get(MotorPort::OutC).map_err(|cause| Ev3ErrorWrapper { cause })?,
-                ],
-            },
+            motor_set: LargeMotorSet::new()?,
+            sensors,
+            guard,
         })
     }
-}
-
-/// Represents a set of [LargeMotor]s that ought to be managed in concert.
-struct LargeMotorSet {
-    motors: Vec<LargeMotor>,
-}
-
-impl LargeMotorSet {
-    /// Proxies [LargeMotor::set_stop_action()].
-    pub fn set_stop_action(&self, stop_action: &str) -> std::result::Result<(), Ev3ErrorWrapper> {
-        for motor in &self.motors {
-            motor
-                .set_stop_action(stop_action)
-                .map_err(|cause| Ev3ErrorWrapper { cause })?;
-        }
-
-        Ok(())
-    }
 
-    /// Proxies [LargeMotor::stop()].
-    pub fn stop(&self) -> std::result::Result<(), Ev3ErrorWrapper> {
-        for motor in &self.motors {
-            motor.stop().map_err(|cause| Ev3ErrorWrapper { cause })?;
+    /// Returns `true` if a cliff is detected, which will always be `false` if there's no color
+    ///   sensor plugged in.
+    pub fn is_cliff(&self) -> std::result::Result<bool, Ev3ErrorWrapper> {
+        match &self.sensors.cliff_detector {
+            Some(cliff_detector) => Ok(cliff_detector.is_cliff()?),
+            None => Ok(false),
         }
-
-        Ok(())
     }
 
-    /// Proxies [LargeMotor::run_forever].
-    pub fn run_forever(&self) -> std::result::Result<(), Ev3ErrorWrapper> {
-        for motor in &self.motors {
-            motor
-                .run_forever()
-                .map_err(|cause| Ev3ErrorWrapper { cause })?;
+    /// Returns how the [CollisionGuard] needs to limit forward driving, if at all, which will
+    ///   always be `None` if the guard is disabled.
+    pub fn check_guard(&self) -> std::result::Result<Option<Intervention>, Ev3ErrorWrapper> {
+        match &self.guard {
+            Some(guard) => Ok(guard.check(&self.sensors)?),
+            None => Ok(None),
         }
-
-        Ok(())
-    }
-
-    /// Proxies [LargeMotor::wait_until_not_moving()].
-    pub fn wait_until_not_moving(&self, timeout: Option<Duration>) -> bool {
-        let mut result = true;
-        for motor in &self.motors {
-            result = match motor.wait_until_not_moving(timeout) {
-                true => result,
-                false => false,
-            };
-        }
-
-        result
     }
 }
 
@@ -349,6 +866,37 @@ struct ControlState {
     mode: Mode,
     speed: u8,
     direction: i8,
+    /// When a command or heartbeat was last received from the client.
+    last_heard: Instant,
+    /// Whether the robot was stopped because the client went silent, which is reported to the
+    ///   client until it sends another command.
+    timed_out: bool,
+    /// When the robot started braking, if it still has to finish stopping before it can drive
+    ///   again (see [brake_to_stop()]).
+    stopping_since: Option<Instant>,
+    /// The driving lease, if any client currently holds it.
+    lease: Option<Lease>,
+    /// How the collision guard last limited forward driving, if it did.
+    guard: Option<Intervention>,
+    /// The emergency stop, which refuses to let the robot drive while it's engaged.
+    estop: EStop,
+    /// Smooths the gamepad input streamed to [drive::drive_input()].
+    input_filter: InputFilter,
+    /// The state of the autopilot, which drives the robot instead of the client while it's
+    ///   running.
+    autopilot: Autopilot,
+    /// Records the driver's changes to the controls, while recording.
+    recorder: Option<Recorder>,
+    /// The last [Recording], from when recording stopped until it's been saved.
+    unsaved_recording: Option<Recording>,
+    /// The state of replaying recordings, which drive the robot instead of the client while
+    ///   they're running.
+    replay: Replay,
+    /// Tracks where the robot is, relative to where it started.
+    odometry: Odometry,
+    /// The state of driving home, which drives the robot instead of the client while it's
+    ///   running.
+    homing: Homing,
 }
 
 impl ControlState {
@@ -358,12 +906,49 @@ impl ControlState {
             mode: Mode::Stop,
             speed: 0,
             direction: 0,
+            last_heard: Instant::now(),
+            timed_out: false,
+            stopping_since: None,
+            lease: None,
+            guard: None,
+            estop: EStop::new(),
+            input_filter: InputFilter::new(),
+            autopilot: Autopilot::new(),
+            recorder: None,
+            unsaved_recording: None,
+            replay: Replay::new(),
+            odometry: Odometry::new(),
+            homing: Homing::new(),
         }
     }
+
+    /// Records that a command was just received from the client.
+    /// As the client is now driving manually, this also takes over from the autopilot, or from
+    ///   any replay or homing run.
+    pub fn command_received(&mut self) {
+        self.autopilot.stop("taken over manually");
+        self.replay.stop("taken over manually");
+        self.homing.stop("taken over manually");
+        self.heartbeat_received();
+        self.timed_out = false;
+    }
+
+    /// Records the current controls, if the driver's changes to them are being recorded.
+    /// This must be called whenever the driver changes the mode, speed, or direction.
+    pub fn record_controls(&mut self) {
+        if let Some(recorder) = &mut self.recorder {
+            recorder.record(self.mode, self.speed, self.direction);
+        }
+    }
+
+    /// Records that a heartbeat was just received from the client.
+    pub fn heartbeat_received(&mut self) {
+        self.last_heard = Instant::now();
+    }
 }
 
 /// Models the different driving modes/gears.
-#[derive(Deserialize, Eq, PartialEq)]
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, Eq, PartialEq)]
 enum Mode {
     Stop,
     Forward,
@@ -0,0 +1,341 @@
+//! Tracks where the robot is, relative to where the session started, by integrating the wheel
+//!   positions ("dead reckoning").
+//!
+//! The starting point is the origin, facing along the x axis, with headings measured in degrees
+//!   counter-clockwise (to the left).
+//! Along the way, a breadcrumb trail of positions is kept, so that the robot can retrace its path
+//!   back to the start (see [crate::homing]), along with whether any obstacles were seen.
+//!
+//! Wheel slip means the tracked pose will drift over time, particularly while turning, so this is
+//!   only good for getting roughly back to where the robot started.
+
+use ev3_common::moves::{AXLE_TRACK_CM, WHEEL_DIAMETER_CM};
+use serde::Serialize;
+
+use crate::device::Robot;
+use crate::Ev3ErrorWrapper;
+
+/// How far apart (in centimeters) the breadcrumbs along the path are dropped.
+const PATH_SPACING_CM: f32 = 10.0;
+
+/// Where the robot is, and which way it's facing.
+#[derive(Clone, Copy, Debug, Default, Serialize)]
+pub struct Pose {
+    pub x_cm: f32,
+    pub y_cm: f32,
+    /// Which way the robot is facing, in degrees counter-clockwise from the starting heading,
+    ///   from `-180.0` to `180.0`.
+    pub heading_deg: f32,
+}
+
+impl Pose {
+    /// Returns the straight-line distance from this [Pose] to the specified point, in
+    ///   centimeters.
+    pub fn distance_to(&self, point: (f32, f32)) -> f32 {
+        (point.0 - self.x_cm).hypot(point.1 - self.y_cm)
+    }
+
+    /// Returns how far (in degrees, counter-clockwise) the robot would have to turn in place to
+    ///   face the specified point.
+    pub fn bearing_to(&self, point: (f32, f32)) -> f32 {
+        let bearing = (point.1 - self.y_cm)
+            .atan2(point.0 - self.x_cm)
+            .to_degrees();
+        normalize_degrees(bearing - self.heading_deg)
+    }
+}
+
+/// Wraps the specified angle into the range `-180.0` to `180.0` degrees.
+pub fn normalize_degrees(degrees: f32) -> f32 {
+    let degrees = degrees % 360.0;
+    if degrees > 180.0 {
+        degrees - 360.0
+    } else if degrees < -180.0 {
+        degrees + 360.0
+    } else {
+        degrees
+    }
+}
+
+/// Tracks the robot's [Pose] and path, from the wheel positions.
+pub struct Odometry {
+    /// The (left, right) wheel positions when last read, in degrees.
+    last_wheel_degrees: Option<(f32, f32)>,
+    pose: Pose,
+    /// The breadcrumbs dropped along the path so far, starting with the origin.
+    path: Vec<(f32, f32)>,
+    /// Whether any obstacles have been seen along the path.
+    obstacles_seen: bool,
+}
+
+impl Odometry {
+    /// Constructs a new [Odometry], at the origin.
+    pub fn new() -> Odometry {
+        Odometry {
+            last_wheel_degrees: None,
+            pose: Pose::default(),
+            path: vec![(0.0, 0.0)],
+            obstacles_seen: false,
+        }
+    }
+
+    /// Returns the current [Pose].
+    pub fn pose(&self) -> Pose {
+        self.pose
+    }
+
+    /// Returns the breadcrumbs dropped along the path so far, starting with the origin.
+    pub fn path(&self) -> &[(f32, f32)] {
+        &self.path
+    }
+
+    /// Returns `true` if any obstacles have been seen along the path.
+    pub fn obstacles_seen(&self) -> bool {
+        self.obstacles_seen
+    }
+
+    /// Makes the robot's current position and heading the new origin, forgetting the path.
+    pub fn reset(&mut self) {
+        self.pose = Pose::default();
+        self.arrived_home();
+    }
+
+    /// Forgets the path, e.g. once the robot has made it back to the origin.
+    pub fn arrived_home(&mut self) {
+        self.path = vec![(0.0, 0.0)];
+        self.obstacles_seen = false;
+    }
+
+    /// Updates the [Pose] per the wheels' movement since the last update.
+    ///
+    /// Parameters:
+    /// * `wheel_degrees`: the current (left, right) wheel positions, in degrees
+    /// * `drop_breadcrumbs`: whether to extend the path, which should be `false` while homing, so
+    ///   that the way back doesn't get added to the way out
+    fn update(&mut self, wheel_degrees: (f32, f32), drop_breadcrumbs: bool) {
+        let last_wheel_degrees = match self.last_wheel_degrees.replace(wheel_degrees) {
+            Some(last_wheel_degrees) => last_wheel_degrees,
+            None => return,
+        };
+
+        let wheel_cm = |degrees: f32| degrees / 360.0 * std::f32::consts::PI * WHEEL_DIAMETER_CM;
+        let left_cm = wheel_cm(wheel_degrees.0 - last_wheel_degrees.0);
+        let right_cm = wheel_cm(wheel_degrees.1 - last_wheel_degrees.1);
+        if left_cm == 0.0 && right_cm == 0.0 {
+            return;
+        }
+
+        // Treat the movement as an arc, and step along its average heading.
+        let distance_cm = (left_cm + right_cm) / 2.0;
+        let turn_deg = ((right_cm - left_cm) / AXLE_TRACK_CM).to_degrees();
+        let heading = (self.pose.heading_deg + turn_deg / 2.0).to_radians();
+        self.pose.x_cm += distance_cm * heading.cos();
+        self.pose.y_cm += distance_cm * heading.sin();
+        self.pose.heading_deg = normalize_degrees(self.pose.heading_deg + turn_deg);
+
+        if drop_breadcrumbs {
+            let last_breadcrumb = self.path[self.path.len() - 1];
+            if self.pose.distance_to(last_breadcrumb) >= PATH_SPACING_CM {
+                self.path.push((self.pose.x_cm, self.pose.y_cm));
+            }
+        }
+    }
+
+    /// Returns the [OdometryData] to report to clients.
+    pub fn data(&self) -> OdometryData {
+        OdometryData {
+            pose: self.pose,
+            distance_from_start_cm: self.pose.distance_to((0.0, 0.0)),
+            path_length_cm: self
+                .path
+                .windows(2)
+                .map(|leg| (leg[1].0 - leg[0].0).hypot(leg[1].1 - leg[0].1))
+                .sum::<f32>()
+                + self.pose.distance_to(self.path[self.path.len() - 1]),
+            obstacles_seen: self.obstacles_seen,
+        }
+    }
+}
+
+/// Models the [Odometry] as reported to clients, as part of [crate::StateData].
+#[derive(Clone, Serialize)]
+pub struct OdometryData {
+    pose: Pose,
+    /// The straight-line distance back to the start, in centimeters.
+    distance_from_start_cm: f32,
+    /// How far the robot has driven from the start, in centimeters, as the breadcrumbs fly.
+    path_length_cm: f32,
+    /// Whether any obstacles have been seen along the path.
+    obstacles_seen: bool,
+}
+
+impl Robot {
+    /// Updates the [crate::ControlState]'s [Odometry] from the wheel positions, on every tick of
+    ///   the device actor, no matter what's driving the robot.
+    ///
+    /// While the robot is moving, an obstacle is considered seen whenever the collision guard
+    ///   would slow down or stop forward driving, or a cliff is detected.
+    pub fn track_odometry(&mut self) {
+        let control_state = &mut self.control_state;
+        let ev3_devices = &self.ev3_devices;
+        let wheel_degrees = match ev3_devices
+            .motor_set
+            .get_wheel_degrees()
+            .map_err(Ev3ErrorWrapper::from)
+        {
+            Ok(wheel_degrees) => wheel_degrees,
+            Err(err) => {
+                eprintln!("Odometry: {}", err);
+                return;
+            }
+        };
+
+        let homing = control_state.homing.running;
+        let moved = control_state.odometry.last_wheel_degrees != Some(wheel_degrees);
+        control_state.odometry.update(wheel_degrees, !homing);
+        if !moved || control_state.odometry.obstacles_seen {
+            return;
+        }
+
+        let obstacle = ev3_devices
+            .check_guard()
+            .and_then(|intervention| Ok(intervention.is_some() || ev3_devices.is_cliff()?));
+        match obstacle {
+            Ok(true) => {
+                let pose = control_state.odometry.pose;
+                println!(
+                    "Odometry: obstacle seen at ({:.0} cm, {:.0} cm).",
+                    pose.x_cm, pose.y_cm
+                );
+                control_state.odometry.obstacles_seen = true;
+            }
+            Ok(false) => {}
+            Err(err) => eprintln!("Odometry: {}", err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ev3_common::moves::{cm_to_wheel_degrees, turn_to_wheel_degrees};
+
+    use super::*;
+
+    /// Asserts that the specified values are within a rounding error of each other.
+    fn assert_close(actual: f32, expected: f32) {
+        assert!(
+            (actual - expected).abs() < 0.01,
+            "expected {}, found {}",
+            expected,
+            actual
+        );
+    }
+
+    /// Constructs a new [Odometry], with its first wheel positions (both `0`) already read.
+    fn odometry() -> Odometry {
+        let mut odometry = Odometry::new();
+        odometry.update((0.0, 0.0), true);
+        odometry
+    }
+
+    #[test]
+    fn normalizes_degrees() {
+        assert_close(normalize_degrees(0.0), 0.0);
+        assert_close(normalize_degrees(180.0), 180.0);
+        assert_close(normalize_degrees(-180.0), -180.0);
+        assert_close(normalize_degrees(190.0), -170.0);
+        assert_close(normalize_degrees(-190.0), 170.0);
+        assert_close(normalize_degrees(720.0), 0.0);
+        assert_close(normalize_degrees(-450.0), -90.0);
+    }
+
+    #[test]
+    fn ignores_first_reading() {
+        // The wheels may not start at `0`, so the first reading is just a baseline.
+        let mut odometry = Odometry::new();
+        odometry.update((1000.0, -1000.0), true);
+        let pose = odometry.pose();
+        assert_close(pose.x_cm, 0.0);
+        assert_close(pose.y_cm, 0.0);
+        assert_close(pose.heading_deg, 0.0);
+    }
+
+    #[test]
+    fn tracks_straight_line() {
+        let mut odometry = odometry();
+        let degrees = cm_to_wheel_degrees(50.0);
+        odometry.update((degrees, degrees), true);
+
+        let pose = odometry.pose();
+        assert_close(pose.x_cm, 50.0);
+        assert_close(pose.y_cm, 0.0);
+        assert_close(pose.heading_deg, 0.0);
+    }
+
+    #[test]
+    fn tracks_turn_in_place() {
+        let mut odometry = odometry();
+        let degrees = turn_to_wheel_degrees(90.0);
+        odometry.update((-degrees, degrees), true);
+
+        let pose = odometry.pose();
+        assert_close(pose.x_cm, 0.0);
+        assert_close(pose.y_cm, 0.0);
+        assert_close(pose.heading_deg, 90.0);
+    }
+
+    #[test]
+    fn tracks_arc() {
+        // Pivot a quarter turn to the left around the (stationary) left wheel, in small steps,
+        //   which swings the middle of the axle around a circle half the axle track across.
+        let mut odometry = odometry();
+        let right_degrees = turn_to_wheel_degrees(90.0) * 2.0;
+        for step in 1..=90 {
+            odometry.update((0.0, right_degrees * step as f32 / 90.0), true);
+        }
+
+        let pose = odometry.pose();
+        assert_close(pose.x_cm, AXLE_TRACK_CM / 2.0);
+        assert_close(pose.y_cm, AXLE_TRACK_CM / 2.0);
+        assert_close(pose.heading_deg, 90.0);
+    }
+
+    #[test]
+    fn drops_breadcrumbs() {
+        let mut odometry = odometry();
+        for cm in 1..=35 {
+            let degrees = cm_to_wheel_degrees(cm as f32);
+            odometry.update((degrees, degrees), true);
+        }
+
+        let path = odometry.path();
+        assert_eq!(path.len(), 4);
+        for (breadcrumb, expected_x) in path.iter().zip(&[0.0, 10.0, 20.0, 30.0]) {
+            assert_close(breadcrumb.0, *expected_x);
+            assert_close(breadcrumb.1, 0.0);
+        }
+        assert_close(odometry.data().path_length_cm, 35.0);
+
+        // The way back isn't added to the path.
+        for cm in (0..35).rev() {
+            let degrees = cm_to_wheel_degrees(cm as f32);
+            odometry.update((degrees, degrees), false);
+        }
+        assert_eq!(odometry.path().len(), 4);
+    }
+
+    #[test]
+    fn wraps_heading() {
+        let mut odometry = odometry();
+        let degrees = turn_to_wheel_degrees(170.0);
+        odometry.update((-degrees, degrees), true);
+        assert_close(odometry.pose().heading_deg, 170.0);
+
+        let degrees = turn_to_wheel_degrees(190.0);
+        odometry.update((-degrees, degrees), true);
+        assert_close(odometry.pose().heading_deg, -170.0);
+
+        odometry.update((0.0, 0.0), true);
+        assert_close(odometry.pose().heading_deg, 0.0);
+    }
+}
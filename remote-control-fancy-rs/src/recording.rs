@@ -0,0 +1,844 @@
+//! Records remote-driven sessions, and replays them later, e.g. to demo a student's path again
+//!   without having to re-drive it.
+//!
+//! While recording, every change the driver makes to the [ControlState]'s mode, speed, and
+//!   direction is saved as a timestamped [Step], no matter which control it came from.
+//! Recordings are saved as JSON files in the directory specified by the
+//!   `REMOTE_CONTROL_RECORDINGS_DIR` environment variable (`./recordings` by default), one per
+//!   name.
+//!
+//! Replays are run by the device actor, which applies each [Step] on its first tick at or after
+//!   the point in time that the step was recorded at, optionally mirrored (left and right
+//!   swapped) or reversed (the path retraced backwards, back to where it started).
+//! Any manual driving command takes over from a replay immediately, just like for the
+//!   [autopilot](crate::autopilot).
+
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use actix_web::{delete, get, post, web, HttpRequest, HttpResponse};
+use ev3_common::pairing::Pairing;
+use serde::{Deserialize, Serialize};
+
+use crate::device::{Device, DeviceCommand, Robot};
+use crate::lease::{self, require_lease};
+use crate::{apply_control_state, brake_to_stop, ControlState, Ev3Devices, Ev3ErrorWrapper, Mode};
+
+/// The environment variable that can be used to override [RECORDINGS_DIR_DEFAULT].
+const RECORDINGS_DIR_VAR: &str = "REMOTE_CONTROL_RECORDINGS_DIR";
+
+/// Where recordings are saved, unless told otherwise.
+const RECORDINGS_DIR_DEFAULT: &str = "recordings";
+
+/// The longest name that a recording can be saved under.
+const NAME_LENGTH_MAX: usize = 64;
+
+/// A single change to the driving controls, within a [Recording].
+#[derive(Clone, Copy, Deserialize, Serialize)]
+pub struct Step {
+    /// When the change was made, in milliseconds since the recording started.
+    at_ms: u64,
+    mode: Mode,
+    speed: u8,
+    direction: i8,
+}
+
+/// A recorded driving session.
+#[derive(Clone, Deserialize, Serialize)]
+pub struct Recording {
+    /// How long the session was recorded for, in milliseconds.
+    duration_ms: u64,
+    /// The changes to the driving controls, in the order they were made.
+    steps: Vec<Step>,
+}
+
+impl Recording {
+    /// Returns the [Step]s to replay this [Recording] with.
+    ///
+    /// Parameters:
+    /// * `mirror`: whether to swap left and right
+    /// * `reverse`: whether to retrace the path backwards: the steps are run in reverse order,
+    ///   with forward and backward swapped, so that the robot ends up back where it started
+    fn plan(&self, mirror: bool, reverse: bool) -> Vec<Step> {
+        let mut steps = self.steps.clone();
+        if reverse {
+            // Each step lasts until the next one, and the last one lasts until the end.
+            let ends: Vec<u64> = steps
+                .iter()
+                .skip(1)
+                .map(|step| step.at_ms)
+                .chain(std::iter::once(self.duration_ms))
+                .collect();
+            steps = steps
+                .iter()
+                .zip(ends)
+                .rev()
+                .map(|(step, end_ms)| Step {
+                    at_ms: self.duration_ms.saturating_sub(end_ms),
+                    mode: match step.mode {
+                        Mode::Forward => Mode::Backward,
+                        Mode::Backward => Mode::Forward,
+                        Mode::Stop => Mode::Stop,
+                    },
+                    ..*step
+                })
+                .collect();
+        }
+        if mirror {
+            for step in &mut steps {
+                // `-128` has no positive counterpart, so it mirrors to `127`.
+                step.direction = step.direction.saturating_neg();
+            }
+        }
+
+        steps
+    }
+}
+
+/// Collects the [Step]s of a [Recording] while it's being recorded.
+pub struct Recorder {
+    started: Instant,
+    steps: Vec<Step>,
+}
+
+impl Recorder {
+    /// Starts a new recording, from the specified [ControlState]'s current controls.
+    fn start(control_state: &ControlState) -> Recorder {
+        let mut recorder = Recorder {
+            started: Instant::now(),
+            steps: Vec::new(),
+        };
+        recorder.record(
+            control_state.mode,
+            control_state.speed,
+            control_state.direction,
+        );
+
+        recorder
+    }
+
+    /// Records the specified controls, if they differ from the last ones recorded.
+    ///
+    /// Parameters:
+    /// * `mode`: the current [Mode]
+    /// * `speed`: the current speed
+    /// * `direction`: the current direction
+    pub fn record(&mut self, mode: Mode, speed: u8, direction: i8) {
+        if let Some(last) = self.steps.last() {
+            if (last.mode, last.speed, last.direction) == (mode, speed, direction) {
+                return;
+            }
+        }
+
+        self.steps.push(Step {
+            at_ms: self.started.elapsed().as_millis() as u64,
+            mode,
+            speed,
+            direction,
+        });
+    }
+
+    /// Ends this recording.
+    fn finish(self) -> Recording {
+        Recording {
+            duration_ms: self.started.elapsed().as_millis() as u64,
+            steps: self.steps,
+        }
+    }
+}
+
+/// The saved [Recording]s.
+pub struct Recordings {
+    dir: PathBuf,
+}
+
+impl Recordings {
+    /// Configures the [Recordings] directory, per the `REMOTE_CONTROL_RECORDINGS_DIR`
+    ///   environment variable.
+    /// The directory won't be created until something is saved to it.
+    pub fn from_env() -> Recordings {
+        let dir = std::env::var(RECORDINGS_DIR_VAR)
+            .unwrap_or_else(|_| RECORDINGS_DIR_DEFAULT.to_string());
+
+        Recordings {
+            dir: PathBuf::from(dir),
+        }
+    }
+
+    /// Returns the path of the file for the specified recording name, or an error if the name
+    ///   isn't valid.
+    fn path(&self, name: &str) -> actix_web::Result<PathBuf> {
+        let valid = !name.is_empty()
+            && name.len() <= NAME_LENGTH_MAX
+            && name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+        if !valid {
+            return Err(actix_web::error::ErrorBadRequest(format!(
+                "Recording names must be 1 to {} letters, numbers, dashes, or underscores.",
+                NAME_LENGTH_MAX
+            )));
+        }
+
+        Ok(self.dir.join(format!("{}.json", name)))
+    }
+
+    /// Saves the specified [Recording] under the specified name, replacing any existing one.
+    fn save(&self, name: &str, recording: &Recording) -> actix_web::Result<()> {
+        let path = self.path(name)?;
+        let json = serde_json::to_string_pretty(recording)?;
+        std::fs::create_dir_all(&self.dir)
+            .and_then(|_| std::fs::write(&path, json))
+            .map_err(|err| {
+                actix_web::error::ErrorInternalServerError(format!(
+                    "Unable to save recording '{}': {}",
+                    name, err
+                ))
+            })
+    }
+
+    /// Loads the [Recording] saved under the specified name.
+    fn load(&self, name: &str) -> actix_web::Result<Recording> {
+        let path = self.path(name)?;
+        let json = std::fs::read_to_string(&path).map_err(|err| match err.kind() {
+            std::io::ErrorKind::NotFound => {
+                actix_web::error::ErrorNotFound(format!("No recording named '{}'.", name))
+            }
+            _ => actix_web::error::ErrorInternalServerError(format!(
+                "Unable to read recording '{}': {}",
+                name, err
+            )),
+        })?;
+
+        serde_json::from_str(&json).map_err(|err| {
+            actix_web::error::ErrorInternalServerError(format!(
+                "Invalid recording '{}': {}",
+                name, err
+            ))
+        })
+    }
+
+    /// Lists the saved recordings, by name.
+    /// Files that can't be read as recordings are skipped.
+    fn list(&self) -> actix_web::Result<Vec<RecordingSummary>> {
+        let entries = match std::fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            // Nothing's been saved yet.
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(err) => {
+                return Err(actix_web::error::ErrorInternalServerError(format!(
+                    "Unable to list recordings: {}",
+                    err
+                )))
+            }
+        };
+
+        let mut summaries = Vec::new();
+        for entry in entries.flatten() {
+            let path = entry.path();
+            let name = match (path.extension(), path.file_stem()) {
+                (Some(extension), Some(name)) if extension == "json" => {
+                    name.to_string_lossy().to_string()
+                }
+                _ => continue,
+            };
+            match self.load(&name) {
+                Ok(recording) => summaries.push(RecordingSummary::new(name, &recording)),
+                Err(err) => eprintln!("Recordings: skipping '{}': {}", path.display(), err),
+            }
+        }
+        summaries.sort_by(|a, b| a.name.cmp(&b.name));
+
+        Ok(summaries)
+    }
+}
+
+/// Models the JSON response for [list_recordings()] and [save_recording()].
+#[derive(Serialize)]
+struct RecordingSummary {
+    name: String,
+    duration_ms: u64,
+    steps: usize,
+}
+
+impl RecordingSummary {
+    fn new(name: String, recording: &Recording) -> RecordingSummary {
+        RecordingSummary {
+            name,
+            duration_ms: recording.duration_ms,
+            steps: recording.steps.len(),
+        }
+    }
+}
+
+/// The state of replaying, which is reported to clients as part of [crate::StateData].
+#[derive(Clone, Serialize)]
+pub struct Replay {
+    /// Whether or not a recording is currently being replayed.
+    pub running: bool,
+    /// The name of the recording being replayed (or last replayed), if any.
+    pub name: Option<String>,
+    /// Why the last replay ended, if one has.
+    pub ended: Option<String>,
+}
+
+impl Replay {
+    /// Constructs a new [Replay], which isn't running.
+    pub fn new() -> Replay {
+        Replay {
+            running: false,
+            name: None,
+            ended: None,
+        }
+    }
+
+    /// Starts a new replay of the specified recording.
+    fn start(&mut self, name: &str) {
+        self.running = true;
+        self.name = Some(name.to_string());
+        self.ended = None;
+    }
+
+    /// Ends the current replay, if one is running.
+    /// The motors are left as they are, for the caller to take over.
+    ///
+    /// Parameters:
+    /// * `reason`: why the replay is ending, which is reported to clients
+    pub fn stop(&mut self, reason: &str) {
+        if !self.running {
+            return;
+        }
+
+        println!("Replay: stopped ({}).", reason);
+        self.running = false;
+        self.ended = Some(reason.to_string());
+    }
+}
+
+/// This API endpoint starts recording the driver's changes to the controls.
+///
+/// Parameters:
+/// * `request`: the HTTP request, which must include the client's session cookie and lease token
+/// * `pairing`: the [Pairing] instance managed/shared by the application
+/// * `device`: the [Device] handle managed/shared by the application
+#[post("/recording")]
+pub async fn start_recording(
+    request: HttpRequest,
+    pairing: web::Data<Pairing>,
+    device: web::Data<Device>,
+) -> actix_web::Result<HttpResponse> {
+    pairing.require_session(&request)?;
+    let token = lease::request_token(&request);
+    device
+        .send(|reply| DeviceCommand::StartRecording { token, reply })
+        .await?;
+
+    Ok(HttpResponse::Ok().finish().into_body())
+}
+
+/// Models the JSON parameters for [save_recording()].
+#[derive(Deserialize)]
+struct SaveData {
+    name: String,
+}
+
+/// This API endpoint stops recording, and saves the recording under the specified name.
+///
+/// If saving fails, e.g. because the disk is full, the recording is kept, so that saving it can
+///   be tried again (or it can be discarded).
+///
+/// Parameters:
+/// * `request`: the HTTP request, which must include the client's session cookie and lease token
+/// * `pairing`: the [Pairing] instance managed/shared by the application
+/// * `recordings`: the [Recordings] instance managed/shared by the application
+/// * `device`: the [Device] handle managed/shared by the application
+/// * `save_data`: the [SaveData] parameters specified in the API call
+#[post("/recording/save")]
+pub async fn save_recording(
+    request: HttpRequest,
+    pairing: web::Data<Pairing>,
+    recordings: web::Data<Recordings>,
+    device: web::Data<Device>,
+    save_data: web::Json<SaveData>,
+) -> actix_web::Result<HttpResponse> {
+    pairing.require_session(&request)?;
+    let token = lease::request_token(&request);
+
+    // Check the name before giving up the recording, so that a typo doesn't lose it.
+    recordings.path(&save_data.name)?;
+    let recording = device
+        .send(|reply| DeviceCommand::FinishRecording {
+            token: token.clone(),
+            reply,
+        })
+        .await?;
+    recordings.save(&save_data.name, &recording)?;
+    device
+        .send(|reply| DeviceCommand::RecordingSaved { token, reply })
+        .await?;
+    println!(
+        "Recording: saved '{}' ({} steps).",
+        save_data.name,
+        recording.steps.len()
+    );
+
+    Ok(HttpResponse::Ok().json(RecordingSummary::new(save_data.name.clone(), &recording)))
+}
+
+/// This API endpoint stops recording, and throws the recording away.
+///
+/// Parameters:
+/// * `request`: the HTTP request, which must include the client's session cookie and lease token
+/// * `pairing`: the [Pairing] instance managed/shared by the application
+/// * `device`: the [Device] handle managed/shared by the application
+#[delete("/recording")]
+pub async fn discard_recording(
+    request: HttpRequest,
+    pairing: web::Data<Pairing>,
+    device: web::Data<Device>,
+) -> actix_web::Result<HttpResponse> {
+    pairing.require_session(&request)?;
+    let token = lease::request_token(&request);
+    device
+        .send(|reply| DeviceCommand::DiscardRecording { token, reply })
+        .await?;
+
+    Ok(HttpResponse::Ok().finish().into_body())
+}
+
+/// This API endpoint lists the saved recordings, e.g.
+///   `[ { "name": "figure-eight", "duration_ms": 12500, "steps": 42 } ]`.
+///
+/// Parameters:
+/// * `recordings`: the [Recordings] instance managed/shared by the application
+#[get("/recordings")]
+pub async fn list_recordings(recordings: web::Data<Recordings>) -> actix_web::Result<HttpResponse> {
+    Ok(HttpResponse::Ok().json(recordings.list()?))
+}
+
+/// This API endpoint returns the saved recording with the specified name, as JSON.
+///
+/// Parameters:
+/// * `recordings`: the [Recordings] instance managed/shared by the application
+/// * `name`: the name of the recording to return
+#[get("/recordings/{name}")]
+pub async fn get_recording(
+    recordings: web::Data<Recordings>,
+    name: web::Path<String>,
+) -> actix_web::Result<HttpResponse> {
+    Ok(HttpResponse::Ok().json(recordings.load(&name)?))
+}
+
+/// Models the JSON parameters for [start_replay()].
+#[derive(Deserialize)]
+struct ReplayData {
+    name: String,
+    #[serde(default)]
+    mirror: bool,
+    #[serde(default)]
+    reverse: bool,
+}
+
+/// This API endpoint stops the robot, and then starts replaying the specified recording.
+/// Any replay, autopilot, or homing run that's already running is stopped first.
+///
+/// Parameters:
+/// * `request`: the HTTP request, which must include the client's session cookie and lease token
+/// * `pairing`: the [Pairing] instance managed/shared by the application
+/// * `recordings`: the [Recordings] instance managed/shared by the application
+/// * `device`: the [Device] handle managed/shared by the application
+/// * `replay_data`: the [ReplayData] parameters specified in the API call
+#[post("/replay")]
+pub async fn start_replay(
+    request: HttpRequest,
+    pairing: web::Data<Pairing>,
+    recordings: web::Data<Recordings>,
+    device: web::Data<Device>,
+    replay_data: web::Json<ReplayData>,
+) -> actix_web::Result<HttpResponse> {
+    pairing.require_session(&request)?;
+    let token = lease::request_token(&request);
+
+    // Load the recording here, rather than in the device actor, so that the disk can't hold up
+    //   the robot.
+    let ReplayData {
+        name,
+        mirror,
+        reverse,
+    } = replay_data.into_inner();
+    let recording = recordings.load(&name)?;
+    let steps = recording.plan(mirror, reverse);
+    let duration = Duration::from_millis(recording.duration_ms);
+    device
+        .send(|reply| DeviceCommand::StartReplay {
+            token,
+            name: name.clone(),
+            steps,
+            duration,
+            reply,
+        })
+        .await?;
+    println!(
+        "Replay: started '{}' (mirror: {}, reverse: {}).",
+        name, mirror, reverse
+    );
+
+    Ok(HttpResponse::Ok().finish().into_body())
+}
+
+/// This API endpoint stops the replay (if one is running) and brakes the robot.
+///
+/// Parameters:
+/// * `request`: the HTTP request, which must include the client's session cookie and lease token
+/// * `pairing`: the [Pairing] instance managed/shared by the application
+/// * `device`: the [Device] handle managed/shared by the application
+#[delete("/replay")]
+pub async fn stop_replay(
+    request: HttpRequest,
+    pairing: web::Data<Pairing>,
+    device: web::Data<Device>,
+) -> actix_web::Result<HttpResponse> {
+    pairing.require_session(&request)?;
+    let token = lease::request_token(&request);
+    device
+        .send(|reply| DeviceCommand::StopReplay { token, reply })
+        .await?;
+
+    Ok(HttpResponse::Ok().finish().into_body())
+}
+
+/// The [Step]s of a replay, which the device actor applies as they come due, via
+///   [Robot::drive_replay()].
+pub struct ReplayRun {
+    steps: Vec<Step>,
+    /// The index of the next [Step] to apply.
+    next: usize,
+    started: Instant,
+    /// How long the replay lasts, after which the robot is stopped.
+    duration: Duration,
+}
+
+impl Robot {
+    /// Starts recording the driver's changes to the controls, for [start_recording()].
+    ///
+    /// Parameters:
+    /// * `token`: the lease token that the client specified, if any
+    pub fn start_recording(&mut self, token: Option<&str>) -> actix_web::Result<()> {
+        let control_state = &mut self.control_state;
+        require_lease(control_state, token)?;
+        if control_state.recorder.is_some() {
+            return Err(actix_web::error::ErrorConflict("Already recording."));
+        }
+        if control_state.unsaved_recording.is_some() {
+            return Err(actix_web::error::ErrorConflict(
+                "The last recording hasn't been saved: save or discard it first.",
+            ));
+        }
+
+        println!("Recording: started.");
+        control_state.recorder = Some(Recorder::start(control_state));
+
+        Ok(())
+    }
+
+    /// Stops recording (if it's still running), and returns the [Recording], for
+    ///   [save_recording()] to save.
+    ///
+    /// The [Recording] is kept until [Robot::recording_saved()] is called, so that it isn't lost
+    ///   if saving it fails.
+    ///
+    /// Parameters:
+    /// * `token`: the lease token that the client specified, if any
+    pub fn finish_recording(&mut self, token: Option<&str>) -> actix_web::Result<Recording> {
+        let control_state = &mut self.control_state;
+        require_lease(control_state, token)?;
+
+        if let Some(recorder) = control_state.recorder.take() {
+            control_state.unsaved_recording = Some(recorder.finish());
+        }
+        match &control_state.unsaved_recording {
+            Some(recording) => Ok(recording.clone()),
+            None => Err(actix_web::error::ErrorConflict("Not recording.")),
+        }
+    }
+
+    /// Lets go of the [Recording] returned by [Robot::finish_recording()], once
+    ///   [save_recording()] has saved it.
+    ///
+    /// Parameters:
+    /// * `token`: the lease token that the client specified, if any
+    pub fn recording_saved(&mut self, token: Option<&str>) -> actix_web::Result<()> {
+        let control_state = &mut self.control_state;
+        require_lease(control_state, token)?;
+        control_state.unsaved_recording = None;
+
+        Ok(())
+    }
+
+    /// Stops recording, and throws the recording away (along with one that couldn't be saved, if
+    ///   any), for [discard_recording()].
+    ///
+    /// Parameters:
+    /// * `token`: the lease token that the client specified, if any
+    pub fn discard_recording(&mut self, token: Option<&str>) -> actix_web::Result<()> {
+        let control_state = &mut self.control_state;
+        require_lease(control_state, token)?;
+
+        let recording = control_state.recorder.take().is_some();
+        let unsaved = control_state.unsaved_recording.take().is_some();
+        if recording || unsaved {
+            println!("Recording: discarded.");
+        }
+
+        Ok(())
+    }
+
+    /// Stops the robot, and then starts replaying the specified [Step]s, for [start_replay()].
+    ///
+    /// Parameters:
+    /// * `token`: the lease token that the client specified, if any
+    /// * `name`: the name of the recording being replayed
+    /// * `steps`: the [Step]s to apply
+    /// * `duration`: how long the replay lasts, after which the robot is stopped
+    pub fn start_replay(
+        &mut self,
+        token: Option<&str>,
+        name: &str,
+        steps: Vec<Step>,
+        duration: Duration,
+    ) -> actix_web::Result<()> {
+        let control_state = &mut self.control_state;
+        require_lease(control_state, token)?;
+        control_state.estop.require_clear()?;
+
+        control_state.autopilot.stop("replay started");
+        control_state.homing.stop("replay started");
+        control_state.replay.stop("another replay started");
+
+        // Start from a standstill, as the recording did.
+        brake_to_stop(control_state, &self.ev3_devices)?;
+        control_state.mode = Mode::Stop;
+        control_state.guard = None;
+        control_state.timed_out = false;
+
+        control_state.replay.start(name);
+        self.replay_run = Some(ReplayRun {
+            steps,
+            next: 0,
+            started: Instant::now(),
+            duration,
+        });
+
+        // Apply the first step right away, rather than waiting for the next tick.
+        self.drive_replay();
+
+        Ok(())
+    }
+
+    /// Stops the replay (if one is running) and brakes the robot, for [stop_replay()].
+    ///
+    /// Parameters:
+    /// * `token`: the lease token that the client specified, if any
+    pub fn stop_replay(&mut self, token: Option<&str>) -> actix_web::Result<()> {
+        let control_state = &mut self.control_state;
+        require_lease(control_state, token)?;
+        if !control_state.replay.running {
+            return Ok(());
+        }
+
+        control_state.replay.stop("stopped by the driver");
+        stop_motors(control_state, &self.ev3_devices)
+    }
+
+    /// Applies each of the [ReplayRun]'s [Step]s that has come due, on every tick of the device
+    ///   actor that a replay is running for, and stops the robot once the replay is over.
+    ///
+    /// Once the replay has been stopped or taken over, it's dropped without touching the motors
+    ///   again, as whoever stopped it is now in charge of them.
+    /// If a step can't be applied, e.g. because the robot would drive forwards over a cliff, the
+    ///   robot is braked and the replay is stopped.
+    pub fn drive_replay(&mut self) {
+        let mut replay_run = match self.replay_run.take() {
+            Some(replay_run) => replay_run,
+            None => return,
+        };
+        let control_state = &mut self.control_state;
+        if !control_state.replay.running {
+            return;
+        }
+
+        let elapsed = replay_run.started.elapsed();
+        while let Some(step) = replay_run.steps.get(replay_run.next) {
+            if Duration::from_millis(step.at_ms) > elapsed {
+                break;
+            }
+            replay_run.next += 1;
+
+            if let Err(err) = apply_step(control_state, &self.ev3_devices, step) {
+                println!("Replay: {}", err);
+                if let Err(err) = stop_motors(control_state, &self.ev3_devices) {
+                    eprintln!("Replay: {}", err);
+                }
+                control_state.replay.stop(&err.to_string());
+                return;
+            }
+        }
+
+        if replay_run.next < replay_run.steps.len() || elapsed < replay_run.duration {
+            self.replay_run = Some(replay_run);
+            return;
+        }
+        if let Err(err) = stop_motors(control_state, &self.ev3_devices) {
+            eprintln!("Replay: {}", err);
+        }
+        control_state.replay.stop("finished");
+    }
+}
+
+/// Updates the [ControlState] to match the specified [Step], and applies it.
+///
+/// Parameters:
+/// * `control_state`: the [ControlState] to update
+/// * `ev3_devices`: the [Ev3Devices] to update
+/// * `step`: the [Step] to apply
+fn apply_step(
+    control_state: &mut ControlState,
+    ev3_devices: &Ev3Devices,
+    step: &Step,
+) -> actix_web::Result<()> {
+    // If switching directions, stop first.
+    let reversing = matches!(
+        (control_state.mode, step.mode),
+        (Mode::Forward, Mode::Backward) | (Mode::Backward, Mode::Forward)
+    );
+    if reversing {
+        brake_to_stop(control_state, ev3_devices)?;
+    }
+
+    control_state.mode = step.mode;
+    control_state.speed = step.speed.min(100);
+    control_state.direction = step.direction.max(-100).min(100);
+    apply_control_state(control_state, ev3_devices)
+}
+
+/// Brakes the robot and shifts it into [Mode::Stop].
+fn stop_motors(
+    control_state: &mut ControlState,
+    ev3_devices: &Ev3Devices,
+) -> actix_web::Result<()> {
+    let motor_set = &ev3_devices.motor_set;
+    motor_set
+        .set_stop_action("brake")
+        .and_then(|_| motor_set.stop())
+        .map_err(Ev3ErrorWrapper::from)?;
+    control_state.mode = Mode::Stop;
+    control_state.guard = None;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A short session: forward, then veering left, then stopped for the last half second.
+    fn recording() -> Recording {
+        Recording {
+            duration_ms: 3000,
+            steps: vec![
+                Step {
+                    at_ms: 0,
+                    mode: Mode::Forward,
+                    speed: 50,
+                    direction: 0,
+                },
+                Step {
+                    at_ms: 1000,
+                    mode: Mode::Forward,
+                    speed: 50,
+                    direction: -30,
+                },
+                Step {
+                    at_ms: 2500,
+                    mode: Mode::Stop,
+                    speed: 0,
+                    direction: 0,
+                },
+            ],
+        }
+    }
+
+    /// Returns the fields of each [Step], so that they can be compared.
+    fn fields(steps: &[Step]) -> Vec<(u64, Mode, u8, i8)> {
+        steps
+            .iter()
+            .map(|step| (step.at_ms, step.mode, step.speed, step.direction))
+            .collect()
+    }
+
+    #[test]
+    fn plans_forward() {
+        assert_eq!(
+            fields(&recording().plan(false, false)),
+            vec![
+                (0, Mode::Forward, 50, 0),
+                (1000, Mode::Forward, 50, -30),
+                (2500, Mode::Stop, 0, 0),
+            ]
+        );
+    }
+
+    #[test]
+    fn plans_reversed() {
+        // Each step starts as long before the end as it originally ended after the start.
+        assert_eq!(
+            fields(&recording().plan(false, true)),
+            vec![
+                (0, Mode::Stop, 0, 0),
+                (500, Mode::Backward, 50, -30),
+                (2000, Mode::Backward, 50, 0),
+            ]
+        );
+    }
+
+    #[test]
+    fn plans_mirrored() {
+        assert_eq!(
+            fields(&recording().plan(true, false)),
+            vec![
+                (0, Mode::Forward, 50, 0),
+                (1000, Mode::Forward, 50, 30),
+                (2500, Mode::Stop, 0, 0),
+            ]
+        );
+
+        // `-128` has no positive counterpart, so it saturates.
+        let mut hard_left = recording();
+        hard_left.steps[1].direction = -128;
+        assert_eq!(hard_left.plan(true, false)[1].direction, 127);
+    }
+
+    #[test]
+    fn plans_reversed_and_mirrored() {
+        assert_eq!(
+            fields(&recording().plan(true, true)),
+            vec![
+                (0, Mode::Stop, 0, 0),
+                (500, Mode::Backward, 50, 30),
+                (2000, Mode::Backward, 50, 0),
+            ]
+        );
+    }
+
+    #[test]
+    fn plans_empty_recording() {
+        let recording = Recording {
+            duration_ms: 1000,
+            steps: Vec::new(),
+        };
+
+        for &(mirror, reverse) in &[(false, false), (false, true), (true, false), (true, true)] {
+            assert!(recording.plan(mirror, reverse).is_empty());
+        }
+    }
+}
@@ -0,0 +1,239 @@
+//! Provides a WebSocket control channel for low-latency driving.
+//!
+//! Clients send a stream of [DriveCommand]s (and heartbeats, while driving) as JSON text messages,
+//!   and are sent the current [crate::StateData] as JSON every [STATE_PUSH_INTERVAL] in return.
+//! Commands are handed off to [apply_drive_commands] via a [watch] channel, which only ever holds
+//!   the latest command: if a burst of commands arrives faster than the device actor can apply
+//!   them, the stale ones are skipped, rather than queued up.
+//! Only the commands and heartbeats from the client holding the driving [crate::lease::Lease]
+//!   are applied: the device actor checks the lease as it applies each one, and skips the rest,
+//!   since every other client is just a spectator.
+
+use std::time::Duration;
+
+use actix::{Actor, ActorContext, AsyncContext, StreamHandler, WrapFuture};
+use actix_web::{get, web, HttpRequest, HttpResponse};
+use actix_web_actors::ws;
+use ev3_common::pairing::Pairing;
+use serde::Deserialize;
+use tokio::sync::watch;
+
+use crate::device::{Device, DeviceCommand, Robot};
+use crate::lease::require_lease;
+use crate::{apply_control_state, brake_to_stop, Mode};
+
+/// How often each WebSocket client is sent the current [crate::StateData].
+const STATE_PUSH_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Models the JSON text messages sent by WebSocket clients.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum SocketMessage {
+    Drive(DriveCommand),
+    /// Lets the server know that the client is still there, e.g. `{ "heartbeat": true }`.
+    Heartbeat {
+        heartbeat: bool,
+    },
+}
+
+/// Models a combined drive command, as sent by WebSocket clients.
+#[derive(Clone, Deserialize)]
+pub struct DriveCommand {
+    mode: Mode,
+    speed: u8,
+    direction: i8,
+}
+
+/// A [DriveCommand], along with the lease token of the client that sent it.
+#[derive(Clone)]
+pub struct LeasedCommand {
+    lease: Option<String>,
+    command: DriveCommand,
+}
+
+/// Models the (optional) query parameters for [drive_socket()].
+#[derive(Deserialize)]
+pub struct SocketParams {
+    /// The client's lease token, which is required to drive, but not to watch.
+    lease: Option<String>,
+}
+
+/// This API endpoint upgrades the connection to a WebSocket, which accepts [DriveCommand]s and
+///   pushes back [crate::StateData].
+///
+/// Parameters:
+/// * `request`: the HTTP request to upgrade, which must include the client's session cookie
+/// * `stream`: the HTTP request's payload
+/// * `params`: the [SocketParams] specified in the API call
+/// * `pairing`: the [Pairing] instance managed/shared by the application
+/// * `drive_commands`: the sending side of the channel read by [apply_drive_commands]
+/// * `device`: the [Device] handle managed/shared by the application
+#[get("/ws")]
+pub async fn drive_socket(
+    request: HttpRequest,
+    stream: web::Payload,
+    params: web::Query<SocketParams>,
+    pairing: web::Data<Pairing>,
+    drive_commands: web::Data<watch::Sender<Option<LeasedCommand>>>,
+    device: web::Data<Device>,
+) -> actix_web::Result<HttpResponse> {
+    pairing.require_session(&request)?;
+    ws::start(
+        DriveSocket {
+            lease: params.into_inner().lease,
+            drive_commands,
+            device,
+        },
+        &request,
+        stream,
+    )
+}
+
+/// The actor that handles a single WebSocket client.
+struct DriveSocket {
+    lease: Option<String>,
+    drive_commands: web::Data<watch::Sender<Option<LeasedCommand>>>,
+    device: web::Data<Device>,
+}
+
+impl DriveSocket {
+    /// Sends the latest [crate::StateData] published by the device actor to the client, as JSON.
+    fn push_state(&self, ctx: &mut ws::WebsocketContext<Self>) {
+        match serde_json::to_string(&self.device.state()) {
+            Ok(state) => ctx.text(state),
+            Err(err) => eprintln!("Drive socket: unable to serialize state: {}", err),
+        }
+    }
+}
+
+impl Actor for DriveSocket {
+    type Context = ws::WebsocketContext<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        println!("Drive socket: client connected.");
+        ctx.run_interval(STATE_PUSH_INTERVAL, |socket, ctx| socket.push_state(ctx));
+    }
+
+    fn stopped(&mut self, _: &mut Self::Context) {
+        println!("Drive socket: client disconnected.");
+    }
+}
+
+impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for DriveSocket {
+    fn handle(&mut self, message: Result<ws::Message, ws::ProtocolError>, ctx: &mut Self::Context) {
+        match message {
+            Ok(ws::Message::Text(text)) => match serde_json::from_str::<SocketMessage>(&text) {
+                Ok(SocketMessage::Heartbeat { heartbeat: false }) => {}
+                Ok(SocketMessage::Heartbeat { heartbeat: true }) => {
+                    let token = self.lease.clone();
+                    let device = self.device.clone();
+                    ctx.spawn(
+                        async move {
+                            // Spectators' heartbeats are refused, and don't count.
+                            let _ = device
+                                .send(|reply| DeviceCommand::Heartbeat { token, reply })
+                                .await;
+                        }
+                        .into_actor(self),
+                    );
+                }
+                Ok(SocketMessage::Drive(command)) => {
+                    let command = LeasedCommand {
+                        lease: self.lease.clone(),
+                        command,
+                    };
+                    // The device actor checks the lease when it applies the command, so
+                    //   there's no need to wait on it here.
+                    if self.drive_commands.broadcast(Some(command)).is_err() {
+                        ctx.text(
+                            serde_json::json!({
+                                "error": "Drive commands are no longer being applied."
+                            })
+                            .to_string(),
+                        );
+                    }
+                }
+                Err(err) => ctx.text(
+                    serde_json::json!({ "error": format!("Invalid message: {}", err) }).to_string(),
+                ),
+            },
+            Ok(ws::Message::Ping(message)) => ctx.pong(&message),
+            Ok(ws::Message::Close(reason)) => {
+                ctx.close(reason);
+                ctx.stop();
+            }
+            Ok(_) => {}
+            Err(err) => {
+                eprintln!("Drive socket: protocol error: {}", err);
+                ctx.stop();
+            }
+        }
+    }
+}
+
+/// Runs forever in the background, handing the latest [DriveCommand] sent by a WebSocket client
+///   off to the device actor, which applies it if that client holds the driving lease.
+///
+/// Parameters:
+/// * `drive_commands`: the receiving side of the channel that [DriveSocket]s send commands to
+/// * `device`: the [Device] handle to send the commands to
+pub async fn apply_drive_commands(
+    mut drive_commands: watch::Receiver<Option<LeasedCommand>>,
+    device: Device,
+) {
+    while let Some(command) = drive_commands.recv().await {
+        let LeasedCommand { lease, command } = match command {
+            Some(command) => command,
+            None => continue,
+        };
+
+        let sent = device
+            .send(|reply| DeviceCommand::SocketDrive {
+                token: lease,
+                command,
+                reply,
+            })
+            .await;
+        if let Err(err) = sent {
+            eprintln!("Drive socket: unable to apply command: {}", err);
+        }
+    }
+}
+
+impl Robot {
+    /// Updates the [crate::ControlState] to match the specified [DriveCommand], and applies it.
+    ///
+    /// Commands from clients that don't hold the driving lease are silently skipped: the lease
+    ///   may have changed hands since the command was sent.
+    ///
+    /// Parameters:
+    /// * `token`: the lease token of the client that sent the command, if any
+    /// * `command`: the [DriveCommand] to apply
+    pub fn apply_drive_command(
+        &mut self,
+        token: Option<&str>,
+        command: &DriveCommand,
+    ) -> actix_web::Result<()> {
+        let control_state = &mut self.control_state;
+        if require_lease(control_state, token).is_err() {
+            return Ok(());
+        }
+        control_state.command_received();
+
+        // If switching directions, stop first.
+        let reversing = matches!(
+            (control_state.mode, command.mode),
+            (Mode::Forward, Mode::Backward) | (Mode::Backward, Mode::Forward)
+        );
+        if reversing {
+            brake_to_stop(control_state, &self.ev3_devices)?;
+        }
+
+        // Clamp the specified speed and direction to the allowed/expected ranges.
+        control_state.mode = command.mode;
+        control_state.speed = command.speed.min(100);
+        control_state.direction = command.direction.max(-100).min(100);
+        control_state.record_controls();
+        apply_control_state(control_state, &self.ev3_devices)
+    }
+}
@@ -0,0 +1,251 @@
+//! Reads Linux [evdev](https://www.kernel.org/doc/html/latest/input/input.html) input events,
+//!   either straight from a gamepad's `/dev/input/event*` device, or from a recording of one.
+//!
+//! Recordings are plain text, with one event per line, e.g. `1.250000 3 1 -32768` for an event
+//!   with a timestamp of 1.25 seconds, a type of `3` (`EV_ABS`), a code of `1` (`ABS_Y`), and a
+//!   value of `-32768`.
+//! Blank lines and lines starting with `#` are ignored.
+//! Unlike the devices' binary format, this doesn't depend on the word size of the machine that
+//!   made the recording, so recordings from the brick can be replayed anywhere.
+
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use anyhow::{anyhow, Context, Result};
+
+/// The `EV_SYN` event type, which marks the end of a batch of related events.
+pub const EV_SYN: u16 = 0x00;
+
+/// The `EV_KEY` event type, which is used for buttons.
+pub const EV_KEY: u16 = 0x01;
+
+/// The `EV_ABS` event type, which is used for sticks, triggers, and d-pads.
+pub const EV_ABS: u16 = 0x03;
+
+/// The `SYN_REPORT` event code, which is sent once all of a batch's events have been sent.
+pub const SYN_REPORT: u16 = 0x00;
+
+/// Where the kernel links the event devices for all of the connected gamepads (and joysticks).
+const GAMEPADS_DIR: &str = "/dev/input/by-id";
+
+/// The suffix of the event device links in [GAMEPADS_DIR] that are for gamepads.
+const GAMEPAD_SUFFIX: &str = "-event-joystick";
+
+/// The largest timestamp (in seconds) allowed in a recording, which is far more than any real
+///   one will ever have, but still well within what a [Duration] can hold.
+const RECORDING_SECS_MAX: f64 = u32::MAX as f64;
+
+/// A single evdev input event.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct InputEvent {
+    /// When the event happened, relative to some arbitrary point in the past.
+    pub time: Duration,
+    /// The event type, e.g. [EV_ABS].
+    pub kind: u16,
+    /// The event code, e.g. which axis or button it's for.
+    pub code: u16,
+    /// The event's value, e.g. the axis' new position, or `1` for a button press.
+    pub value: i32,
+}
+
+impl InputEvent {
+    /// Returns `true` if this event marks the end of a batch of related events.
+    pub fn is_report(&self) -> bool {
+        self.kind == EV_SYN && self.code == SYN_REPORT
+    }
+
+    /// Parses an [InputEvent] from the `struct input_event` bytes read from an event device.
+    ///
+    /// Parameters:
+    /// * `bytes`: exactly [InputEvent::size()] bytes, in the machine's native byte order
+    fn from_bytes(bytes: &[u8]) -> InputEvent {
+        // The timestamp is a `struct timeval`, which is two `long`s.
+        let long = std::mem::size_of::<isize>();
+        let read_long = |bytes: &[u8]| {
+            let mut buffer = [0u8; std::mem::size_of::<isize>()];
+            buffer.copy_from_slice(bytes);
+            isize::from_ne_bytes(buffer)
+        };
+        let seconds = read_long(&bytes[0..long]);
+        let micros = read_long(&bytes[long..2 * long]);
+        let rest = &bytes[2 * long..];
+
+        InputEvent {
+            time: Duration::from_secs(seconds as u64) + Duration::from_micros(micros as u64),
+            kind: u16::from_ne_bytes([rest[0], rest[1]]),
+            code: u16::from_ne_bytes([rest[2], rest[3]]),
+            value: i32::from_ne_bytes([rest[4], rest[5], rest[6], rest[7]]),
+        }
+    }
+
+    /// Returns the size of a `struct input_event` on this machine.
+    fn size() -> usize {
+        2 * std::mem::size_of::<isize>() + 8
+    }
+
+    /// Parses an [InputEvent] from a line of a recording.
+    ///
+    /// Parameters:
+    /// * `line`: the line to parse, e.g. `1.250000 3 1 -32768`
+    fn from_line(line: &str) -> Result<InputEvent> {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.len() != 4 {
+            return Err(anyhow!("Expected 4 fields, but found {}.", fields.len()));
+        }
+
+        let seconds: f64 = fields[0].parse().context("Invalid timestamp.")?;
+        if !seconds.is_finite() || seconds < 0.0 {
+            return Err(anyhow!("Invalid timestamp."));
+        }
+        if seconds > RECORDING_SECS_MAX {
+            return Err(anyhow!(
+                "Timestamp must be at most {} seconds.",
+                RECORDING_SECS_MAX
+            ));
+        }
+        Ok(InputEvent {
+            time: Duration::from_secs_f64(seconds),
+            kind: fields[1].parse().context("Invalid type.")?,
+            code: fields[2].parse().context("Invalid code.")?,
+            value: fields[3].parse().context("Invalid value.")?,
+        })
+    }
+
+    /// Formats this [InputEvent] as a line of a recording (without the trailing newline).
+    fn to_line(&self) -> String {
+        format!(
+            "{}.{:06} {} {} {}",
+            self.time.as_secs(),
+            self.time.subsec_micros(),
+            self.kind,
+            self.code,
+            self.value
+        )
+    }
+}
+
+/// Returns the event device for the first connected gamepad, if any.
+pub fn find_gamepad() -> Option<PathBuf> {
+    let mut gamepads: Vec<PathBuf> = std::fs::read_dir(GAMEPADS_DIR)
+        .ok()?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.file_name()
+                .and_then(|name| name.to_str())
+                .map_or(false, |name| name.ends_with(GAMEPAD_SUFFIX))
+        })
+        .collect();
+    gamepads.sort();
+    gamepads.into_iter().next()
+}
+
+/// Reads [InputEvent]s from a gamepad's event device.
+pub struct Device {
+    file: File,
+}
+
+impl Device {
+    /// Opens the specified event device.
+    ///
+    /// Parameters:
+    /// * `path`: the event device to open, e.g. `/dev/input/event2`
+    pub fn open(path: &Path) -> Result<Device> {
+        let file = File::open(path)
+            .with_context(|| format!("Unable to open gamepad '{}'.", path.display()))?;
+        Ok(Device { file })
+    }
+
+    /// Blocks until the next [InputEvent] is available, and then returns it.
+    ///
+    /// Fails once the gamepad is disconnected.
+    pub fn next_event(&mut self) -> Result<InputEvent> {
+        let mut buffer = vec![0u8; InputEvent::size()];
+        self.file
+            .read_exact(&mut buffer)
+            .context("Unable to read from gamepad.")?;
+        Ok(InputEvent::from_bytes(&buffer))
+    }
+}
+
+/// Reads all of the [InputEvent]s in a recording.
+///
+/// Parameters:
+/// * `path`: the recording to read
+pub fn read_recording(path: &Path) -> Result<Vec<InputEvent>> {
+    let file = File::open(path)
+        .with_context(|| format!("Unable to open recording '{}'.", path.display()))?;
+
+    let mut events = Vec::new();
+    for (index, line) in BufReader::new(file).lines().enumerate() {
+        let line = line.with_context(|| format!("Unable to read '{}'.", path.display()))?;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let event = InputEvent::from_line(line)
+            .with_context(|| format!("Invalid event at {}:{}.", path.display(), index + 1))?;
+        events.push(event);
+    }
+
+    Ok(events)
+}
+
+/// Writes [InputEvent]s to a recording, as they happen.
+pub struct Recorder {
+    file: File,
+}
+
+impl Recorder {
+    /// Creates (or replaces) the specified recording.
+    ///
+    /// Parameters:
+    /// * `path`: the recording to write
+    pub fn create(path: &Path) -> Result<Recorder> {
+        let file = File::create(path)
+            .with_context(|| format!("Unable to create recording '{}'.", path.display()))?;
+        Ok(Recorder { file })
+    }
+
+    /// Appends the specified [InputEvent] to the recording.
+    pub fn record(&mut self, event: &InputEvent) -> Result<()> {
+        writeln!(self.file, "{}", event.to_line()).context("Unable to write to recording.")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_recording_lines() {
+        let event = InputEvent::from_line("1.250000 3 1 -32768").expect("line should parse");
+        assert_eq!(
+            event,
+            InputEvent {
+                time: Duration::from_millis(1250),
+                kind: EV_ABS,
+                code: 1,
+                value: -32768,
+            }
+        );
+        assert_eq!(event.to_line(), "1.250000 3 1 -32768");
+    }
+
+    #[test]
+    fn rejects_bad_timestamps() {
+        for line in &[
+            "-1.0 3 1 0",
+            "NaN 3 1 0",
+            "inf 3 1 0",
+            "4294967296.0 3 1 0",
+            "1e300 3 1 0",
+        ] {
+            assert!(InputEvent::from_line(line).is_err(), "{}", line);
+        }
+        assert!(InputEvent::from_line("4294967295.0 3 1 0").is_ok());
+    }
+}
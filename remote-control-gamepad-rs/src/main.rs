@@ -0,0 +1,262 @@
+//! A remote controlled driving routine for the [ev3dev platform](https://www.ev3dev.org/),
+//!   which uses a gamepad plugged into (or paired with) the brick itself,
+//!   so no network is needed at all.
+//!
+//! Usage:
+//!
+//! ```text
+//! remote-control-gamepad-rs [--record <file>] [device]
+//! remote-control-gamepad-rs --replay <file>
+//! ```
+//!
+//! If no device (e.g. `/dev/input/event2`) is specified, the first connected gamepad is used.
+//! The gamepad's axes and buttons are mapped onto wheel speeds per a [mapping::Mapping], which
+//!   can be customized for gamepads that don't work with the default one.
+//!
+//! With `--record`, the gamepad's raw events are also saved to the specified file as they're
+//!   read. With `--replay`, the events in such a file are run through the mapping, and the
+//!   resulting wheel speeds are printed out, without touching the motors. Together, these make it
+//!   possible to test a mapping (or changes to this program) without a robot or gamepad handy.
+
+mod evdev;
+mod mapping;
+
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::time::Duration;
+
+use anyhow::{anyhow, Context, Result};
+use ev3_common::cliff::CliffDetector;
+use ev3_common::motors::LargeMotorSet;
+use ev3dev_lang_rust::{Ev3Button, Ev3Error};
+use evdev::{Device, InputEvent, Recorder};
+use mapping::{DriveCommand, Gamepad, Mapping};
+
+/// How long to wait for gamepad events before checking the brick's buttons and the cliff
+///   detector again.
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+fn main() -> Result<()> {
+    let usage = "Usage: remote-control-gamepad-rs [--record <file>] [device]\n       \
+                 remote-control-gamepad-rs --replay <file>";
+    let mut record_path = None;
+    let mut replay_path = None;
+    let mut device_path = None;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--record" => record_path = Some(args.next().ok_or_else(|| anyhow!(usage))?),
+            "--replay" => replay_path = Some(args.next().ok_or_else(|| anyhow!(usage))?),
+            _ if device_path.is_none() => device_path = Some(PathBuf::from(arg)),
+            _ => return Err(anyhow!(usage)),
+        }
+    }
+
+    let mapping = Mapping::load()?;
+    if let Some(replay_path) = replay_path {
+        if record_path.is_some() || device_path.is_some() {
+            return Err(anyhow!(usage));
+        }
+        return replay(Path::new(&replay_path), mapping);
+    }
+
+    // Open the gamepad, and start reading its events in the background.
+    let device_path = match device_path {
+        Some(device_path) => device_path,
+        None => evdev::find_gamepad().ok_or_else(|| anyhow!("No gamepad found."))?,
+    };
+    let device = Device::open(&device_path)?;
+    let recorder = match record_path {
+        Some(record_path) => Some(Recorder::create(Path::new(&record_path))?),
+        None => None,
+    };
+    let (events_tx, events_rx) = mpsc::channel();
+    std::thread::spawn(move || read_events(device, recorder, events_tx));
+
+    // Get motors and sensors.
+    let motor_set = LargeMotorSet::new().map_err(ev3_error)?;
+    let cliff_detector = CliffDetector::find();
+    let buttons = Ev3Button::new().map_err(ev3_error)?;
+
+    println!(
+        "Gamepad: ready, reading '{}'. Press backspace on the brick to exit.",
+        device_path.display()
+    );
+    let result = drive(
+        &motor_set,
+        &events_rx,
+        mapping,
+        cliff_detector.as_ref(),
+        &buttons,
+    );
+
+    // Whatever happened, make sure the motors end up stopped.
+    motor_set.set_stop_action("brake").map_err(ev3_error)?;
+    motor_set.stop().map_err(ev3_error)?;
+    motor_set.wait_until_not_moving(None);
+    println!("Gamepad: stopped. Bye!");
+
+    result
+}
+
+/// Converts an [Ev3Error], which doesn't implement [std::error::Error], into an [anyhow::Error].
+fn ev3_error(cause: Ev3Error) -> anyhow::Error {
+    anyhow!("EV3 error: {:?}", cause)
+}
+
+/// Runs in the background, sending each of the gamepad's events to the main thread (and the
+///   recording, if any), until the gamepad is disconnected.
+///
+/// Parameters:
+/// * `device`: the gamepad [Device] to read
+/// * `recorder`: the [Recorder] to save the events with, if any
+/// * `events`: the channel to send the events (or the error that stopped them) to
+fn read_events(
+    mut device: Device,
+    mut recorder: Option<Recorder>,
+    events: Sender<Result<InputEvent>>,
+) {
+    loop {
+        let event = device.next_event();
+        let record_result = match (&event, recorder.as_mut()) {
+            (Ok(event), Some(recorder)) => recorder.record(event),
+            _ => Ok(()),
+        };
+        if let Err(err) = record_result {
+            eprintln!("Gamepad: {:#}; no longer recording.", err);
+            recorder = None;
+        }
+
+        // Stop once the gamepad is gone, or once the main thread is no longer listening.
+        let failed = event.is_err();
+        if events.send(event).is_err() || failed {
+            return;
+        }
+    }
+}
+
+/// Drives per the gamepad until its exit button or the brick's backspace button is pushed.
+///
+/// Parameters:
+/// * `motor_set`: the [LargeMotorSet] to drive
+/// * `events`: the channel that the gamepad's events are received from
+/// * `mapping`: the [Mapping] to read the gamepad's events with
+/// * `cliff_detector`: the [CliffDetector] (if any) to refuse to drive forwards over a cliff with
+/// * `buttons`: the brick's buttons
+fn drive(
+    motor_set: &LargeMotorSet,
+    events: &Receiver<Result<InputEvent>>,
+    mapping: Mapping,
+    cliff_detector: Option<&CliffDetector>,
+    buttons: &Ev3Button,
+) -> Result<()> {
+    let mut gamepad = Gamepad::new(mapping);
+    let mut current = DriveCommand::default();
+    loop {
+        buttons.process();
+        if buttons.get_pressed_buttons().contains("backspace") {
+            return Ok(());
+        }
+
+        match events.recv_timeout(POLL_INTERVAL) {
+            Ok(event) => {
+                let event = event.context("Gamepad disconnected.")?;
+                gamepad.handle(&event);
+
+                // Wait for the rest of the batch, so that e.g. a diagonal stick movement is
+                //   applied all at once.
+                if !event.is_report() {
+                    continue;
+                }
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => return Err(anyhow!("Gamepad disconnected.")),
+        }
+        if gamepad.exit_requested() {
+            return Ok(());
+        }
+
+        let mut command = gamepad.command();
+        let cliff = match cliff_detector {
+            Some(cliff_detector) => cliff_detector.is_cliff().map_err(ev3_error)?,
+            None => false,
+        };
+        // Never drive forwards over a cliff, no matter what the gamepad says.
+        if cliff && command.is_forward() {
+            command = DriveCommand::default();
+        }
+
+        // Only re-issue motor commands when the wheel speeds change.
+        if command != current {
+            match cliff {
+                true => println!("Gamepad: {:?} (cliff detected)", command),
+                false => println!("Gamepad: {:?}", command),
+            }
+            apply_command(motor_set, command).map_err(ev3_error)?;
+            current = command;
+        }
+    }
+}
+
+/// Runs the events in a recording through the specified [Mapping], and prints out the resulting
+///   [DriveCommand]s, without touching the motors.
+///
+/// Parameters:
+/// * `path`: the recording to replay
+/// * `mapping`: the [Mapping] to read the recorded events with
+fn replay(path: &Path, mapping: Mapping) -> Result<()> {
+    let events = evdev::read_recording(path)?;
+    let start = match events.first() {
+        Some(event) => event.time,
+        None => return Err(anyhow!("Recording '{}' is empty.", path.display())),
+    };
+
+    let mut gamepad = Gamepad::new(mapping);
+    let mut current = DriveCommand::default();
+    for event in &events {
+        gamepad.handle(event);
+        if !event.is_report() {
+            continue;
+        }
+
+        let elapsed = event.time.checked_sub(start).unwrap_or_default();
+        if gamepad.exit_requested() {
+            println!("[{:>8.3}s] exit", elapsed.as_secs_f32());
+            return Ok(());
+        }
+
+        let command = gamepad.command();
+        if command != current {
+            println!(
+                "[{:>8.3}s] left: {}, right: {}",
+                elapsed.as_secs_f32(),
+                command.left,
+                command.right
+            );
+            current = command;
+        }
+    }
+
+    Ok(())
+}
+
+/// Updates the motor settings to match the specified [DriveCommand].
+///
+/// Parameters:
+/// * `motor_set`: the [LargeMotorSet] to update
+/// * `command`: the [DriveCommand] to apply
+fn apply_command(motor_set: &LargeMotorSet, command: DriveCommand) -> Result<(), Ev3Error> {
+    if command == DriveCommand::default() {
+        motor_set.set_stop_action("brake")?;
+        return motor_set.stop();
+    }
+
+    for (motor, speed) in motor_set
+        .motors
+        .iter()
+        .zip(vec![command.left, command.right])
+    {
+        motor.set_speed_sp(speed)?;
+    }
+    motor_set.run_forever()
+}
@@ -0,0 +1,300 @@
+//! Maps a gamepad's axes and buttons onto [DriveCommand]s, per a [Mapping].
+//!
+//! Gamepads don't agree on much: which axis codes their sticks report on, what ranges those axes
+//!   cover, or which way is up.
+//! So, while the default [Mapping] works for Xbox-style gamepads, other ones can be described by
+//!   a JSON mapping file, specified via the `REMOTE_CONTROL_GAMEPAD_MAPPING` environment
+//!   variable, e.g.:
+//!
+//! ```json
+//! {
+//!   "throttle": { "code": 1, "min": 0, "max": 255, "invert": true },
+//!   "steering": { "code": 3, "min": 0, "max": 255 },
+//!   "deadzone": 0.1,
+//!   "stop_button": 304,
+//!   "exit_button": 316
+//! }
+//! ```
+//!
+//! Any fields that are left out keep their default values.
+//! The codes are the ones from the kernel's `linux/input-event-codes.h`, which can also be found
+//!   by running `evtest` on the brick, or by recording the gamepad (see [crate::evdev]).
+
+use std::path::Path;
+
+use anyhow::{anyhow, Context, Result};
+use serde::Deserialize;
+
+use crate::evdev::{InputEvent, EV_ABS, EV_KEY};
+
+/// The environment variable that can be used to specify a mapping file.
+const MAPPING_VAR: &str = "REMOTE_CONTROL_GAMEPAD_MAPPING";
+
+/// The absolute motor speed (in tacho counts per second) that a fully deflected stick drives at,
+///   which matches the web remote's max speed.
+const SPEED_MAX_ABSOLUTE: f32 = 900.0;
+
+/// Wheel speeds are rounded off to this fraction of [SPEED_MAX_ABSOLUTE], so that the sticks'
+///   jitter doesn't cause a constant stream of motor updates.
+const SPEED_STEP: f32 = 0.05;
+
+/// Describes how to read a gamepad's axes and buttons.
+#[derive(Clone, Copy, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Mapping {
+    /// The axis that drives forwards (up) and backwards (down).
+    throttle: AxisMapping,
+    /// The axis that steers left and right.
+    steering: AxisMapping,
+    /// How far (as a fraction of full deflection) the sticks have to move from center before
+    ///   they have any effect.
+    deadzone: f32,
+    /// The code of the button that stops the robot while it's held, if any.
+    stop_button: Option<u16>,
+    /// The code of the button that exits the program, if any.
+    exit_button: Option<u16>,
+}
+
+impl Default for Mapping {
+    /// Returns the [Mapping] for Xbox-style gamepads, as read by the kernel's `xpad` driver: the
+    ///   left stick is the throttle, the right stick steers, the "A" button stops, and the
+    ///   "Xbox" button exits.
+    fn default() -> Mapping {
+        Mapping {
+            throttle: AxisMapping {
+                code: 1, // ABS_Y
+                min: -32768,
+                max: 32767,
+                invert: true,
+            },
+            steering: AxisMapping {
+                code: 3, // ABS_RX
+                min: -32768,
+                max: 32767,
+                invert: false,
+            },
+            deadzone: 0.1,
+            stop_button: Some(304), // BTN_SOUTH
+            exit_button: Some(316), // BTN_MODE
+        }
+    }
+}
+
+impl Mapping {
+    /// Loads the [Mapping] from the file specified by the `REMOTE_CONTROL_GAMEPAD_MAPPING`
+    ///   environment variable, or returns the default [Mapping] if it isn't set.
+    pub fn load() -> Result<Mapping> {
+        match std::env::var(MAPPING_VAR) {
+            Ok(path) => Mapping::from_file(Path::new(&path)),
+            Err(_) => Ok(Mapping::default()),
+        }
+    }
+
+    /// Loads the [Mapping] from the specified JSON file.
+    ///
+    /// Parameters:
+    /// * `path`: the mapping file to read
+    fn from_file(path: &Path) -> Result<Mapping> {
+        let json = std::fs::read_to_string(path)
+            .with_context(|| format!("Unable to read mapping '{}'.", path.display()))?;
+        let mapping: Mapping = serde_json::from_str(&json)
+            .with_context(|| format!("Invalid mapping '{}'.", path.display()))?;
+
+        if !(0.0..1.0).contains(&mapping.deadzone) {
+            return Err(anyhow!(
+                "Invalid mapping '{}': the deadzone must be at least 0.0 and less than 1.0.",
+                path.display()
+            ));
+        }
+        for axis in &[mapping.throttle, mapping.steering] {
+            if axis.min >= axis.max {
+                return Err(anyhow!(
+                    "Invalid mapping '{}': axis {} has a min that isn't less than its max.",
+                    path.display(),
+                    axis.code
+                ));
+            }
+        }
+
+        Ok(mapping)
+    }
+}
+
+/// Describes how to read one of a gamepad's axes.
+#[derive(Clone, Copy, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct AxisMapping {
+    /// The axis' event code, e.g. `1` for `ABS_Y`.
+    code: u16,
+    /// The value the axis reports at one end of its travel (left or up).
+    min: i32,
+    /// The value the axis reports at the other end of its travel (right or down).
+    max: i32,
+    /// Whether the axis should be flipped, e.g. because up is reported as the minimum.
+    #[serde(default)]
+    invert: bool,
+}
+
+impl AxisMapping {
+    /// Converts a raw axis value into a position from `-1.0` to `1.0`.
+    ///
+    /// Parameters:
+    /// * `value`: the raw value reported by the gamepad
+    fn normalize(&self, value: i32) -> f32 {
+        let center = (self.min as f32 + self.max as f32) / 2.0;
+        let half_range = (self.max as f32 - self.min as f32) / 2.0;
+        let position = ((value as f32 - center) / half_range).max(-1.0).min(1.0);
+        match self.invert {
+            true => -position,
+            false => position,
+        }
+    }
+}
+
+/// Which speed (in tacho counts per second) each wheel should be driven at.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct DriveCommand {
+    pub left: i32,
+    pub right: i32,
+}
+
+impl DriveCommand {
+    /// Returns `true` if this would drive the robot forwards, e.g. towards a cliff.
+    pub fn is_forward(&self) -> bool {
+        self.left + self.right > 0
+    }
+}
+
+/// Tracks the state of a gamepad, as read from its [InputEvent]s.
+pub struct Gamepad {
+    mapping: Mapping,
+    throttle: f32,
+    steering: f32,
+    stop_pressed: bool,
+    exit_pressed: bool,
+}
+
+impl Gamepad {
+    /// Constructs a new [Gamepad], with its sticks centered and nothing pressed.
+    ///
+    /// Parameters:
+    /// * `mapping`: the [Mapping] to read the gamepad's events with
+    pub fn new(mapping: Mapping) -> Gamepad {
+        Gamepad {
+            mapping,
+            throttle: 0.0,
+            steering: 0.0,
+            stop_pressed: false,
+            exit_pressed: false,
+        }
+    }
+
+    /// Updates the state of this [Gamepad] per the specified [InputEvent].
+    /// Events for axes and buttons that aren't mapped are ignored.
+    pub fn handle(&mut self, event: &InputEvent) {
+        match event.kind {
+            EV_ABS if event.code == self.mapping.throttle.code => {
+                self.throttle = self.mapping.throttle.normalize(event.value);
+            }
+            EV_ABS if event.code == self.mapping.steering.code => {
+                self.steering = self.mapping.steering.normalize(event.value);
+            }
+            // A value of `0` is a release, `1` is a press, and `2` is a key repeat.
+            EV_KEY if Some(event.code) == self.mapping.stop_button => {
+                self.stop_pressed = event.value != 0;
+            }
+            EV_KEY if Some(event.code) == self.mapping.exit_button => {
+                self.exit_pressed = event.value != 0;
+            }
+            _ => {}
+        }
+    }
+
+    /// Returns `true` if the exit button has been pressed.
+    pub fn exit_requested(&self) -> bool {
+        self.exit_pressed
+    }
+
+    /// Returns the [DriveCommand] for the gamepad's current state.
+    ///
+    /// The throttle and steering are mixed "arcade style": steering speeds up one wheel and slows
+    ///   down the other, so that steering with no throttle spins the robot in place.
+    pub fn command(&self) -> DriveCommand {
+        if self.stop_pressed {
+            return DriveCommand::default();
+        }
+
+        let throttle = self.apply_deadzone(self.throttle);
+        let steering = self.apply_deadzone(self.steering);
+        let speed = |percent: f32| {
+            let percent = percent.max(-1.0).min(1.0);
+            let percent = (percent / SPEED_STEP).round() * SPEED_STEP;
+            (percent * SPEED_MAX_ABSOLUTE).round() as i32
+        };
+
+        DriveCommand {
+            left: speed(throttle + steering),
+            right: speed(throttle - steering),
+        }
+    }
+
+    /// Zeroes out the specified stick position if it's within the deadzone, and otherwise
+    ///   rescales it so that the output still starts at zero.
+    fn apply_deadzone(&self, position: f32) -> f32 {
+        let deadzone = self.mapping.deadzone;
+        if position.abs() <= deadzone {
+            return 0.0;
+        }
+
+        position.signum() * (position.abs() - deadzone) / (1.0 - deadzone)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+    use crate::evdev::read_recording;
+
+    #[test]
+    fn maps_xbox_recording() {
+        let recording = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("recordings")
+            .join("xbox-forward-then-spin.txt");
+        let events = read_recording(&recording).expect("recording should be readable");
+
+        // Collect the command for each batch of events, up until the exit button is pressed.
+        let mut gamepad = Gamepad::new(Mapping::default());
+        let mut commands = Vec::new();
+        for event in &events {
+            gamepad.handle(event);
+            if event.is_report() {
+                if gamepad.exit_requested() {
+                    break;
+                }
+                let command = gamepad.command();
+                commands.push((command.left, command.right));
+            }
+        }
+
+        assert!(gamepad.exit_requested());
+        assert_eq!(
+            commands,
+            vec![
+                // The left stick's drift is inside of the deadzone.
+                (0, 0),
+                // Halfway up, past the deadzone, and rounded off to a multiple of 45.
+                (405, 405),
+                // All the way up.
+                (900, 900),
+                // Released.
+                (0, 0),
+                // The right stick all the way right, which spins in place.
+                (900, -900),
+                // Released.
+                (0, 0),
+            ]
+        );
+    }
+}
@@ -0,0 +1,199 @@
+//! A remote controlled driving routine for the [ev3dev platform](https://www.ev3dev.org/),
+//!   which uses the official EV3 infrared remote control (a.k.a. the beacon),
+//!   so no wifi adapter is needed.
+//!
+//! Usage:
+//!
+//! ```text
+//! remote-control-ir-rs [channel]
+//! ```
+//!
+//! If a channel (`1` to `4`) is specified, only the remote set to that channel will be listened
+//!   to. Otherwise, whichever channel has a button pressed will be used.
+//!
+//! The remote's buttons work like a tank's controls: the red buttons drive the left wheel, and
+//!   the blue buttons drive the right wheel.
+
+use std::time::Duration;
+
+use ev3_common::cliff::CliffDetector;
+use ev3_common::motors::LargeMotorSet;
+use ev3dev_lang_rust::sensors::{InfraredSensor, Sensor};
+use ev3dev_lang_rust::{Ev3Button, Ev3Result};
+
+/// How often the remote's buttons are checked.
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+/// The absolute motor speed (in tacho counts per second) that a pressed button drives its wheel
+///   at, which matches the web remote's max speed.
+const SPEED_MAX_ABSOLUTE: i32 = 900;
+
+fn main() -> Ev3Result<()> {
+    let channel = match std::env::args().nth(1) {
+        Some(arg) => match arg.parse::<u8>() {
+            Ok(channel @ 1..=4) => Some(channel),
+            _ => {
+                eprintln!("Usage: remote-control-ir-rs [channel], where channel is 1 to 4.");
+                std::process::exit(1);
+            }
+        },
+        None => None,
+    };
+
+    // Get motors and sensors.
+    let motor_set = LargeMotorSet::new()?;
+    let remote = RemoteControl::find(channel)?;
+    let cliff_detector = CliffDetector::find();
+    let buttons = Ev3Button::new()?;
+
+    println!("IR remote: ready. Press backspace on the brick to exit.");
+    let result = drive(&motor_set, &remote, cliff_detector.as_ref(), &buttons);
+
+    // Whatever happened, make sure the motors end up stopped.
+    motor_set.set_stop_action("brake")?;
+    motor_set.stop()?;
+    motor_set.wait_until_not_moving(None);
+    println!("IR remote: stopped. Bye!");
+
+    result
+}
+
+/// Drives per the remote's buttons until the brick's backspace button is pushed.
+///
+/// Parameters:
+/// * `motor_set`: the [LargeMotorSet] to drive
+/// * `remote`: the [RemoteControl] to read the buttons of
+/// * `cliff_detector`: the [CliffDetector] (if any) to refuse to drive forwards over a cliff with
+/// * `buttons`: the brick's buttons
+fn drive(
+    motor_set: &LargeMotorSet,
+    remote: &RemoteControl,
+    cliff_detector: Option<&CliffDetector>,
+    buttons: &Ev3Button,
+) -> Ev3Result<()> {
+    let mut current = RemoteButtons::default();
+    loop {
+        buttons.process();
+        if buttons.get_pressed_buttons().contains("backspace") {
+            return Ok(());
+        }
+
+        let mut pressed = remote.get_pressed()?;
+        let cliff = match cliff_detector {
+            Some(cliff_detector) => cliff_detector.is_cliff()?,
+            None => false,
+        };
+        // Never drive forwards over a cliff, no matter what the remote says.
+        if cliff && pressed.is_forward() {
+            pressed = RemoteButtons::default();
+        }
+
+        // Only re-issue motor commands when the pressed buttons change.
+        if pressed != current {
+            match cliff {
+                true => println!("IR remote: {:?} (cliff detected)", pressed),
+                false => println!("IR remote: {:?}", pressed),
+            }
+            apply_buttons(motor_set, pressed)?;
+            current = pressed;
+        }
+
+        std::thread::sleep(POLL_INTERVAL);
+    }
+}
+
+/// Updates the motor settings to match the specified [RemoteButtons].
+///
+/// Parameters:
+/// * `motor_set`: the [LargeMotorSet] to update
+/// * `pressed`: the [RemoteButtons] that are currently pressed
+fn apply_buttons(motor_set: &LargeMotorSet, pressed: RemoteButtons) -> Ev3Result<()> {
+    if pressed.left == 0 && pressed.right == 0 {
+        motor_set.set_stop_action("brake")?;
+        return motor_set.stop();
+    }
+
+    for (motor, direction) in motor_set
+        .motors
+        .iter()
+        .zip(vec![pressed.left, pressed.right])
+    {
+        motor.set_speed_sp(SPEED_MAX_ABSOLUTE * direction as i32)?;
+    }
+    motor_set.run_forever()
+}
+
+/// Which way each wheel should be driven, per the remote's buttons: `1` for forwards, `-1` for
+///   backwards, or `0` for stopped.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+struct RemoteButtons {
+    left: i8,
+    right: i8,
+}
+
+impl RemoteButtons {
+    /// Decodes the specified button code, as reported by an [InfraredSensor] in remote mode.
+    fn from_code(code: i32) -> RemoteButtons {
+        let (left, right) = match code {
+            1 => (1, 0),   // red up
+            2 => (-1, 0),  // red down
+            3 => (0, 1),   // blue up
+            4 => (0, -1),  // blue down
+            5 => (1, 1),   // red up + blue up
+            6 => (1, -1),  // red up + blue down
+            7 => (-1, 1),  // red down + blue up
+            8 => (-1, -1), // red down + blue down
+            // Nothing, the beacon button, or both buttons of the same color.
+            _ => (0, 0),
+        };
+
+        RemoteButtons { left, right }
+    }
+
+    /// Returns `true` if this would drive the robot forwards, e.g. towards a cliff.
+    fn is_forward(&self) -> bool {
+        self.left + self.right > 0
+    }
+}
+
+/// Reads the buttons of the EV3 infrared remote control via an [InfraredSensor].
+struct RemoteControl {
+    sensor: InfraredSensor,
+    channel: Option<u8>,
+}
+
+impl RemoteControl {
+    /// Finds the infrared sensor and switches it into remote mode.
+    ///
+    /// Parameters:
+    /// * `channel`: the channel (`1` to `4`) to listen to, or `None` to listen to all of them
+    fn find(channel: Option<u8>) -> Ev3Result<RemoteControl> {
+        let sensor = InfraredSensor::find()?;
+        sensor.set_mode_ir_remote()?;
+        match channel {
+            Some(channel) => println!("IR remote: listening on channel {}.", channel),
+            None => println!("IR remote: listening on all channels."),
+        }
+
+        Ok(RemoteControl { sensor, channel })
+    }
+
+    /// Returns the buttons that are currently pressed.
+    fn get_pressed(&self) -> Ev3Result<RemoteButtons> {
+        let codes = [
+            self.sensor.get_value0()?,
+            self.sensor.get_value1()?,
+            self.sensor.get_value2()?,
+            self.sensor.get_value3()?,
+        ];
+
+        Ok(match self.channel {
+            Some(channel) => RemoteButtons::from_code(codes[channel as usize - 1]),
+            None => codes
+                .iter()
+                .map(|code| RemoteButtons::from_code(*code))
+                .find(|pressed| *pressed != RemoteButtons::default())
+                .unwrap_or_default(),
+        })
+    }
+}
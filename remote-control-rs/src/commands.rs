@@ -0,0 +1,195 @@
+//! Keeps track of the motion commands (moves, turns, and wheel rotations), which run in the
+//!   background, so that the endpoints that start them can return right away.
+//!
+//! Each command gets an ID, which its endpoint returns, and which can then be used to check on it
+//!   via `GET /commands/{id}`, or to cancel it via `DELETE /commands/{id}`.
+//! Only one command runs at a time: starting a new one supersedes whichever one was running.
+
+use std::collections::VecDeque;
+
+use actix_web::{delete, get, web, HttpRequest, HttpResponse};
+use ev3_common::pairing::Pairing;
+use serde::Serialize;
+
+use crate::device::{Device, DeviceCommand, Robot};
+use crate::Ev3ErrorWrapper;
+
+/// How many of the most recent commands are remembered, for checking on.
+const HISTORY_MAX: usize = 32;
+
+/// The status of a motion command.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CommandStatus {
+    /// The command is still running.
+    Running,
+    /// The command ran to completion.
+    Done,
+    /// The command was stopped partway, e.g. by the collision guard or the emergency stop.
+    Stopped,
+    /// The command failed, e.g. because it didn't complete in time.
+    Failed,
+    /// The command was cancelled via `DELETE /commands/{id}`.
+    Cancelled,
+    /// A newer command was started before this one finished.
+    Superseded,
+}
+
+/// A motion command, as reported to clients.
+#[derive(Clone, Serialize)]
+pub struct Command {
+    pub id: u64,
+    /// What the command does, e.g. `"move forward 30 cm"`.
+    pub description: String,
+    pub status: CommandStatus,
+    /// Why the command stopped or failed, if it did.
+    pub message: Option<String>,
+}
+
+/// Tracks the running motion command (if any), along with the most recent ones.
+#[derive(Clone)]
+pub struct Commands {
+    /// The ID of the last command that was started.
+    last_id: u64,
+    /// The ID of the command that's currently running, if any.
+    running: Option<u64>,
+    /// The most recent commands, oldest first.
+    history: VecDeque<Command>,
+}
+
+impl Commands {
+    /// Constructs a new [Commands], with no commands yet.
+    pub fn new() -> Commands {
+        Commands {
+            last_id: 0,
+            running: None,
+            history: VecDeque::with_capacity(HISTORY_MAX),
+        }
+    }
+
+    /// Returns the command with the specified ID, if it's recent enough to still be remembered.
+    pub fn get(&self, id: u64) -> Option<&Command> {
+        self.history.iter().find(|command| command.id == id)
+    }
+
+    /// Starts a new command, superseding the one that's running (if any), and returns it.
+    ///
+    /// Parameters:
+    /// * `description`: what the command does, which is reported to clients
+    pub fn start(&mut self, description: String) -> Command {
+        if let Some(running) = self.running {
+            self.finish(running, CommandStatus::Superseded, None);
+        }
+
+        self.last_id += 1;
+        let command = Command {
+            id: self.last_id,
+            description,
+            status: CommandStatus::Running,
+            message: None,
+        };
+        if self.history.len() >= HISTORY_MAX {
+            self.history.pop_front();
+        }
+        self.history.push_back(command.clone());
+        self.running = Some(command.id);
+
+        command
+    }
+
+    /// Returns `true` if the specified command is the one that's currently running.
+    pub fn is_running(&self, id: u64) -> bool {
+        self.running == Some(id)
+    }
+
+    /// Ends the specified command, if it's the one that's currently running.
+    /// The motors are left as they are, for the caller to deal with.
+    ///
+    /// Parameters:
+    /// * `id`: the ID of the command to end
+    /// * `status`: how the command ended
+    /// * `message`: why the command ended, if it stopped or failed
+    pub fn finish(&mut self, id: u64, status: CommandStatus, message: Option<String>) {
+        if !self.is_running(id) {
+            return;
+        }
+
+        self.running = None;
+        if let Some(command) = self.history.iter_mut().find(|command| command.id == id) {
+            if let Some(message) = &message {
+                println!(
+                    "Commands: {} {:?}: {}",
+                    command.description, status, message
+                );
+            }
+            command.status = status;
+            command.message = message;
+        }
+    }
+}
+
+impl Robot {
+    /// Cancels the specified motion [Command], braking the robot, if it's still running, and
+    ///   returns it.
+    ///
+    /// Parameters:
+    /// * `id`: the ID of the [Command] to cancel
+    pub fn cancel_command(&mut self, id: u64) -> actix_web::Result<Command> {
+        if self.commands.is_running(id) {
+            self.commands.finish(id, CommandStatus::Cancelled, None);
+            self.wheel_move = None;
+            let motor_set = &self.ev3_devices.motor_set;
+            motor_set
+                .set_stop_action("brake")
+                .and_then(|_| motor_set.stop())
+                .map_err(Ev3ErrorWrapper::from)?;
+        }
+
+        self.commands
+            .get(id)
+            .cloned()
+            .ok_or_else(|| actix_web::error::ErrorNotFound("No such command."))
+    }
+}
+
+/// This API endpoint returns the specified motion [Command], so that clients can check on it.
+///
+/// Parameters:
+/// * `device`: the [Device] handle managed/shared by the application
+/// * `id`: the ID of the [Command] to return
+#[get("/commands/{id}")]
+pub async fn get_command(
+    device: web::Data<Device>,
+    id: web::Path<u64>,
+) -> actix_web::Result<HttpResponse> {
+    let commands = device.state().commands;
+    let command = commands
+        .get(*id)
+        .ok_or_else(|| actix_web::error::ErrorNotFound("No such command."))?;
+
+    Ok(HttpResponse::Ok().json(command))
+}
+
+/// This API endpoint cancels the specified motion [Command], braking the robot, if it's still
+///   running.
+///
+/// Parameters:
+/// * `request`: the HTTP request, which must include the client's session cookie
+/// * `pairing`: the [Pairing] instance managed/shared by the application
+/// * `device`: the [Device] handle managed/shared by the application
+/// * `id`: the ID of the [Command] to cancel
+#[delete("/commands/{id}")]
+pub async fn cancel_command(
+    request: HttpRequest,
+    pairing: web::Data<Pairing>,
+    device: web::Data<Device>,
+    id: web::Path<u64>,
+) -> actix_web::Result<HttpResponse> {
+    pairing.require_session(&request)?;
+    let id = *id;
+    let command = device
+        .send(|reply| DeviceCommand::CancelCommand { id, reply })
+        .await?;
+
+    Ok(HttpResponse::Ok().json(command))
+}
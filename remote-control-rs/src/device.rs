@@ -0,0 +1,145 @@
+//! Provides the commands and state for the device actor (see [ev3_common::device]), which owns
+//!   the motors and sensors, along with everything else that's needed to move them safely (the
+//!   motion [Commands], the move that's running, and the emergency stop).
+//!
+//! Request handlers send the actor a [DeviceCommand] via its [Device] handle, and wait for its
+//!   reply.
+//! Between requests, the actor is also ticked every [crate::CLIFF_POLL_INTERVAL], which it uses
+//!   to watch over the move that's running and to check the brick's buttons.
+//! After every command, it publishes a [DeviceState], so that handlers that only need to read the
+//!   state don't have to wait on the actor at all.
+
+use std::time::Duration;
+
+use ev3_common::buttons::BrickButtons;
+use ev3_common::device::{respond, Actor, DeviceError, Reply};
+use ev3_common::guard::Intervention;
+
+use crate::commands::{Command, Commands};
+use crate::estop::EStop;
+use crate::{
+    Ev3Devices, Ev3ErrorWrapper, TelemetryData, WheelMove, CLIFF_POLL_INTERVAL, STOP_TIMEOUT,
+};
+
+/// The handle that the rest of the application uses to talk to the device actor.
+pub type Device = ev3_common::device::Device<Robot>;
+
+/// The commands that the device actor handles.
+pub enum DeviceCommand {
+    /// Starts a new motion [Command], via [Robot::start_wheel_move()].
+    StartMove {
+        description: String,
+        left_degrees: f32,
+        right_degrees: f32,
+        reply: Reply<(Command, Option<Intervention>)>,
+    },
+    /// Cancels a motion [Command], via [Robot::cancel_command()].
+    CancelCommand { id: u64, reply: Reply<Command> },
+    /// Engages the emergency stop, via [Robot::engage_estop()].
+    EngageEStop { source: String, reply: Reply<EStop> },
+    /// Clears the emergency stop, via [Robot::clear_estop()].
+    ClearEStop { reply: Reply<EStop> },
+    /// Reads the current [TelemetryData].
+    ReadTelemetry { reply: Reply<TelemetryData> },
+}
+
+/// The state that the device actor publishes after every [DeviceCommand].
+#[derive(Clone)]
+pub struct DeviceState {
+    pub commands: Commands,
+    pub estop: EStop,
+}
+
+/// Everything that's owned by the device actor.
+pub struct Robot {
+    pub ev3_devices: Ev3Devices,
+    pub commands: Commands,
+    /// The move that's running, if any.
+    pub wheel_move: Option<WheelMove>,
+    pub estop: EStop,
+    /// The brick's buttons, which engage the emergency stop, if they can be read.
+    pub buttons: Option<BrickButtons>,
+}
+
+impl Robot {
+    /// Constructs a [Robot] for the device actor to own.
+    pub fn new() -> Result<Robot, Ev3ErrorWrapper> {
+        Ok(Robot {
+            ev3_devices: Ev3Devices::new()?,
+            commands: Commands::new(),
+            wheel_move: None,
+            estop: EStop::new(),
+            buttons: BrickButtons::find(),
+        })
+    }
+}
+
+impl Actor for Robot {
+    type Command = DeviceCommand;
+    type State = DeviceState;
+
+    const TICK_INTERVAL: Duration = CLIFF_POLL_INTERVAL;
+    const STOP_TIMEOUT: Duration = STOP_TIMEOUT;
+
+    /// Returns the [DeviceState] to publish.
+    fn state(&self) -> DeviceState {
+        DeviceState {
+            commands: self.commands.clone(),
+            estop: self.estop.clone(),
+        }
+    }
+
+    fn handle(&mut self, command: DeviceCommand) {
+        match command {
+            DeviceCommand::StartMove {
+                description,
+                left_degrees,
+                right_degrees,
+                reply,
+            } => respond(
+                reply,
+                self.start_wheel_move(description, left_degrees, right_degrees),
+            ),
+            DeviceCommand::CancelCommand { id, reply } => respond(reply, self.cancel_command(id)),
+            DeviceCommand::EngageEStop { source, reply } => {
+                respond(reply, self.engage_estop(Some(&source)))
+            }
+            DeviceCommand::ClearEStop { reply } => {
+                let _ = reply.send(Ok(self.clear_estop()));
+            }
+            DeviceCommand::ReadTelemetry { reply } => {
+                respond(reply, self.ev3_devices.read_telemetry())
+            }
+        }
+    }
+
+    /// Engages the emergency stop if any of the brick's buttons has been pressed, and then watches
+    ///   over the move that's running (if any).
+    fn tick(&mut self) {
+        if self
+            .buttons
+            .as_mut()
+            .map_or(false, BrickButtons::newly_pressed)
+        {
+            if let Err(err) = self.engage_estop(Some("brick button")) {
+                eprintln!("E-stop: {}", err);
+            }
+        }
+        self.check_wheel_move();
+    }
+
+    /// Engages the emergency stop, ahead of the [DeviceCommand::EngageEStop] that goes with it.
+    fn stop_signalled(&mut self) {
+        if let Err(err) = self.engage_estop(None) {
+            eprintln!("E-stop: {}", err);
+        }
+    }
+
+    fn stop(&mut self) -> Result<(), DeviceError> {
+        Ok(self.ev3_devices.motor_set.stop()?)
+    }
+
+    fn is_running(&self) -> Result<bool, DeviceError> {
+        Ok(self.ev3_devices.motor_set.is_running()?)
+    }
+}
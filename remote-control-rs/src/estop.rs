@@ -0,0 +1,166 @@
+//! Provides an emergency stop, which brakes the robot and then refuses to move it at all until
+//!   it's explicitly cleared.
+//!
+//! Any paired client can engage the emergency stop via `POST /estop`, and so can anyone standing
+//!   next to the robot, by pressing any of the brick's buttons.
+//! It stays engaged until a client clears it via `DELETE /estop`.
+//! While it's engaged, every move is refused with `409 Conflict`, and any move that's already
+//!   running is stopped partway.
+//!
+//! A client's request jumps the device actor's queue (via [Device::signal_stop()]), so that the
+//!   robot is braked before any of the moves already waiting on the actor can start.
+
+use actix_web::{delete, get, post, web, HttpRequest, HttpResponse};
+use ev3_common::pairing::Pairing;
+use serde::Serialize;
+
+use crate::commands::CommandStatus;
+use crate::device::{Device, DeviceCommand, Robot};
+use crate::Ev3ErrorWrapper;
+
+/// The state of the emergency stop, which is owned by the device actor.
+#[derive(Clone, Serialize)]
+pub struct EStop {
+    /// Whether or not the emergency stop is engaged.
+    pub engaged: bool,
+    /// What engaged the emergency stop, e.g. `"brick button"` or a client's IP address, if it's
+    ///   engaged.
+    pub source: Option<String>,
+}
+
+impl EStop {
+    /// Constructs a new [EStop], which isn't engaged.
+    pub fn new() -> EStop {
+        EStop {
+            engaged: false,
+            source: None,
+        }
+    }
+
+    /// Returns an error if the emergency stop is engaged.
+    pub fn require_clear(&self) -> actix_web::Result<()> {
+        if !self.engaged {
+            return Ok(());
+        }
+
+        Err(actix_web::error::ErrorConflict(format!(
+            "Emergency stop engaged by {}: clear it to move.",
+            self.source.as_deref().unwrap_or("unknown")
+        )))
+    }
+
+    /// Returns why a move was stopped partway by the emergency stop, which is reported to
+    ///   clients.
+    pub fn move_stopped_reason(&self) -> String {
+        format!(
+            "Emergency stop engaged by {}: move stopped.",
+            self.source.as_deref().unwrap_or("unknown")
+        )
+    }
+}
+
+impl Robot {
+    /// Engages the emergency stop, and brakes the robot.
+    /// Any move that's running is finished as stopped, with the emergency stop as the reason.
+    ///
+    /// The emergency stop is latched even if braking fails, so that nothing else can move the
+    ///   robot.
+    ///
+    /// Parameters:
+    /// * `source`: what engaged the emergency stop, which is reported to clients, or `None` if it
+    ///   was signalled ahead of the request that says who engaged it
+    pub fn engage_estop(&mut self, source: Option<&str>) -> Result<EStop, Ev3ErrorWrapper> {
+        if !self.estop.engaged || (self.estop.source.is_none() && source.is_some()) {
+            match source {
+                Some(source) => println!("E-stop: engaged by {}.", source),
+                None => println!("E-stop: engaged, ahead of the request."),
+            }
+            self.estop.engaged = true;
+            self.estop.source = source.map(String::from);
+        }
+
+        if let Some(wheel_move) = self.wheel_move.take() {
+            self.commands.finish(
+                wheel_move.id,
+                CommandStatus::Stopped,
+                Some(self.estop.move_stopped_reason()),
+            );
+        }
+
+        let motor_set = &self.ev3_devices.motor_set;
+        motor_set.set_stop_action("brake")?;
+        motor_set.stop()?;
+
+        Ok(self.estop.clone())
+    }
+
+    /// Clears the emergency stop, if it's engaged.
+    pub fn clear_estop(&mut self) -> EStop {
+        if self.estop.engaged {
+            println!("E-stop: cleared.");
+            self.estop = EStop::new();
+        }
+
+        self.estop.clone()
+    }
+}
+
+/// This API endpoint returns whether the emergency stop is engaged, and if so, by what.
+///
+/// Parameters:
+/// * `device`: the [Device] handle managed/shared by the application
+#[get("/estop")]
+pub async fn get_estop(device: web::Data<Device>) -> HttpResponse {
+    HttpResponse::Ok().json(device.state().estop)
+}
+
+/// This API endpoint is called when the user clicks the "emergency stop" button in the web
+///   application.
+/// Brakes the robot (stopping any move that's running), and refuses to move it again until the
+///   emergency stop is cleared.
+///
+/// Parameters:
+/// * `request`: the HTTP request, which must include the client's session cookie
+/// * `pairing`: the [Pairing] instance managed/shared by the application
+/// * `device`: the [Device] handle managed/shared by the application
+#[post("/estop")]
+pub async fn engage_estop(
+    request: HttpRequest,
+    pairing: web::Data<Pairing>,
+    device: web::Data<Device>,
+) -> actix_web::Result<HttpResponse> {
+    pairing.require_session(&request)?;
+    device.signal_stop();
+
+    let source = request
+        .peer_addr()
+        .map(|address| address.ip().to_string())
+        .unwrap_or_else(|| "unknown".to_string());
+    let estop = device
+        .send(|reply| DeviceCommand::EngageEStop { source, reply })
+        .await?;
+
+    Ok(HttpResponse::Ok().json(estop))
+}
+
+/// This API endpoint is called when the user clicks the "clear emergency stop" button in the web
+///   application.
+/// Lets the robot be moved again.
+///
+/// Parameters:
+/// * `request`: the HTTP request, which must include the client's session cookie
+/// * `pairing`: the [Pairing] instance managed/shared by the application
+/// * `device`: the [Device] handle managed/shared by the application
+#[delete("/estop")]
+pub async fn clear_estop(
+    request: HttpRequest,
+    pairing: web::Data<Pairing>,
+    device: web::Data<Device>,
+) -> actix_web::Result<HttpResponse> {
+    pairing.require_session(&request)?;
+    let estop = device
+        .send(|reply| DeviceCommand::ClearEStop { reply })
+        .await?;
+
+    Ok(HttpResponse::Ok().json(estop))
+}
@@ -11,30 +11,108 @@
 //!   (and because it's fast).
 //! The frontend is just the webpage provided by the `./static/index.html` file.
 //!
-//! Everything here is kept to a single file as much as possible,
-//!   for simplicity's sake.
+//! The robot's devices are owned by a single device actor (see [device]),
+//!   which every endpoint sends its commands to,
+//!   and the background move commands and the emergency stop each live in their own module.
+//! The collision guard is shared with the fancy remote control, in `ev3_common::guard`.
 
-use actix_web::{get, web, App, HttpResponse, HttpServer};
+mod commands;
+mod device;
+mod estop;
+
+use actix_web::{get, web, App, HttpRequest, HttpResponse, HttpServer};
 use anyhow::{Context, Result};
-use ev3dev_lang_rust::motors::{LargeMotor, MotorPort};
-use std::time::Duration;
-use tokio::sync::Mutex;
+use commands::{Command, CommandStatus};
+use device::{Device, DeviceCommand, Robot};
+use ev3_common::device::DeviceError;
+use ev3_common::events::{self, Event, EventDetector};
+use ev3_common::guard::{CollisionGuard, GuardAction, Intervention};
+use ev3_common::motors::LargeMotorSet;
+use ev3_common::moves::{cm_to_wheel_degrees, turn_to_wheel_degrees};
+use ev3_common::pairing::{self, Pairing};
+use ev3_common::telemetry::{MotorTelemetry, SensorTelemetry, Sensors};
+use ev3dev_lang_rust::motors::LargeMotor;
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast;
+
+/// How far the move endpoints drive, unless told otherwise.
+const MOVE_CM_DEFAULT: f32 = 30.0;
+
+/// How far the turn endpoints turn the robot, unless told otherwise.
+const TURN_DEGREES_DEFAULT: f32 = 45.0;
+
+/// The furthest (in centimeters, in either direction) that a single move can drive.
+const MOVE_CM_MAX: f32 = 500.0;
+
+/// The furthest (in degrees, in either direction) that a single turn can turn the robot.
+const TURN_DEGREES_MAX: f32 = 3600.0;
+
+/// The furthest (in degrees, in either direction) that a single rotation can rotate each wheel,
+///   which is about as far as the longest move.
+const ROTATE_DEGREES_MAX: f32 = 10_800.0;
+
+/// The speed that all moves are run at, in degrees per second.
+const MOVE_SPEED: i32 = 750;
+
+/// How often the device actor checks for cliffs (and obstacles, and the emergency stop) while a
+///   move is running, and checks the brick's buttons.
+const CLIFF_POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+/// The longest (in seconds) that a single move can be expected to take.
+const MOVE_SECS_MAX: f32 = 600.0;
+
+/// How long the motors get to start running before a move is considered to have finished.
+const MOVE_START_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// How long the device actor gives the motors to stop before giving up on them, so that a motor
+///   that won't stop can't hold anything up.
+const STOP_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// The HTTP header that reports how the collision guard limited a move, if it did.
+const GUARD_HEADER: &str = "X-Collision-Guard";
+
+/// The environment variable that can be used to adjust how the infrared sensor's proximity
+///   readings are mapped to centimeters, if it's the proximity sensor.
+const IR_CM_PER_PERCENT_VAR: &str = "REMOTE_CONTROL_IR_CM_PER_PERCENT";
 
 /// The main method for the application, which will be run when the application is launched.
 /// It mostly just configures and runs the backend Actix webserver.
 #[actix_web::main]
 async fn main() -> Result<()> {
-    // Ev3 devices
-    let ev3_devices_app = web::Data::new(Mutex::new(Ev3Devices::new()?));
-    let ev3_devices_server = ev3_devices_app.clone();
+    // The device actor owns the Ev3 devices, and everything else that's needed to move them.
+    let device = Device::start(Robot::new()?);
+    let device_app = web::Data::new(device.clone());
+
+    // Only let clients that have entered the code shown on the brick's screen drive.
+    let pairing = web::Data::new(Pairing::new());
+
+    // Sample the telemetry for all of the `/events` subscribers.
+    let events = web::Data::new(events::channel());
+    actix_web::rt::spawn(sample_events(
+        events.clone(),
+        device.clone(),
+        events::telemetry_interval()?,
+    ));
+
     HttpServer::new(move || {
         App::new()
-            .app_data(ev3_devices_server.clone())
+            .app_data(device_app.clone())
+            .app_data(events.clone())
+            .app_data(pairing.clone())
             .service(index)
+            .service(pairing::pair)
+            .service(events::events)
             .service(move_forward)
             .service(move_backward)
             .service(turn_left)
             .service(turn_right)
+            .service(rotate_wheels)
+            .service(commands::get_command)
+            .service(commands::cancel_command)
+            .service(estop::get_estop)
+            .service(estop::engage_estop)
+            .service(estop::clear_estop)
     })
     .bind("0.0.0.0:8080")?
     .run()
@@ -42,9 +120,7 @@ async fn main() -> Result<()> {
     .context("Actix server errored.")?;
 
     // Make sure motors get stopped on exit.
-    let motor_set = &ev3_devices_app.lock().await.motor_set;
-    motor_set.stop()?;
-    motor_set.wait_until_not_moving(None);
+    device.shutdown().await?;
 
     Ok(())
 }
@@ -54,125 +130,466 @@ async fn main() -> Result<()> {
 ///
 /// Accessible by browsing to <http://ev3dev.local:8080/> from
 ///   another device on the same network as the EV3.
+/// Clients that haven't paired yet get the `./static/pair.html` page instead.
+///
+/// Parameters:
+/// * `request`: the HTTP request, which may include the client's session cookie
+/// * `pairing`: the [Pairing] instance managed/shared by the application
 #[get("/")]
-async fn index() -> HttpResponse {
+async fn index(request: HttpRequest, pairing: web::Data<Pairing>) -> HttpResponse {
+    let page = if pairing.is_paired(&request) {
+        include_str!("../static/index.html")
+    } else {
+        include_str!("../static/pair.html")
+    };
+
     HttpResponse::Ok()
         .content_type("text/html; charset=utf-8")
-        .body(include_str!("../static/index.html"))
+        .body(page)
+}
+
+/// Models the (optional) query parameters for [move_forward()] and [move_backward()].
+#[derive(Deserialize)]
+struct MoveParams {
+    /// How far to drive, in centimeters.
+    cm: Option<f32>,
 }
 
 /// This API endpoint is called when the user clicks the "go forward" button in the web application.
-/// Drives the robot straight ahead for a brief bit.
+/// Starts driving the robot straight ahead for a bit.
 ///
 /// Parameters:
-/// * `ev3_devices`: the [Ev3Devices] instance managed/shared by the application
+/// * `request`: the HTTP request, which must include the client's session cookie
+/// * `pairing`: the [Pairing] instance managed/shared by the application
+/// * `device`: the [Device] handle managed/shared by the application
+/// * `params`: the [MoveParams] specified in the API call
 #[get("/move/forward")]
 async fn move_forward(
-    ev3_devices: web::Data<Mutex<Ev3Devices>>,
+    request: HttpRequest,
+    pairing: web::Data<Pairing>,
+    device: web::Data<Device>,
+    params: web::Query<MoveParams>,
 ) -> actix_web::Result<HttpResponse> {
-    let motor_set = &ev3_devices.lock().await.motor_set;
+    pairing.require_session(&request)?;
 
     // Drive forward a bit.
-    motor_set.set_duty_cycle_sp(100)?;
-    motor_set.run_direct()?;
-    motor_set.wait_until(LargeMotor::STATE_RUNNING, None);
-    tokio::time::delay_for(Duration::from_millis(1000)).await;
-    motor_set.set_stop_action("coast")?;
-    motor_set.stop()?;
-
-    // Send the client back to the home page.
-    Ok(HttpResponse::Found()
-        .header(actix_web::http::header::LOCATION, "/")
-        .finish()
-        .into_body())
+    let cm = require_in_range("cm", params.cm.unwrap_or(MOVE_CM_DEFAULT), MOVE_CM_MAX)?;
+    let degrees = cm_to_wheel_degrees(cm);
+    let description = format!("move forward {} cm", cm);
+    request_wheel_move(&device, description, degrees, degrees).await
 }
 
 /// This API endpoint is called when the user clicks the "go backward" button in the web application.
-/// Drives the robot straight back for a brief bit.
+/// Starts driving the robot straight back for a bit.
 ///
 /// Parameters:
-/// * `ev3_devices`: the [Ev3Devices] instance managed/shared by the application
+/// * `request`: the HTTP request, which must include the client's session cookie
+/// * `pairing`: the [Pairing] instance managed/shared by the application
+/// * `device`: the [Device] handle managed/shared by the application
+/// * `params`: the [MoveParams] specified in the API call
 #[get("/move/backward")]
 async fn move_backward(
-    ev3_devices: web::Data<Mutex<Ev3Devices>>,
+    request: HttpRequest,
+    pairing: web::Data<Pairing>,
+    device: web::Data<Device>,
+    params: web::Query<MoveParams>,
 ) -> actix_web::Result<HttpResponse> {
-    let motor_set = &ev3_devices.lock().await.motor_set;
+    pairing.require_session(&request)?;
 
-    // Drive forward a bit.
-    motor_set.set_duty_cycle_sp(-100)?;
-    motor_set.run_direct()?;
-    motor_set.wait_until(LargeMotor::STATE_RUNNING, None);
-    tokio::time::delay_for(Duration::from_millis(1000)).await;
-    motor_set.set_stop_action("coast")?;
-    motor_set.stop()?;
-
-    // Send the client back to the home page.
-    Ok(HttpResponse::Found()
-        .header(actix_web::http::header::LOCATION, "/")
-        .finish()
-        .into_body())
+    // Drive backward a bit.
+    let cm = require_in_range("cm", params.cm.unwrap_or(MOVE_CM_DEFAULT), MOVE_CM_MAX)?;
+    let degrees = -cm_to_wheel_degrees(cm);
+    let description = format!("move backward {} cm", cm);
+    request_wheel_move(&device, description, degrees, degrees).await
+}
+
+/// Models the (optional) query parameters for [turn_left()] and [turn_right()].
+#[derive(Deserialize)]
+struct TurnParams {
+    /// How far to turn the robot, in degrees.
+    degrees: Option<f32>,
 }
 
 /// This API endpoint is called when the user clicks the "turn left" button in the web application.
-/// Turns the robot backwards and to the left a bit.
+/// Starts turning the robot in place to the left a bit.
 ///
 /// Parameters:
-/// * `ev3_devices`: the [Ev3Devices] instance managed/shared by the application
+/// * `request`: the HTTP request, which must include the client's session cookie
+/// * `pairing`: the [Pairing] instance managed/shared by the application
+/// * `device`: the [Device] handle managed/shared by the application
+/// * `params`: the [TurnParams] specified in the API call
 #[get("/turn/left")]
-async fn turn_left(ev3_devices: web::Data<Mutex<Ev3Devices>>) -> actix_web::Result<HttpResponse> {
-    let motor_set = &ev3_devices.lock().await.motor_set;
-
-    // Set the direction and time for the turn.
-    let direction = vec![-1, 1];
-    let backup_time = Duration::from_millis(150);
+async fn turn_left(
+    request: HttpRequest,
+    pairing: web::Data<Pairing>,
+    device: web::Data<Device>,
+    params: web::Query<TurnParams>,
+) -> actix_web::Result<HttpResponse> {
+    pairing.require_session(&request)?;
 
     // Run the turn.
-    for (motor, direction) in motor_set.motors.iter().zip(direction) {
-        // Set this wheel to run at 750, either forwards or backwards.
-        motor
-            .set_speed_sp(750 * direction)
-            .map_err(|cause| Ev3ErrorWrapper { cause })?;
-    }
-    motor_set.run_timed(Some(backup_time))?;
-    motor_set.wait_until(LargeMotor::STATE_RUNNING, None);
-    motor_set.wait_until_not_moving(None);
-
-    // Send the client back to the home page.
-    Ok(HttpResponse::Found()
-        .header(actix_web::http::header::LOCATION, "/")
-        .finish()
-        .into_body())
+    let turn_degrees = require_in_range(
+        "degrees",
+        params.degrees.unwrap_or(TURN_DEGREES_DEFAULT),
+        TURN_DEGREES_MAX,
+    )?;
+    let degrees = turn_to_wheel_degrees(turn_degrees);
+    let description = format!("turn left {} degrees", turn_degrees);
+    request_wheel_move(&device, description, -degrees, degrees).await
 }
 
 /// This API endpoint is called when the user clicks the "turn right" button in the web application.
-/// Turns the robot backwards and to the right a bit.
+/// Starts turning the robot in place to the right a bit.
 ///
 /// Parameters:
-/// * `ev3_devices`: the [Ev3Devices] instance managed/shared by the application
+/// * `request`: the HTTP request, which must include the client's session cookie
+/// * `pairing`: the [Pairing] instance managed/shared by the application
+/// * `device`: the [Device] handle managed/shared by the application
+/// * `params`: the [TurnParams] specified in the API call
 #[get("/turn/right")]
-async fn turn_right(ev3_devices: web::Data<Mutex<Ev3Devices>>) -> actix_web::Result<HttpResponse> {
-    let motor_set = &ev3_devices.lock().await.motor_set;
-
-    // Set the direction and time for the turn.
-    let direction = vec![1, -1];
-    let backup_time = Duration::from_millis(150);
+async fn turn_right(
+    request: HttpRequest,
+    pairing: web::Data<Pairing>,
+    device: web::Data<Device>,
+    params: web::Query<TurnParams>,
+) -> actix_web::Result<HttpResponse> {
+    pairing.require_session(&request)?;
 
     // Run the turn.
-    for (motor, direction) in motor_set.motors.iter().zip(direction) {
-        // Set this wheel to run at 750, either forwards or backwards.
-        motor
-            .set_speed_sp(750 * direction)
-            .map_err(|cause| Ev3ErrorWrapper { cause })?;
+    let turn_degrees = require_in_range(
+        "degrees",
+        params.degrees.unwrap_or(TURN_DEGREES_DEFAULT),
+        TURN_DEGREES_MAX,
+    )?;
+    let degrees = turn_to_wheel_degrees(turn_degrees);
+    let description = format!("turn right {} degrees", turn_degrees);
+    request_wheel_move(&device, description, degrees, -degrees).await
+}
+
+/// Models the query parameters for [rotate_wheels()].
+#[derive(Deserialize)]
+struct RotateParams {
+    /// How far to rotate the left wheel, in degrees.
+    left: f32,
+    /// How far to rotate the right wheel, in degrees.
+    right: f32,
+}
+
+/// This API endpoint starts rotating each of the wheels by an exact number of degrees,
+///   e.g. `/rotate?left=360&right=-360`.
+///
+/// Parameters:
+/// * `request`: the HTTP request, which must include the client's session cookie
+/// * `pairing`: the [Pairing] instance managed/shared by the application
+/// * `device`: the [Device] handle managed/shared by the application
+/// * `params`: the [RotateParams] specified in the API call
+#[get("/rotate")]
+async fn rotate_wheels(
+    request: HttpRequest,
+    pairing: web::Data<Pairing>,
+    device: web::Data<Device>,
+    params: web::Query<RotateParams>,
+) -> actix_web::Result<HttpResponse> {
+    pairing.require_session(&request)?;
+    let left = require_in_range("left", params.left, ROTATE_DEGREES_MAX)?;
+    let right = require_in_range("right", params.right, ROTATE_DEGREES_MAX)?;
+
+    let description = format!("rotate wheels {} and {} degrees", left, right);
+    request_wheel_move(&device, description, left, right).await
+}
+
+/// Returns the specified query parameter's value, or a `400 Bad Request` error if it isn't a
+///   number within the allowed range.
+///
+/// Parameters:
+/// * `name`: the name of the query parameter, for the error message
+/// * `value`: the value specified in the API call
+/// * `max`: the largest allowed value, in either direction
+fn require_in_range(name: &str, value: f32, max: f32) -> actix_web::Result<f32> {
+    if !value.is_finite() || value.abs() > max {
+        return Err(actix_web::error::ErrorBadRequest(format!(
+            "'{}' must be a number from -{} to {}.",
+            name, max, max
+        )));
+    }
+
+    Ok(value)
+}
+
+/// Returns the response for a motion [Command] that was started successfully:
+///   `202 Accepted`, with the [Command] as JSON, and its `Location` for checking on it.
+///
+/// If the collision guard slowed the move down, that's reported in an `X-Collision-Guard` header,
+///   e.g. `Obstacle 25.0 cm ahead: slowed to 50% speed.`
+///
+/// Parameters:
+/// * `command`: the [Command] that was started
+/// * `intervention`: how the collision guard limited the move, if it did
+fn command_response(command: &Command, intervention: Option<Intervention>) -> HttpResponse {
+    let mut response = HttpResponse::Accepted();
+    response.header(
+        actix_web::http::header::LOCATION,
+        format!("/commands/{}", command.id),
+    );
+    if let Some(intervention) = intervention {
+        response.header(
+            GUARD_HEADER,
+            format!(
+                "{}: slowed to {:.0}% speed.",
+                intervention.describe(),
+                intervention.speed_factor * 100.0
+            ),
+        );
+    }
+
+    response.json(command)
+}
+
+/// Asks the device actor to start rotating each wheel by the specified number of degrees, via
+///   [Robot::start_wheel_move()], and returns the response for it right away.
+///
+/// Parameters:
+/// * `device`: the [Device] handle managed/shared by the application
+/// * `description`: what the move does, which is reported to clients
+/// * `left_degrees`: how far to rotate the left wheel
+/// * `right_degrees`: how far to rotate the right wheel
+async fn request_wheel_move(
+    device: &Device,
+    description: String,
+    left_degrees: f32,
+    right_degrees: f32,
+) -> actix_web::Result<HttpResponse> {
+    let (command, intervention) = device
+        .send(|reply| DeviceCommand::StartMove {
+            description,
+            left_degrees,
+            right_degrees,
+            reply,
+        })
+        .await?;
+
+    Ok(command_response(&command, intervention))
+}
+
+/// A move that's running, which the device actor watches over via
+///   [Robot::check_wheel_move()].
+#[derive(Clone, Copy)]
+struct WheelMove {
+    /// The ID of the motion [Command] for the move.
+    id: u64,
+    /// Whether the move is driving the robot forwards.
+    forward: bool,
+    /// When the move was started.
+    started: Instant,
+    /// How long to give the move before giving up on it.
+    timeout: Duration,
+}
+
+impl Robot {
+    /// Starts rotating each wheel by the specified number of degrees, as a new motion [Command]
+    ///   that supersedes whichever one was running, and returns it right away.
+    /// The rest of the move is watched over by [Robot::check_wheel_move()], on each tick.
+    ///
+    /// Forward moves are refused if a cliff is detected, or if the collision guard sees an
+    ///   obstacle too close ahead, and are slowed down if one is nearby.
+    /// All moves are refused while the emergency stop is engaged.
+    ///
+    /// Parameters:
+    /// * `description`: what the move does, which is reported to clients
+    /// * `left_degrees`: how far to rotate the left wheel
+    /// * `right_degrees`: how far to rotate the right wheel
+    fn start_wheel_move(
+        &mut self,
+        description: String,
+        left_degrees: f32,
+        right_degrees: f32,
+    ) -> actix_web::Result<(Command, Option<Intervention>)> {
+        if !left_degrees.is_finite() || !right_degrees.is_finite() {
+            return Err(actix_web::error::ErrorBadRequest("Invalid move."));
+        }
+        self.estop.require_clear()?;
+
+        let forward = left_degrees + right_degrees > 0.0;
+        if forward && self.ev3_devices.is_cliff()? {
+            return Err(actix_web::error::ErrorConflict(
+                "Cliff detected: refusing to drive forward.",
+            ));
+        }
+
+        // Only forward moves can run into whatever's in front of the robot.
+        let intervention = match forward {
+            true => self.ev3_devices.check_guard()?,
+            false => None,
+        };
+        if let Some(intervention) = intervention {
+            if intervention.action == GuardAction::Stop {
+                return Err(actix_web::error::ErrorConflict(format!(
+                    "{}: refusing to drive forward.",
+                    intervention.describe()
+                )));
+            }
+        }
+        let speed_factor = intervention.map_or(1.0, |intervention| intervention.speed_factor);
+        let speed = ((MOVE_SPEED as f32) * speed_factor).round().max(1.0) as i32;
+
+        // Allow for half again as long as the move ought to take, plus a bit for acceleration.
+        // The handlers bound every move, but check again before trusting it to a [Duration].
+        let degrees_max = left_degrees.abs().max(right_degrees.abs());
+        let expected_secs = degrees_max / speed as f32;
+        if !expected_secs.is_finite() || expected_secs > MOVE_SECS_MAX {
+            return Err(actix_web::error::ErrorBadRequest("Move is too long."));
+        }
+        let timeout = Duration::from_secs_f32(expected_secs).mul_f32(1.5) + Duration::from_secs(1);
+
+        let motor_set = &self.ev3_devices.motor_set;
+        motor_set
+            .set_stop_action("brake")
+            .and_then(|_| {
+                motor_set.run_to_rel_degrees(
+                    &[left_degrees.round() as i32, right_degrees.round() as i32],
+                    speed,
+                )
+            })
+            .map_err(Ev3ErrorWrapper::from)?;
+        let command = self.commands.start(description);
+        self.wheel_move = Some(WheelMove {
+            id: command.id,
+            forward,
+            started: Instant::now(),
+            timeout,
+        });
+
+        Ok((command, intervention))
+    }
+
+    /// Called on each tick, to check on the move that's running (if any), marking it as done once
+    ///   the wheels have gotten where they're going.
+    ///
+    /// Forward moves are stopped partway if a cliff is detected, or if the collision guard sees an
+    ///   obstacle too close ahead, and all moves are stopped partway if the emergency stop is
+    ///   engaged.
+    /// If the move takes much longer than it ought to, e.g. because the robot is stuck, it's
+    ///   stopped and marked as failed.
+    fn check_wheel_move(&mut self) {
+        let wheel_move = match self.wheel_move {
+            Some(wheel_move) => wheel_move,
+            None => return,
+        };
+
+        let started = wheel_move.started.elapsed() > MOVE_START_TIMEOUT;
+        let timed_out = wheel_move.started.elapsed() > wheel_move.timeout;
+        let motor_set = &self.ev3_devices.motor_set;
+        let stop_reason = match motor_set.is_running().map_err(Ev3ErrorWrapper::from) {
+            Ok(false) if started => {
+                self.commands
+                    .finish(wheel_move.id, CommandStatus::Done, None);
+                self.wheel_move = None;
+                return;
+            }
+            Ok(_) => match self.wheel_move_problem(wheel_move.forward) {
+                Ok(Some(reason)) => Some((CommandStatus::Stopped, reason)),
+                Ok(None) if timed_out => Some((
+                    CommandStatus::Failed,
+                    "Move did not complete in time.".to_string(),
+                )),
+                Ok(None) => None,
+                Err(err) => Some((CommandStatus::Failed, err.to_string())),
+            },
+            Err(err) => Some((CommandStatus::Failed, err.to_string())),
+        };
+
+        if let Some((status, reason)) = stop_reason {
+            let motor_set = &self.ev3_devices.motor_set;
+            if let Err(err) = motor_set
+                .set_stop_action("brake")
+                .and_then(|_| motor_set.stop())
+            {
+                eprintln!("Commands: {}", Ev3ErrorWrapper::from(err));
+            }
+            self.commands.finish(wheel_move.id, status, Some(reason));
+            self.wheel_move = None;
+        }
+    }
+
+    /// Returns why the move that's running needs to be stopped partway, if it does.
+    ///
+    /// Parameters:
+    /// * `forward`: whether the move is driving the robot forwards
+    fn wheel_move_problem(
+        &self,
+        forward: bool,
+    ) -> std::result::Result<Option<String>, Ev3ErrorWrapper> {
+        if self.estop.engaged {
+            return Ok(Some(self.estop.move_stopped_reason()));
+        }
+
+        // Only forward moves can run into whatever's in front of the robot.
+        if !forward {
+            return Ok(None);
+        }
+        if self.ev3_devices.is_cliff()? {
+            return Ok(Some("Cliff detected: move stopped.".to_string()));
+        }
+        match self.ev3_devices.check_guard()? {
+            Some(intervention) if intervention.action == GuardAction::Stop => {
+                Ok(Some(format!("{}: move stopped.", intervention.describe())))
+            }
+            _ => Ok(None),
+        }
+    }
+}
+
+/// Models the `telemetry` [Event]s sent by [sample_events()].
+#[derive(Serialize)]
+struct TelemetryData {
+    /// Either `Moving` or `Idle`.
+    mode: &'static str,
+    motors: Vec<MotorTelemetry>,
+    sensors: SensorTelemetry,
+}
+
+/// Runs forever in the background, sampling the telemetry and publishing it (along with any
+///   discrete events detected in it) to all of the `/events` subscribers.
+///
+/// The telemetry is read by the device actor, so each sample waits its turn behind whatever else
+///   the actor is doing, rather than reading the devices out from under it.
+///
+/// Parameters:
+/// * `events`: the channel to publish [Event]s to
+/// * `device`: the [Device] handle to read the telemetry via
+/// * `sample_interval`: how often to sample the telemetry
+async fn sample_events(
+    events: web::Data<broadcast::Sender<Event>>,
+    device: Device,
+    sample_interval: Duration,
+) {
+    let mut detector = EventDetector::new();
+    let mut interval = actix_web::rt::time::interval(sample_interval);
+    loop {
+        interval.tick().await;
+
+        // Don't bother reading anything if nobody's listening.
+        if events.receiver_count() == 0 {
+            continue;
+        }
+
+        let telemetry = match device
+            .send(|reply| DeviceCommand::ReadTelemetry { reply })
+            .await
+        {
+            Ok(telemetry) => telemetry,
+            Err(err) => {
+                eprintln!("Events: {}", err);
+                continue;
+            }
+        };
+
+        // Sending only fails if all of the subscribers have since disconnected, which is fine.
+        for event in detector.detect(&telemetry.mode, &telemetry.motors, &telemetry.sensors) {
+            let _ = events.send(event);
+        }
+        let _ = events.send(Event::new("telemetry", &telemetry));
     }
-    motor_set.run_timed(Some(backup_time))?;
-    motor_set.wait_until(LargeMotor::STATE_RUNNING, None);
-    motor_set.wait_until_not_moving(None);
-
-    // Send the client back to the home page.
-    Ok(HttpResponse::Found()
-        .header(actix_web::http::header::LOCATION, "/")
-        .finish()
-        .into_body())
 }
 
 /// A local wrapper of [ev3dev_lang_rust::Ev3Error], which is required so that we can implement
@@ -203,107 +620,64 @@ impl std::error::Error for Ev3ErrorWrapper {
 
 impl actix_web::error::ResponseError for Ev3ErrorWrapper {}
 
-/// The EV3 devices that will be used and shared by the application..
+impl From<Ev3ErrorWrapper> for DeviceError {
+    fn from(err: Ev3ErrorWrapper) -> Self {
+        err.cause.into()
+    }
+}
+
+/// The EV3 devices used by the application, which are owned by the device actor.
 struct Ev3Devices {
     motor_set: LargeMotorSet,
+    sensors: Sensors,
+    guard: Option<CollisionGuard>,
 }
 
 impl Ev3Devices {
     /// Constructs an [Ev3Devices] for the application to use.
     pub fn new() -> std::result::Result<Ev3Devices, Ev3ErrorWrapper> {
+        let sensors = Sensors::find(IR_CM_PER_PERCENT_VAR);
+        let guard = CollisionGuard::find(&sensors);
+
         Ok(Ev3Devices {
-            motor_set: LargeMotorSet {
-                motors: vec![
-                    LargeMotor::get(MotorPort::OutB).map_err(|cause| Ev3ErrorWrapper { cause })?,
-                    LargeMotor::get(MotorPort::OutC).map_err(|cause| Ev3ErrorWrapper { cause })?,
-                ],
-            },
+            motor_set: LargeMotorSet::new()?,
+            sensors,
+            guard,
         })
     }
-}
-
-/// Represents a set of [LargeMotor]s that ought to be managed in concert.
-struct LargeMotorSet {
-    motors: Vec<LargeMotor>,
-}
-
-impl LargeMotorSet {
-    /// Proxies [LargeMotor::set_stop_action()].
-    pub fn set_stop_action(&self, stop_action: &str) -> std::result::Result<(), Ev3ErrorWrapper> {
-        for motor in &self.motors {
-            motor
-                .set_stop_action(stop_action)
-                .map_err(|cause| Ev3ErrorWrapper { cause })?;
-        }
-
-        Ok(())
-    }
-
-    /// Proxies [LargeMotor::stop()].
-    pub fn stop(&self) -> std::result::Result<(), Ev3ErrorWrapper> {
-        for motor in &self.motors {
-            motor.stop().map_err(|cause| Ev3ErrorWrapper { cause })?;
-        }
-
-        Ok(())
-    }
 
-    /// Proxies [LargeMotor::set_duty_cycle_sp()].
-    pub fn set_duty_cycle_sp(&self, duty_cycle: i32) -> std::result::Result<(), Ev3ErrorWrapper> {
-        for motor in &self.motors {
+    /// Reads the current [TelemetryData].
+    pub fn read_telemetry(&self) -> std::result::Result<TelemetryData, Ev3ErrorWrapper> {
+        let motors = self.motor_set.read_telemetry()?;
+        let moving = motors.iter().any(|motor| {
             motor
-                .set_duty_cycle_sp(duty_cycle)
-                .map_err(|cause| Ev3ErrorWrapper { cause })?;
-        }
-
-        Ok(())
-    }
-
-    /// Proxies [LargeMotor::run_direct()].
-    pub fn run_direct(&self) -> std::result::Result<(), Ev3ErrorWrapper> {
-        for motor in &self.motors {
-            motor
-                .run_direct()
-                .map_err(|cause| Ev3ErrorWrapper { cause })?;
-        }
-
-        Ok(())
-    }
-
-    /// Proxies [LargeMotor::run_timed()].
-    pub fn run_timed(&self, time_sp: Option<Duration>) -> std::result::Result<(), Ev3ErrorWrapper> {
-        for motor in &self.motors {
-            motor
-                .run_timed(time_sp)
-                .map_err(|cause| Ev3ErrorWrapper { cause })?;
-        }
-
-        Ok(())
+                .state
+                .iter()
+                .any(|state| state == LargeMotor::STATE_RUNNING)
+        });
+
+        Ok(TelemetryData {
+            mode: if moving { "Moving" } else { "Idle" },
+            motors,
+            sensors: self.sensors.read()?,
+        })
     }
 
-    /// Proxies [LargeMotor::wait_until()].
-    pub fn wait_until(&self, state: &str, timeout: Option<Duration>) -> bool {
-        let mut result = true;
-        for motor in &self.motors {
-            result = match motor.wait_until(state, timeout) {
-                true => result,
-                false => false,
-            };
+    /// Returns `true` if a cliff is detected, which will always be `false` if there's no color
+    ///   sensor plugged in.
+    pub fn is_cliff(&self) -> std::result::Result<bool, Ev3ErrorWrapper> {
+        match &self.sensors.cliff_detector {
+            Some(cliff_detector) => Ok(cliff_detector.is_cliff()?),
+            None => Ok(false),
         }
-
-        result
     }
 
-    /// Proxies [LargeMotor::wait_until_not_moving()].
-    pub fn wait_until_not_moving(&self, timeout: Option<Duration>) -> bool {
-        let mut result = true;
-        for motor in &self.motors {
-            result = match motor.wait_until_not_moving(timeout) {
-                true => result,
-                false => false,
-            };
+    /// Returns how the [CollisionGuard] needs to limit forward driving, if at all, which will
+    ///   always be `None` if the guard is disabled.
+    pub fn check_guard(&self) -> std::result::Result<Option<Intervention>, Ev3ErrorWrapper> {
+        match &self.guard {
+            Some(guard) => Ok(guard.check(&self.sensors)?),
+            None => Ok(None),
         }
-
-        result
     }
 }
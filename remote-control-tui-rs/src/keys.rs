@@ -0,0 +1,198 @@
+//! Turns terminal key presses into driving [Command]s, and works out which driving key is being
+//!   held down.
+//!
+//! Terminals only report key presses, never releases: holding a key down sends one press, then
+//!   (after the keyboard's repeat delay) a steady stream of repeated presses.
+//! So a [KeyHold] treats a driving key as held until its repeats stop arriving.
+//! That also means that if the connection to the terminal stalls, e.g. because the SSH session's
+//!   wifi drops, the key will soon be treated as released, and the robot will stop.
+
+use std::time::{Duration, Instant};
+
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+
+/// How long to wait for the first repeat of a newly pressed key before treating it as released.
+/// This has to cover the keyboard's repeat delay (usually 250 to 500 ms), plus some network
+///   jitter.
+const INITIAL_REPEAT_TIMEOUT: Duration = Duration::from_millis(600);
+
+/// How long to wait for each subsequent repeat of a held key before treating it as released.
+/// This has to cover the keyboard's repeat interval (usually 30 to 40 ms), plus some network
+///   jitter.
+const REPEAT_TIMEOUT: Duration = Duration::from_millis(200);
+
+/// The ways that the robot can be driven, while their keys are held down.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Motion {
+    Forward,
+    Backward,
+    Left,
+    Right,
+}
+
+/// The things that the user can do from the keyboard.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Command {
+    /// Drive, for as long as the key is held down.
+    Move(Motion),
+    /// Speed up by a notch.
+    Faster,
+    /// Slow down by a notch.
+    Slower,
+    /// Stop immediately, even if a driving key is still held down.
+    Brake,
+    /// Stop and exit.
+    Quit,
+}
+
+impl Command {
+    /// Returns the [Command] for the specified key press, if it has one.
+    pub fn from_key(key: KeyEvent) -> Option<Command> {
+        // Raw mode means that Ctrl+C arrives as a key press, rather than a signal.
+        if key.modifiers.contains(KeyModifiers::CONTROL) {
+            return match key.code {
+                KeyCode::Char('c') | KeyCode::Char('d') => Some(Command::Quit),
+                _ => None,
+            };
+        }
+
+        match key.code {
+            KeyCode::Up | KeyCode::Char('w') | KeyCode::Char('W') => {
+                Some(Command::Move(Motion::Forward))
+            }
+            KeyCode::Down | KeyCode::Char('s') | KeyCode::Char('S') => {
+                Some(Command::Move(Motion::Backward))
+            }
+            KeyCode::Left | KeyCode::Char('a') | KeyCode::Char('A') => {
+                Some(Command::Move(Motion::Left))
+            }
+            KeyCode::Right | KeyCode::Char('d') | KeyCode::Char('D') => {
+                Some(Command::Move(Motion::Right))
+            }
+            KeyCode::Char('+') | KeyCode::Char('=') => Some(Command::Faster),
+            KeyCode::Char('-') | KeyCode::Char('_') => Some(Command::Slower),
+            KeyCode::Char(' ') => Some(Command::Brake),
+            KeyCode::Char('q') | KeyCode::Char('Q') | KeyCode::Esc => Some(Command::Quit),
+            _ => None,
+        }
+    }
+}
+
+/// Tracks which [Motion]'s key (if any) is currently being held down.
+pub struct KeyHold {
+    motion: Option<Motion>,
+    last_pressed: Instant,
+    repeating: bool,
+}
+
+impl KeyHold {
+    /// Constructs a new [KeyHold], with nothing held down.
+    pub fn new() -> KeyHold {
+        KeyHold {
+            motion: None,
+            last_pressed: Instant::now(),
+            repeating: false,
+        }
+    }
+
+    /// Records a press (or repeat) of the specified [Motion]'s key.
+    /// Pressing a different key replaces the held one, as terminals only repeat the last key
+    ///   pressed anyways.
+    ///
+    /// Parameters:
+    /// * `motion`: the [Motion] whose key was pressed
+    /// * `now`: when the key press arrived
+    pub fn pressed(&mut self, motion: Motion, now: Instant) {
+        self.repeating = self.motion == Some(motion);
+        self.motion = Some(motion);
+        self.last_pressed = now;
+    }
+
+    /// Treats the held key (if any) as released, e.g. when braking.
+    pub fn release(&mut self) {
+        self.motion = None;
+    }
+
+    /// Returns the [Motion] whose key is still being held down, if any.
+    ///
+    /// Parameters:
+    /// * `now`: the current time, which is compared against the last key press
+    pub fn held(&mut self, now: Instant) -> Option<Motion> {
+        let timeout = match self.repeating {
+            true => REPEAT_TIMEOUT,
+            false => INITIAL_REPEAT_TIMEOUT,
+        };
+        if now.saturating_duration_since(self.last_pressed) > timeout {
+            self.motion = None;
+        }
+
+        self.motion
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn waits_longer_for_first_repeat() {
+        let start = Instant::now();
+        let mut hold = KeyHold::new();
+        hold.pressed(Motion::Forward, start);
+
+        // The keyboard's repeat delay is longer than its repeat interval.
+        assert_eq!(hold.held(start + REPEAT_TIMEOUT * 2), Some(Motion::Forward));
+        assert_eq!(
+            hold.held(start + INITIAL_REPEAT_TIMEOUT),
+            Some(Motion::Forward)
+        );
+        assert_eq!(hold.held(start + INITIAL_REPEAT_TIMEOUT * 2), None);
+    }
+
+    #[test]
+    fn times_out_between_repeats() {
+        let start = Instant::now();
+        let mut hold = KeyHold::new();
+        hold.pressed(Motion::Left, start);
+        let repeated = start + Duration::from_millis(500);
+        hold.pressed(Motion::Left, repeated);
+
+        assert_eq!(hold.held(repeated + REPEAT_TIMEOUT), Some(Motion::Left));
+        assert_eq!(hold.held(repeated + REPEAT_TIMEOUT * 2), None);
+
+        // Once released, it stays released, even if it's checked on again.
+        assert_eq!(hold.held(repeated), None);
+    }
+
+    #[test]
+    fn replaces_key_on_change() {
+        let start = Instant::now();
+        let mut hold = KeyHold::new();
+        hold.pressed(Motion::Forward, start);
+        hold.pressed(Motion::Forward, start + Duration::from_millis(500));
+
+        // A different key is a new press, and so gets the longer initial timeout again.
+        let changed = start + Duration::from_millis(530);
+        hold.pressed(Motion::Right, changed);
+        assert_eq!(hold.held(changed + REPEAT_TIMEOUT * 2), Some(Motion::Right));
+        assert_eq!(hold.held(changed + INITIAL_REPEAT_TIMEOUT * 2), None);
+    }
+
+    #[test]
+    fn releases() {
+        let start = Instant::now();
+        let mut hold = KeyHold::new();
+        assert_eq!(hold.held(start), None);
+
+        hold.pressed(Motion::Backward, start);
+        hold.release();
+        assert_eq!(hold.held(start), None);
+
+        // Pressing the same key again after a release is a new press.
+        hold.pressed(Motion::Backward, start);
+        assert_eq!(
+            hold.held(start + REPEAT_TIMEOUT * 2),
+            Some(Motion::Backward)
+        );
+    }
+}
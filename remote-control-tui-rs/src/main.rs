@@ -0,0 +1,310 @@
+//! A remote controlled driving routine for the [ev3dev platform](https://www.ev3dev.org/),
+//!   which is driven from the keyboard of a terminal, e.g. an SSH session on the brick.
+//!
+//! Usage:
+//!
+//! ```text
+//! remote-control-tui-rs
+//! ```
+//!
+//! The robot drives for as long as the arrow (or WASD) keys are held down, at the speed set with
+//!   `+` and `-`, and stops when they're let go or when space is pressed.
+//! The motors' and sensors' live readings are shown in the terminal while driving.
+//!
+//! The robot is always stopped on the way out, including when the terminal disconnects, e.g.
+//!   because the SSH session was closed or dropped.
+
+mod keys;
+
+use std::io::{Stdout, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Context, Result};
+use crossterm::cursor::{Hide, MoveTo, Show};
+use crossterm::event::{self, Event};
+use crossterm::style::Print;
+use crossterm::terminal::{self, Clear, ClearType, EnterAlternateScreen, LeaveAlternateScreen};
+use crossterm::{execute, queue};
+use ev3_common::motors::LargeMotorSet;
+use ev3_common::telemetry::{MotorTelemetry, Sensors};
+use ev3dev_lang_rust::Ev3Error;
+use keys::{Command, KeyHold, Motion};
+
+/// How long to wait for key presses before checking on everything else again.
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+/// How often the live readings are redrawn.
+const REDRAW_INTERVAL: Duration = Duration::from_millis(250);
+
+/// The absolute motor speed (in tacho counts per second) at 100% speed, which matches the web
+///   remote's max speed.
+const SPEED_MAX_ABSOLUTE: i32 = 900;
+
+/// The speed (as a percentage) that the robot starts out at.
+const SPEED_DEFAULT: i32 = 50;
+
+/// How much (as a percentage) each `+` or `-` press changes the speed by.
+const SPEED_STEP: i32 = 10;
+
+/// Turns are made by spinning in place, at this fraction of the current speed, so that they're
+///   easier to control.
+const TURN_SPEED_RATIO: f32 = 0.5;
+
+/// The environment variable that can be used to adjust how the infrared sensor's proximity
+///   readings are mapped to centimeters, if it's the proximity sensor.
+const IR_CM_PER_PERCENT_VAR: &str = "REMOTE_CONTROL_IR_CM_PER_PERCENT";
+
+fn main() -> Result<()> {
+    // Get motors and sensors.
+    let motor_set = LargeMotorSet::new().map_err(ev3_error)?;
+    let sensors = Sensors::find(IR_CM_PER_PERCENT_VAR);
+
+    // Find out when the terminal goes away, so that the robot can be stopped.
+    let hung_up = Arc::new(AtomicBool::new(false));
+    for signal in &[signal_hook::consts::SIGHUP, signal_hook::consts::SIGTERM] {
+        signal_hook::flag::register(*signal, hung_up.clone())
+            .context("Unable to listen for hangups.")?;
+    }
+
+    let result = RawTerminal::enter()
+        .and_then(|mut terminal| teleoperate(&mut terminal, &motor_set, &sensors, &hung_up));
+
+    // Whatever happened, make sure the motors end up stopped.
+    motor_set.set_stop_action("brake").map_err(ev3_error)?;
+    motor_set.stop().map_err(ev3_error)?;
+    motor_set.wait_until_not_moving(None);
+
+    // There's nobody left to say goodbye to if the terminal's gone.
+    if !hung_up.load(Ordering::Relaxed) {
+        println!("TUI: stopped. Bye!");
+    }
+
+    result
+}
+
+/// Converts an [Ev3Error], which doesn't implement [std::error::Error], into an [anyhow::Error].
+fn ev3_error(cause: Ev3Error) -> anyhow::Error {
+    anyhow!("EV3 error: {:?}", cause)
+}
+
+/// Drives per the keyboard until the user quits or the terminal disconnects.
+///
+/// Parameters:
+/// * `terminal`: the [RawTerminal] to read keys from and draw to
+/// * `motor_set`: the [LargeMotorSet] to drive
+/// * `sensors`: the [Sensors] to show readings from
+/// * `hung_up`: set when the terminal has disconnected
+fn teleoperate(
+    terminal: &mut RawTerminal,
+    motor_set: &LargeMotorSet,
+    sensors: &Sensors,
+    hung_up: &AtomicBool,
+) -> Result<()> {
+    let mut speed = SPEED_DEFAULT;
+    let mut hold = KeyHold::new();
+    let mut current: (Option<Motion>, i32) = (None, speed);
+    let mut last_drawn: Option<Instant> = None;
+    loop {
+        if hung_up.load(Ordering::Relaxed) {
+            return Err(anyhow!("Terminal disconnected."));
+        }
+
+        // Handle all of the key presses that have arrived.
+        let mut braked = false;
+        while event::poll(POLL_INTERVAL).context("Unable to read from terminal.")? {
+            let key = match event::read().context("Unable to read from terminal.")? {
+                Event::Key(key) => key,
+                _ => continue,
+            };
+            match Command::from_key(key) {
+                Some(Command::Move(motion)) => hold.pressed(motion, Instant::now()),
+                Some(Command::Faster) => speed = (speed + SPEED_STEP).min(100),
+                Some(Command::Slower) => speed = (speed - SPEED_STEP).max(SPEED_STEP),
+                Some(Command::Brake) => {
+                    hold.release();
+                    braked = true;
+                }
+                Some(Command::Quit) => return Ok(()),
+                None => {}
+            }
+        }
+
+        // Never drive forwards over a cliff, no matter which key is held.
+        let mut motion = hold.held(Instant::now());
+        if motion == Some(Motion::Forward) {
+            if let Some(cliff_detector) = &sensors.cliff_detector {
+                if cliff_detector.is_cliff().map_err(ev3_error)? {
+                    motion = None;
+                }
+            }
+        }
+
+        // Only re-issue motor commands when something changes.
+        if (motion, speed) != current || braked {
+            apply_motion(motor_set, motion, speed).map_err(ev3_error)?;
+            current = (motion, speed);
+        }
+
+        if last_drawn.map_or(true, |last_drawn| last_drawn.elapsed() >= REDRAW_INTERVAL) {
+            terminal.draw(motor_set, sensors, speed, motion)?;
+            last_drawn = Some(Instant::now());
+        }
+    }
+}
+
+/// Updates the motor settings to match the specified [Motion].
+///
+/// Parameters:
+/// * `motor_set`: the [LargeMotorSet] to update
+/// * `motion`: the [Motion] to drive with, or `None` to stop
+/// * `speed`: the speed to drive at, as a percentage
+fn apply_motion(
+    motor_set: &LargeMotorSet,
+    motion: Option<Motion>,
+    speed: i32,
+) -> Result<(), Ev3Error> {
+    let speed = SPEED_MAX_ABSOLUTE * speed / 100;
+    let turn_speed = (speed as f32 * TURN_SPEED_RATIO) as i32;
+    let (left, right) = match motion {
+        Some(Motion::Forward) => (speed, speed),
+        Some(Motion::Backward) => (-speed, -speed),
+        Some(Motion::Left) => (-turn_speed, turn_speed),
+        Some(Motion::Right) => (turn_speed, -turn_speed),
+        None => {
+            motor_set.set_stop_action("brake")?;
+            return motor_set.stop();
+        }
+    };
+
+    for (motor, speed_sp) in motor_set.motors.iter().zip(vec![left, right]) {
+        motor.set_speed_sp(speed_sp)?;
+    }
+    motor_set.run_forever()
+}
+
+/// The terminal, switched into raw mode (so that key presses arrive as they happen), and onto the
+///   alternate screen (so that the TUI doesn't clobber the scrollback).
+/// It's switched back when this is dropped.
+struct RawTerminal {
+    stdout: Stdout,
+}
+
+impl RawTerminal {
+    /// Switches the terminal into raw mode and onto the alternate screen.
+    fn enter() -> Result<RawTerminal> {
+        terminal::enable_raw_mode().context("Unable to switch terminal to raw mode.")?;
+
+        // From here on, the terminal will be switched back when this is dropped, even on failure.
+        let mut raw_terminal = RawTerminal {
+            stdout: std::io::stdout(),
+        };
+        execute!(raw_terminal.stdout, EnterAlternateScreen, Hide)
+            .context("Unable to set up terminal.")?;
+
+        Ok(raw_terminal)
+    }
+
+    /// Redraws the controls and the live readings.
+    ///
+    /// Parameters:
+    /// * `motor_set`: the [LargeMotorSet] to show readings from
+    /// * `sensors`: the [Sensors] to show readings from
+    /// * `speed`: the current speed setting, as a percentage
+    /// * `motion`: the current [Motion], if any
+    fn draw(
+        &mut self,
+        motor_set: &LargeMotorSet,
+        sensors: &Sensors,
+        speed: i32,
+        motion: Option<Motion>,
+    ) -> Result<()> {
+        let mut lines = vec![
+            "EV3 Remote Control".to_string(),
+            "Hold arrows/WASD: drive | +/-: speed | space: brake | q: quit".to_string(),
+            String::new(),
+            format!(
+                "Speed: {}%    Driving: {}",
+                speed,
+                match motion {
+                    Some(motion) => format!("{:?}", motion),
+                    None => "Stopped".to_string(),
+                }
+            ),
+            String::new(),
+        ];
+
+        for (name, motor) in ["Left motor ", "Right motor"].iter().zip(&motor_set.motors) {
+            lines.push(match MotorTelemetry::read(motor) {
+                Ok(telemetry) => format!(
+                    "{}: speed {:>5}, position {:>7}, state [{}]",
+                    name,
+                    telemetry.speed,
+                    telemetry.position,
+                    telemetry.state.join(", ")
+                ),
+                Err(err) => format!("{}: {:?}", name, err),
+            });
+        }
+        lines.push(String::new());
+
+        match sensors.read() {
+            Ok(readings) => {
+                lines.push(format!(
+                    "Distance: {}",
+                    show(readings.distance_cm, |cm| format!("{:.1} cm", cm))
+                ));
+                lines.push(format!(
+                    "Touch:    {}",
+                    show(readings.touch_pressed, |pressed| match pressed {
+                        true => "pressed".to_string(),
+                        false => "released".to_string(),
+                    })
+                ));
+                lines.push(format!(
+                    "Cliff:    {}",
+                    show(readings.cliff, |cliff| {
+                        match (cliff, readings.reflected_light) {
+                            (true, Some(light)) => format!("detected ({:.0}% reflected)", light),
+                            (false, Some(light)) => format!("none ({:.0}% reflected)", light),
+                            (true, None) => "detected".to_string(),
+                            (false, None) => "none".to_string(),
+                        }
+                    })
+                ));
+                lines.push(format!(
+                    "Battery:  {}",
+                    show(readings.battery_volts, |volts| format!("{:.2} V", volts))
+                ));
+            }
+            Err(err) => lines.push(format!("Sensors: {:?}", err)),
+        }
+
+        queue!(self.stdout, Clear(ClearType::All)).context("Unable to draw.")?;
+        for (row, line) in lines.iter().enumerate() {
+            queue!(self.stdout, MoveTo(0, row as u16), Print(line)).context("Unable to draw.")?;
+        }
+        self.stdout.flush().context("Unable to draw.")
+    }
+}
+
+impl Drop for RawTerminal {
+    fn drop(&mut self) {
+        // There's nothing to be done if this fails, e.g. because the terminal's gone.
+        let _ = execute!(self.stdout, Show, LeaveAlternateScreen);
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+/// Formats the specified (optional) sensor reading for display.
+///
+/// Parameters:
+/// * `reading`: the sensor reading, or `None` if that sensor isn't plugged in
+/// * `format`: formats the reading, if there is one
+fn show<T>(reading: Option<T>, format: impl Fn(T) -> String) -> String {
+    match reading {
+        Some(reading) => format(reading),
+        None => "(not plugged in)".to_string(),
+    }
+}
@@ -0,0 +1,190 @@
+//! The robot API that is exposed to scripts, as a set of Rhai functions.
+//!
+//! All motor speeds are percentages of the max speed, from `-100` to `100`,
+//!   and all times are in milliseconds.
+
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use ev3_common::motors::LargeMotorSet;
+use ev3dev_lang_rust::motors::LargeMotor;
+use ev3dev_lang_rust::sensors::{TouchSensor, UltrasonicSensor};
+use ev3dev_lang_rust::{sound, Ev3Button, Ev3Error, Led};
+use rhai::{Engine, EvalAltResult};
+
+/// The absolute motor speed (in tacho counts per second) that a speed of `100` corresponds to.
+const SPEED_MAX_ABSOLUTE: i64 = 900;
+
+/// How often `sleep(...)` and `drive_for(...)` check for a brick button press.
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+/// The EV3 devices that scripts can use.
+pub struct Devices {
+    pub motors: LargeMotorSet,
+    pub touch_sensor: Option<TouchSensor>,
+    pub ultrasonic_sensor: Option<UltrasonicSensor>,
+    pub buttons: Ev3Button,
+    pub leds: Led,
+}
+
+impl Devices {
+    /// Returns `true` if any of the brick's buttons is currently pressed.
+    pub fn button_pressed(&self) -> bool {
+        self.buttons.process();
+        !self.buttons.get_pressed_buttons().is_empty()
+    }
+}
+
+type ScriptResult<T> = Result<T, Box<EvalAltResult>>;
+
+/// Converts an [Ev3Error] into a Rhai runtime error.
+fn ev3_error(cause: Ev3Error) -> Box<EvalAltResult> {
+    format!("EV3 error: {:?}", cause).into()
+}
+
+/// Registers all of the robot API functions with the specified [Engine].
+///
+/// Parameters:
+/// * `engine`: the [Engine] to register the functions with
+/// * `devices`: the [Devices] that the functions will control
+pub fn register(engine: &mut Engine, devices: Rc<Devices>) {
+    // Motors
+    let d = devices.clone();
+    engine.register_fn("drive", move |left: i64, right: i64| -> ScriptResult<()> {
+        drive(&d, left, right)
+    });
+    let d = devices.clone();
+    engine.register_fn(
+        "drive_for",
+        move |left: i64, right: i64, millis: i64| -> ScriptResult<()> {
+            drive(&d, left, right)?;
+            let result = sleep(&d, millis);
+            stop(&d, "brake")?;
+            result
+        },
+    );
+    let d = devices.clone();
+    engine.register_fn("stop", move || -> ScriptResult<()> { stop(&d, "coast") });
+    let d = devices.clone();
+    engine.register_fn("brake", move || -> ScriptResult<()> { stop(&d, "brake") });
+    let d = devices.clone();
+    engine.register_fn("left_position", move || -> ScriptResult<i64> {
+        motor_position(&d.motors.motors[0])
+    });
+    let d = devices.clone();
+    engine.register_fn("right_position", move || -> ScriptResult<i64> {
+        motor_position(&d.motors.motors[1])
+    });
+
+    // Sensors
+    let d = devices.clone();
+    engine.register_fn("touch_pressed", move || -> ScriptResult<bool> {
+        d.touch_sensor
+            .as_ref()
+            .ok_or_else(|| "No touch sensor is plugged in.".into())
+            .and_then(|sensor: &TouchSensor| sensor.get_pressed_state().map_err(ev3_error))
+    });
+    let d = devices.clone();
+    engine.register_fn("distance_cm", move || -> ScriptResult<f64> {
+        d.ultrasonic_sensor
+            .as_ref()
+            .ok_or_else(|| "No ultrasonic sensor is plugged in.".into())
+            .and_then(|sensor: &UltrasonicSensor| {
+                sensor
+                    .get_distance_centimeters()
+                    .map(f64::from)
+                    .map_err(ev3_error)
+            })
+    });
+    let d = devices.clone();
+    engine.register_fn("button_pressed", move || d.button_pressed());
+
+    // LEDs
+    let d = devices.clone();
+    engine.register_fn("leds", move |color: &str| -> ScriptResult<()> {
+        let color = match color {
+            "off" => Led::COLOR_OFF,
+            "green" => Led::COLOR_GREEN,
+            "red" => Led::COLOR_RED,
+            "amber" => Led::COLOR_AMBER,
+            other => {
+                return Err(format!(
+                    "Unknown LED color '{}'; use 'off', 'green', 'red', or 'amber'.",
+                    other
+                )
+                .into())
+            }
+        };
+        d.leds.set_left_color(color).map_err(ev3_error)?;
+        d.leds.set_right_color(color).map_err(ev3_error)
+    });
+
+    // Sound
+    engine.register_fn("beep", || -> ScriptResult<()> {
+        sound::beep()
+            .map_err(ev3_error)?
+            .wait()
+            .map(|_| ())
+            .map_err(|err| format!("Sound error: {}", err).into())
+    });
+    engine.register_fn("tone", |frequency: f64, millis: i64| -> ScriptResult<()> {
+        sound::tone(frequency as f32, millis.clamp(0, i32::MAX as i64) as i32)
+            .map_err(ev3_error)?
+            .wait()
+            .map(|_| ())
+            .map_err(|err| format!("Sound error: {}", err).into())
+    });
+    engine.register_fn("say", |text: &str| -> ScriptResult<()> {
+        sound::speak(text)
+            .map_err(ev3_error)?
+            .wait()
+            .map(|_| ())
+            .map_err(|err| format!("Sound error: {}", err).into())
+    });
+
+    // Timing
+    let d = devices;
+    engine.register_fn("sleep", move |millis: i64| -> ScriptResult<()> {
+        sleep(&d, millis)
+    });
+}
+
+/// Runs the left and right motors at the specified speed percentages, until told otherwise.
+fn drive(devices: &Devices, left: i64, right: i64) -> ScriptResult<()> {
+    for (motor, speed) in devices.motors.motors.iter().zip(vec![left, right]) {
+        if !(-100..=100).contains(&speed) {
+            return Err(format!("Speed of {} must be between -100 and 100.", speed).into());
+        }
+        motor
+            .set_speed_sp((SPEED_MAX_ABSOLUTE * speed / 100) as i32)
+            .map_err(ev3_error)?;
+    }
+
+    devices.motors.run_forever().map_err(ev3_error)
+}
+
+fn stop(devices: &Devices, stop_action: &str) -> ScriptResult<()> {
+    devices
+        .motors
+        .set_stop_action(stop_action)
+        .map_err(ev3_error)?;
+    devices.motors.stop().map_err(ev3_error)
+}
+
+fn motor_position(motor: &LargeMotor) -> ScriptResult<i64> {
+    motor.get_position().map(i64::from).map_err(ev3_error)
+}
+
+/// Waits for the specified number of milliseconds, or errors out if a brick button is pressed.
+fn sleep(devices: &Devices, millis: i64) -> ScriptResult<()> {
+    let duration = Duration::from_millis(millis.max(0) as u64);
+    let start = Instant::now();
+    while start.elapsed() < duration {
+        if devices.button_pressed() {
+            return Err("Aborted by button press.".into());
+        }
+        std::thread::sleep(POLL_INTERVAL.min(duration - start.elapsed().min(duration)));
+    }
+
+    Ok(())
+}
@@ -0,0 +1,142 @@
+//! Runs robot behaviors written in the [Rhai](https://rhai.rs/) scripting language
+//!   on the [ev3dev platform](https://www.ev3dev.org/).
+//!
+//! Usage:
+//!
+//! ```text
+//! script-runner-rs [--check] [--max-operations <count>] <script-file>
+//! ```
+//!
+//! Scripts get access to the robot via the functions registered in the [api] module.
+//! They are sandboxed: they can't access files or load other modules,
+//!   and are stopped after running too many operations, e.g. in a runaway loop.
+//! No matter how a script ends, the motors are always stopped afterwards.
+
+mod api;
+
+use std::rc::Rc;
+use std::time::Duration;
+
+use anyhow::{anyhow, Context, Result};
+use ev3_common::motors::LargeMotorSet;
+use ev3dev_lang_rust::sensors::{TouchSensor, UltrasonicSensor};
+use ev3dev_lang_rust::{Ev3Button, Ev3Result, Led};
+use rhai::module_resolvers::DummyModuleResolver;
+use rhai::{Dynamic, Engine};
+
+use api::Devices;
+
+/// The default limit on how many operations a script may run before it's stopped.
+/// Note that time spent in `sleep(...)` doesn't count against this,
+///   so a well-behaved script with a `sleep(...)` in each loop can run for a very long time.
+const MAX_OPERATIONS_DEFAULT: u64 = 50_000_000;
+
+/// How many operations to run between checks for a brick button press.
+const BUTTON_CHECK_OPERATIONS: u64 = 10_000;
+
+fn main() -> Result<()> {
+    let usage = "Usage: script-runner-rs [--check] [--max-operations <count>] <script-file>";
+    let mut check_only = false;
+    let mut max_operations = MAX_OPERATIONS_DEFAULT;
+    let mut script_path = None;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--check" => check_only = true,
+            "--max-operations" => {
+                max_operations = args
+                    .next()
+                    .and_then(|count| count.parse().ok())
+                    .ok_or_else(|| anyhow!(usage))?;
+            }
+            _ if script_path.is_none() => script_path = Some(arg),
+            _ => return Err(anyhow!(usage)),
+        }
+    }
+    let script_path = script_path.ok_or_else(|| anyhow!(usage))?;
+
+    let source = std::fs::read_to_string(&script_path)
+        .with_context(|| format!("Unable to read script '{}'.", script_path))?;
+
+    if check_only {
+        let engine = sandboxed_engine(max_operations);
+        engine
+            .compile(&source)
+            .map_err(|err| anyhow!("Script '{}': {}", script_path, err))?;
+        println!("Script '{}': OK.", script_path);
+        return Ok(());
+    }
+
+    let devices =
+        Rc::new(find_devices().map_err(|err| anyhow!("Unable to find EV3 devices: {:?}", err))?);
+    let _motor_guard = MotorGuard {
+        devices: devices.clone(),
+    };
+
+    let mut engine = sandboxed_engine(max_operations);
+    api::register(&mut engine, devices.clone());
+    let button_devices = devices;
+    engine.on_progress(move |operations| {
+        if operations % BUTTON_CHECK_OPERATIONS == 0 && button_devices.button_pressed() {
+            Some(Dynamic::from("Aborted by button press."))
+        } else {
+            None
+        }
+    });
+
+    println!(
+        "Script '{}': running. Press any brick button to stop.",
+        script_path
+    );
+    engine
+        .run(&source)
+        .map_err(|err| anyhow!("Script '{}': {}", script_path, err))?;
+    println!("Script '{}': done.", script_path);
+
+    Ok(())
+}
+
+/// Constructs a Rhai [Engine] that's been locked down for running untrusted scripts.
+///
+/// Parameters:
+/// * `max_operations`: the number of operations after which scripts will be stopped
+fn sandboxed_engine(max_operations: u64) -> Engine {
+    let mut engine = Engine::new();
+    engine
+        .set_module_resolver(DummyModuleResolver::new())
+        .set_max_operations(max_operations)
+        .set_max_call_levels(32)
+        .set_max_expr_depths(64, 32)
+        .set_max_string_size(10_000)
+        .set_max_array_size(10_000)
+        .set_max_map_size(10_000);
+
+    engine
+}
+
+/// Finds the motors, along with whichever of the optional sensors are plugged in.
+fn find_devices() -> Ev3Result<Devices> {
+    Ok(Devices {
+        motors: LargeMotorSet::new()?,
+        touch_sensor: TouchSensor::find().ok(),
+        ultrasonic_sensor: UltrasonicSensor::find().ok(),
+        buttons: Ev3Button::new()?,
+        leds: Led::new()?,
+    })
+}
+
+/// Stops the motors when dropped, so that they get stopped no matter how the script ends,
+///   including errors and panics.
+struct MotorGuard {
+    devices: Rc<Devices>,
+}
+
+impl Drop for MotorGuard {
+    fn drop(&mut self) {
+        let motors = &self.devices.motors;
+        if let Err(err) = motors.set_stop_action("brake").and_then(|_| motors.stop()) {
+            eprintln!("Unable to stop motors: {:?}", err);
+        }
+        motors.wait_until_not_moving(Some(Duration::from_secs(2)));
+    }
+}